@@ -1,3 +1,13 @@
+use std::env;
+
 fn main() {
-    println!("cargo:rustc-link-search=native=/usr/local/lib");
+    println!("cargo:rerun-if-env-changed=BMAPI_LIB_DIR");
+
+    // The library is loaded at runtime, nothing to link.
+    if env::var_os("CARGO_FEATURE_DYNAMIC").is_some() {
+        return;
+    }
+
+    let dir = env::var("BMAPI_LIB_DIR").unwrap_or_else(|_| "/usr/local/lib".to_string());
+    println!("cargo:rustc-link-search=native={}", dir);
 }
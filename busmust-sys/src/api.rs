@@ -1,21 +1,18 @@
 use types::*;
 use std::os::raw::{c_char, c_int, c_ushort, c_void};
 
-#[cfg(target_arch = "x86_64")]
-#[link(name = "bmapi64")]
-#[allow(non_snake_case)]
-extern "C" {
+bmapi! {
     /// Initialize BM API library, this function shall be called before any other API calls and shall only be called once.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_Init() -> BMStatus;
+    pub fn BM_Init() -> u32;
 
     /// Un-initialize BM API library, this function shall be called after any other API calls and shall only be called once.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_UnInit() -> BMStatus;
+    pub fn BM_UnInit() -> u32;
 
     /// Enumerate all connected Busmust device.
     ///
@@ -25,9 +22,9 @@ extern "C" {
     /// * `n_channels`: Number of device channels available, which is also the number of valid entries in `channel_infos`,
     /// this param must be initialized with the maximum length of the `channel_infos` array when calling this function.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_Enumerate(channel_infos: *mut BMChannelInfo, n_channels: *mut c_int) -> BMStatus;
+    pub fn BM_Enumerate(channel_infos: *mut BMChannelInfo, n_channels: *mut c_int) -> u32;
 
     /// Open the specified CAN device port.
     ///
@@ -51,11 +48,11 @@ extern "C" {
     /// * `rx_filter_list`: CAN acceptance filters option of the opened channel, see [BMRxFilter] for details.
     /// * `rc_filter_count`: Number of acceptance filters, usually there could be up to 2 filters.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
     pub fn BM_OpenEx(handle: *mut *mut c_void, channel_info: *const BMChannelInfo,
                      mode: BMCanMode, term: BMTerminalResistor, bit_rate: *const BMBitrate,
-                     rx_filter_list: *const BMRxFilter, rc_filter_count: c_int) -> BMStatus;
+                     rx_filter_list: *const BMRxFilter, rc_filter_count: c_int) -> u32;
 
     /// Close an opened channel.
     ///
@@ -63,9 +60,9 @@ extern "C" {
     ///
     /// * `channel_handle`: Handle to the channel to be closed.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_Close(channel_handle: *const c_void) -> BMStatus;
+    pub fn BM_Close(channel_handle: *const c_void) -> u32;
 
     /// Reset an opened channel.
     /// The configuration options will not lost when the channel is reset, so [BM_Reset] is basically identical to [BM_Close] and then [BM_OpenEx].
@@ -74,9 +71,9 @@ extern "C" {
     ///
     /// * `channel_handle`: Handle to the channel to be reset.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_Reset(channel_handle: *const c_void) -> BMStatus;
+    pub fn BM_Reset(channel_handle: *const c_void) -> u32;
 
     /// Activate an opened channel, and thus goes on bus for the selected port and channels.
     /// At this point, the user can transmit and receive messages on the bus.
@@ -86,9 +83,9 @@ extern "C" {
     ///
     /// * `channel_handle`: Handle to the channel to be activated.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_Activate(channel_handle: *const c_void) -> BMStatus;
+    pub fn BM_Activate(channel_handle: *const c_void) -> u32;
 
     /// Deactivate an opened channel, and thus the selected channels goes off the bus and stay in BUSOFF state until re-activation.
     /// Any call to [BM_Write] or [BM_Read] will return [BMStatus::BusOff] immediately if the channel is deactivated.
@@ -97,9 +94,9 @@ extern "C" {
     ///
     /// * `channel_handle`: Handle to the channel to be deactivated.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_Deactivate(channel_handle: *const c_void) -> BMStatus;
+    pub fn BM_Deactivate(channel_handle: *const c_void) -> u32;
 
     /// Clear TX&RX message buffer of an opened channel.
     /// This function is available since BM API 1.3, hardware status will not be changed when clearing buffer.
@@ -108,9 +105,9 @@ extern "C" {
     ///
     /// * `channel_handle`: Handle to the channel to be cleared.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_ClearBuffer(channel_handle: *const c_void) -> BMStatus;
+    pub fn BM_ClearBuffer(channel_handle: *const c_void) -> u32;
 
     /// Read a message/event out of the given channel.
     /// This function is non-blocked, and thus will return [BMStatus::ReceiveBufferEmpty] if no message is received.
@@ -121,9 +118,9 @@ extern "C" {
     /// * `channel_handle`: Handle to the channel to read from.
     /// * `data`: A caller-allocated buffer to hold the message/event output, see [BMData] for details.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_Read(channel_handle: *const c_void, data: *const BMData) -> BMStatus;
+    pub fn BM_Read(channel_handle: *const c_void, data: *const BMData) -> u32;
 
 
     /// Read multiple messages/events out of the given channel.
@@ -137,9 +134,9 @@ extern "C" {
     ///              Set any negative number (i.e. -1) to wait infinitely.
     ///              Set 0 if you would like to receive asynchronously: read from BM API internal buffer and return immediately, use [BM_WaitForNotifications] before reading.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_ReadMultiple(channel_handle: *const c_void, data: *const BMData, n_messages: *mut c_int, timeout: c_int) -> BMStatus;
+    pub fn BM_ReadMultiple(channel_handle: *const c_void, data: *const BMData, n_messages: *mut c_int, timeout: c_int) -> u32;
 
     /// Read data block using ISO-TP protocol.
     /// This API enables rapid transmission using ISO-TP without app intervention.
@@ -154,9 +151,9 @@ extern "C" {
     ///              Set 0 if you would like to receive asynchronously: read from BM API internal buffer and return immediately, use [BM_WaitForNotifications] before reading.
     /// * `config`: ISO-TP configuration used by current transfer.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_ReadIsotp(channel_handle: *const c_void, data: *const u8, n_bytes: *mut c_int, timeout: c_int, config: *const BMIsotpConfig) -> BMStatus;
+    pub fn BM_ReadIsotp(channel_handle: *const c_void, data: *mut u8, n_bytes: *mut c_int, timeout: c_int, config: *const BMIsotpConfig) -> u32;
 
     /// Read CAN message out of the given channel.
     /// Note this function is a simple wrapper of [BM_Read], see [BM_Read] for details.
@@ -168,9 +165,9 @@ extern "C" {
     /// * `channel`: The source channel ID from which the message is received, starting from zero, could be NULL if not required.
     /// * `timestamp`: The device local high precision timestamp in microseconds, when the message is physically received on the CAN bus, could be NULL if not required.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_ReadCanMessage(channel_handle: *const c_void, msg: *mut BMCanMessage, channel: *mut c_int, timestamp: *mut c_int) -> BMStatus;
+    pub fn BM_ReadCanMessage(channel_handle: *const c_void, msg: *mut BMCanMessage, channel: *mut c_int, timestamp: *mut c_int) -> u32;
 
     /// Read multiple CAN messages out of the given channel.
     /// This function is non-blocked, and thus will return [BMStatus::ReceiveBufferEmpty] if not all messages are received.
@@ -187,9 +184,9 @@ extern "C" {
     /// * `channels`: The source channel ID from which the message is received, starting from zero, could be NULL if not required.
     /// * `timestamps`: The device local high precision timestamp array in microseconds, when the message is physically transmitted on the CAN bus, could be NULL if not required.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_ReadMultipleCanMessage(channel_handle: *const c_void, msgs: *mut BMCanMessage, n_messages: *mut c_int, timeout: c_int, channels: *mut c_int, timestamps: *mut c_int) -> BMStatus;
+    pub fn BM_ReadMultipleCanMessage(channel_handle: *const c_void, msgs: *mut BMCanMessage, n_messages: *mut c_int, timeout: c_int, channels: *mut c_int, timestamps: *mut c_int) -> u32;
 
     /// Write a message/event to the given channel.
     ///
@@ -202,9 +199,9 @@ extern "C" {
     ///              Set 0 if you would like to transmit asynchronously: put to BM API internal buffer and return immediately, then receive `TXCMPLT` event over [BM_Read] later.
     /// * `timestamp`: The device local high precision timestamp in microseconds, when the message is physically transmitted on the CAN bus, could be NULL if not required.
     ///
-    /// returns: BMStatus
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_Write(channel_handle: *const c_void, data: *const BMData, timeout: c_int, timestamp: *mut c_int) -> BMStatus;
+    pub fn BM_Write(channel_handle: *const c_void, data: *const BMData, timeout: c_int, timestamp: *mut c_int) -> u32;
 
     /// Write multiple messages/events to the given channel.
     /// This function is allowed to be called from multiple threads since BM API 1.3.
@@ -219,9 +216,9 @@ extern "C" {
     ///              Set 0 if you would like to transmit asynchronously: put to BM API internal buffer and return immediately, then receive `TXCMPLT` event over [BM_Read] later.
     /// * `timestamp`: The device local high precision timestamp array in microseconds, when the message is physically transmitted on the CAN bus, could be NULL if not required.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_WriteMultiple(channel_handle: *const c_void, msgs: *const BMData, n_messages: *mut c_int, timeout: c_int, timestamps: *mut c_int) -> BMStatus;
+    pub fn BM_WriteMultiple(channel_handle: *const c_void, msgs: *const BMData, n_messages: *mut c_int, timeout: c_int, timestamps: *mut c_int) -> u32;
 
 
     /// Write data block using ISO-TP protocol.
@@ -236,9 +233,9 @@ extern "C" {
     ///              Note this is only for bus level timeout waiting for CAN ACK, for setting ISO-TP protocol timeouts, see [BMIsotpConfig].
     /// * `config`: ISO-TP configuration used by current transfer.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_WriteIsotp(channel_handle: *const c_void, data: *const u8, n_bytes: c_int, timeout: c_int, config: *const BMIsotpConfig) -> BMStatus;
+    pub fn BM_WriteIsotp(channel_handle: *const c_void, data: *const u8, n_bytes: c_int, timeout: c_int, config: *const BMIsotpConfig) -> u32;
 
     /// Write CAN message to the given channel.
     /// Note this function is a simple wrapper to [BM_Write], see [BM_Write] for details.
@@ -253,9 +250,9 @@ extern "C" {
     ///              Set 0 if you would like to transmit asynchronously: put to BM API internal buffer and return immediately, then receive `TXCMPLT` event over [BM_Read] later.
     /// * `timestamp`: The device local high precision timestamp in microseconds, when the message is physically transmitted on the CAN bus, could be NULL if not required.
     ///
-    /// returns: BMStatus
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_WriteCanMessage(channel_handle: *const c_void, msg: *const BMCanMessage, reserved: c_int, timeout: c_int, timestamp: *mut c_int) -> BMStatus;
+    pub fn BM_WriteCanMessage(channel_handle: *const c_void, msg: *const BMCanMessage, reserved: c_int, timeout: c_int, timestamp: *mut c_int) -> u32;
 
     /// Write multiple CAN messages to the given channel.
     /// This function is allowed to be called from multiple threads since BM API 1.3.
@@ -271,9 +268,9 @@ extern "C" {
     ///              Set 0 if you would like to transmit asynchronously: put to BM API internal buffer and return immediately, then receive `TXCMPLT` event over [BM_Read] later.
     /// * `timestamp`: The device local high precision timestamp array in microseconds, when the message is physically transmitted on the CAN bus, could be NULL if not required.
     ///
-    /// returns: BMStatus
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_WriteMultipleCanMessage(channel_handle: *const c_void, msgs: *const BMCanMessage, n_messages: *mut c_int, reserved: c_int, timeout: c_int, timestamps: *mut c_int) -> BMStatus;
+    pub fn BM_WriteMultipleCanMessage(channel_handle: *const c_void, msgs: *const BMCanMessage, n_messages: *mut c_int, reserved: c_int, timeout: c_int, timestamps: *mut c_int) -> u32;
 
     /// Get current CAN status of the given channel.
     ///
@@ -282,9 +279,9 @@ extern "C" {
     /// * `channel_handle`: Handle to the channel to operate on.
     /// * `status_info`: Detailed information of current CAN status, see [BMCanStatusInfo] for details.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_GetStatus(channel_handle: *const c_void, status_info: *mut BMCanStatusInfo) -> BMStatus;
+    pub fn BM_GetStatus(channel_handle: *const c_void, status_info: *mut BMCanStatusInfo) -> u32;
 
     /// Get current local high precision device timestamp, in microseconds.
     ///
@@ -293,9 +290,9 @@ extern "C" {
     /// * `channel_handle`: Handle to the channel to operate on.
     /// * `timestamp`: Timestamp value.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_GetTimestamp(channel_handle: *const c_void, timestamp: *mut c_int) -> BMStatus;
+    pub fn BM_GetTimestamp(channel_handle: *const c_void, timestamp: *mut c_int) -> u32;
 
     /// Set CAN mode option of the given channel.
     ///
@@ -304,7 +301,7 @@ extern "C" {
     /// * `channel_handle`: Handle to the channel to operate on.
     /// * `mode`: Expected CAN mode, see [BMCanMode] for details.
     ///
-    pub fn BM_SetCanMode(channel_handle: *const c_void, mode: BMCanMode) -> BMStatus;
+    pub fn BM_SetCanMode(channel_handle: *const c_void, mode: BMCanMode) -> u32;
 
     /// Set terminal resistor option of the given channel.
     ///
@@ -313,9 +310,9 @@ extern "C" {
     /// * `channel_handle`: Handle to the channel to operate on.
     /// * `tres`: Expected terminal resistor value, see [BMTerminalResistor] for details.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_SetTerminalRegister(channel_handle: *const c_void, tres: BMTerminalResistor) -> BMStatus;
+    pub fn BM_SetTerminalRegister(channel_handle: *const c_void, tres: BMTerminalResistor) -> u32;
 
     /// Set bitrate option of the given channel.
     ///
@@ -324,9 +321,9 @@ extern "C" {
     /// * `channel_handle`: Handle to the channel to operate on.
     /// * `bitrate`: Expected bitrate, see [BMBitrate] for details.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_SetBitrate(channel_handle: *const c_void, bitrate: *const BMBitrate) -> BMStatus;
+    pub fn BM_SetBitrate(channel_handle: *const c_void, bitrate: *const BMBitrate) -> u32;
 
     /// Set TX tasks option of the given channel.
     ///
@@ -336,9 +333,9 @@ extern "C" {
     /// * `tx_tasks`: An array of TX task information, see [BMTxTask] for details.
     /// * `n_tx_tasks`: Number of valid TX tasks in the array.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_SetTxTasks(channel_handle: *const c_void, tx_tasks: *const BMTxTask, n_tx_tasks: c_int) -> BMStatus;
+    pub fn BM_SetTxTasks(channel_handle: *const c_void, tx_tasks: *const BMTxTask, n_tx_tasks: c_int) -> u32;
 
     /// Set RX filters option of the given channel.
     ///
@@ -348,9 +345,9 @@ extern "C" {
     /// * `rx_filters`: An array of RX filter information, see [BMRxFilter] for details.
     /// * `n_rx_filters`: Number of valid RX filters in the array.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_SetRxFilters(channel_handle: *const c_void, rx_filters: *const BMRxFilter, n_rx_filters: c_int) -> BMStatus;
+    pub fn BM_SetRxFilters(channel_handle: *const c_void, rx_filters: *const BMRxFilter, n_rx_filters: c_int) -> u32;
 
     /// Get the platform/OS independent notification handle for the given channel, so that the application could wait for notifications later.
     ///
@@ -359,9 +356,9 @@ extern "C" {
     /// * `channel_handle`: Handle to the channel that owns the notification handle.
     /// * `notification`: The platform/OS independent notification handle.
     ///
-    /// returns: [BMStatus]
+    /// returns: Raw [BMStatus] code, see [BMStatus::from_raw].
     ///
-    pub fn BM_GetNotification(channel_handle: *const c_void, notification: *mut *mut c_void) -> BMStatus;
+    pub fn BM_GetNotification(channel_handle: *const c_void, notification: *mut *mut c_void) -> u32;
 
 
     /// A platform/OS independent implementation to wait for single/multiple notification handles.
@@ -386,7 +383,7 @@ extern "C" {
     /// * `length`: Number in bytes of the string buffer.
     /// * `reserved`: Reserved.
     ///
-    pub fn BM_GetErrorText(status: BMStatus, buffer: *mut c_char, length: usize, reserved: c_ushort);
+    pub fn BM_GetErrorText(status: u32, buffer: *mut c_char, length: usize, reserved: c_ushort);
 
     /// Translate data (i.e. CAN message) to string, this is a helper function to ease application programming.
     ///
@@ -409,7 +406,8 @@ extern "C" {
 
     /// Get library log level.
     ///
-    /// returns: Current log level, all messages equal to or less than this level are currently printed on debug console.
+    /// returns: Raw current log level, all messages equal to or less than this level are currently printed on debug console,
+    /// see [BMLogLevel::from_raw].
     ///
-    pub fn BM_GetLogLevel() -> BMLogLevel;
+    pub fn BM_GetLogLevel() -> u32;
 }
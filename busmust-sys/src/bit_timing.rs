@@ -0,0 +1,232 @@
+use std::cmp::Ordering;
+
+/// Range of the baud rate prescaler encoded in BTR0.
+const BRP_RANGE: (u8, u8) = (1, 64);
+/// Range of the time segment before the sample point (excluding the sync segment) encoded in BTR1.
+const TSEG1_RANGE: (u8, u8) = (1, 16);
+/// Range of the time segment after the sample point encoded in BTR1.
+const TSEG2_RANGE: (u8, u8) = (1, 8);
+/// Range of the synchronization jump width encoded in BTR0.
+const SJW_RANGE: (u8, u8) = (1, 4);
+
+/// Bit timing of a CAN controller, encoded into the BTR register fields of [super::BMBitrate].
+///
+/// The registers use the SJA1000 layout, the time quantum is `2 * brp / clock_freq`, i.e. 125 ns
+/// for a 16 MHz controller clock and `brp` 1. A bit is made of a one quantum sync segment, `tseg1` and `tseg2`
+/// quanta, and sampled after `1 + tseg1` quanta.
+///
+/// # Examples
+///
+/// ```
+/// use busmust_sys::BMBitTiming;
+///
+/// let timing = BMBitTiming::solve(16, 500, 87, 1)[0].timing;
+/// assert_eq!((timing.btr0(), timing.btr1()), (0x00, 0x1C));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BMBitTiming {
+    /// Baud rate prescaler (1-64)
+    pub brp: u8,
+    /// Time segment before the sample point, excluding the sync segment (1-16)
+    pub tseg1: u8,
+    /// Time segment after the sample point (1-8)
+    pub tseg2: u8,
+    /// Synchronization jump width (1-4), at most `tseg2`
+    pub sjw: u8
+}
+
+/// Bit timing found by [BMBitTiming::solve] together with its deviation from the requested values.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BMBitTimingSolution {
+    pub timing: BMBitTiming,
+    /// Relative deviation from the requested bitrate, i.e. `0.01` for 1%
+    pub bitrate_error: f64,
+    /// Absolute deviation from the requested sample point in percent
+    pub sample_point_error: f64
+}
+
+impl BMBitTiming {
+    /// Check whether all fields are within the ranges supported by the BTR registers.
+    pub fn is_valid(&self) -> bool {
+        let within = |value: u8, range: (u8, u8)| value >= range.0 && value <= range.1;
+
+        within(self.brp, BRP_RANGE) &&
+            within(self.tseg1, TSEG1_RANGE) &&
+            within(self.tseg2, TSEG2_RANGE) &&
+            within(self.sjw, SJW_RANGE) &&
+            self.sjw <= self.tseg2
+    }
+
+    /// Number of time quanta of a bit.
+    pub fn quanta(&self) -> u32 {
+        1 + self.tseg1 as u32 + self.tseg2 as u32
+    }
+
+    /// Get the bitrate in kbps using the given controller clock in MHz.
+    pub fn bitrate(&self, clock_freq: u8) -> f64 {
+        clock_freq as f64 * 1000.0 / (2.0 * self.brp as f64 * self.quanta() as f64)
+    }
+
+    /// Get the sample point in percent of the bit time.
+    pub fn sample_point(&self) -> f64 {
+        (1 + self.tseg1 as u32) as f64 * 100.0 / self.quanta() as f64
+    }
+
+    /// Encode the prescaler and synchronization jump width into the BTR0 register value.
+    ///
+    /// Fields outside the ranges checked by [BMBitTiming::is_valid] are truncated to their register bits.
+    pub fn btr0(&self) -> u8 {
+        (self.sjw.wrapping_sub(1) & 0x03) << 6 | (self.brp.wrapping_sub(1) & 0x3F)
+    }
+
+    /// Encode the time segments into the BTR1 register value, using single sampling.
+    ///
+    /// Fields outside the ranges checked by [BMBitTiming::is_valid] are truncated to their register bits.
+    pub fn btr1(&self) -> u8 {
+        (self.tseg2.wrapping_sub(1) & 0x07) << 4 | (self.tseg1.wrapping_sub(1) & 0x0F)
+    }
+
+    /// Decode BTR0 and BTR1 register values.
+    pub fn from_btr(btr0: u8, btr1: u8) -> BMBitTiming {
+        BMBitTiming {
+            brp: (btr0 & 0x3F) + 1,
+            tseg1: (btr1 & 0x0F) + 1,
+            tseg2: ((btr1 >> 4) & 0x07) + 1,
+            sjw: (btr0 >> 6) + 1
+        }
+    }
+
+    /// Find all bit timings reaching the given bitrate with the given controller clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `clock_freq`: CAN controller clock in MHz.
+    /// * `bitrate`: Target bitrate in kbps.
+    /// * `sample_pos`: Target sample point in percent.
+    /// * `sjw`: Synchronization jump width, timings with `tseg2` below it are skipped.
+    ///
+    /// returns: Timings within 1% of the target bitrate, best first: ordered by bitrate error,
+    /// sample point error and then by the number of quanta per bit (more is better).
+    pub fn solve(clock_freq: u8, bitrate: u16, sample_pos: u8, sjw: u8) -> Vec<BMBitTimingSolution> {
+        let mut solutions = Vec::new();
+        if bitrate == 0 {
+            return solutions;
+        }
+
+        for brp in BRP_RANGE.0..=BRP_RANGE.1 {
+            for tseg1 in TSEG1_RANGE.0..=TSEG1_RANGE.1 {
+                for tseg2 in TSEG2_RANGE.0..=TSEG2_RANGE.1 {
+                    let timing = BMBitTiming { brp, tseg1, tseg2, sjw };
+                    if !timing.is_valid() {
+                        continue;
+                    }
+
+                    let bitrate_error = (timing.bitrate(clock_freq) - bitrate as f64).abs() / bitrate as f64;
+                    if bitrate_error > MAX_BITRATE_ERROR {
+                        continue;
+                    }
+
+                    solutions.push(BMBitTimingSolution {
+                        timing,
+                        bitrate_error,
+                        sample_point_error: (timing.sample_point() - sample_pos as f64).abs()
+                    });
+                }
+            }
+        }
+
+        solutions.sort_by(|a, b| {
+            compare(a.bitrate_error, b.bitrate_error)
+                .then(compare(a.sample_point_error, b.sample_point_error))
+                .then(b.timing.quanta().cmp(&a.timing.quanta()))
+        });
+        solutions
+    }
+}
+
+/// Maximum relative bitrate deviation of a solution returned by [BMBitTiming::solve].
+const MAX_BITRATE_ERROR: f64 = 0.01;
+
+/// Compare errors, treating differences caused by rounding as equal.
+fn compare(a: f64, b: f64) -> Ordering {
+    if (a - b).abs() < 1e-9 {
+        Ordering::Equal
+    } else {
+        a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solve_common_bitrates() {
+        // Clock in MHz, bitrate in kbps, sample point in percent, BTR0, BTR1.
+        let table: [(u8, u16, u8, u8, u8); 16] = [
+            (16, 125, 87, 0x03, 0x1C),
+            (16, 250, 87, 0x01, 0x1C),
+            (16, 500, 87, 0x00, 0x1C),
+            (16, 1000, 75, 0x00, 0x14),
+            (20, 125, 87, 0x04, 0x1C),
+            (20, 250, 87, 0x04, 0x05),
+            (20, 500, 87, 0x00, 0x2F),
+            (20, 1000, 75, 0x00, 0x25),
+            (40, 125, 87, 0x09, 0x1C),
+            (40, 250, 87, 0x04, 0x1C),
+            (40, 500, 87, 0x04, 0x05),
+            (40, 1000, 75, 0x00, 0x4D),
+            (80, 125, 87, 0x13, 0x1C),
+            (80, 250, 87, 0x09, 0x1C),
+            (80, 500, 87, 0x04, 0x1C),
+            (80, 1000, 75, 0x01, 0x4D)
+        ];
+
+        for &(clock_freq, bitrate, sample_pos, btr0, btr1) in table.iter() {
+            let solution = BMBitTiming::solve(clock_freq, bitrate, sample_pos, 1)[0];
+            let timing = solution.timing;
+
+            assert_eq!((timing.btr0(), timing.btr1()), (btr0, btr1), "{} MHz {} kbps", clock_freq, bitrate);
+            assert_eq!(solution.bitrate_error, 0.0);
+            assert_eq!(timing.bitrate(clock_freq), bitrate as f64);
+            assert_eq!(BMBitTiming::from_btr(timing.btr0(), timing.btr1()), timing);
+        }
+    }
+
+    #[test]
+    fn btr_round_trip() {
+        for brp in BRP_RANGE.0..=BRP_RANGE.1 {
+            for tseg1 in TSEG1_RANGE.0..=TSEG1_RANGE.1 {
+                for tseg2 in TSEG2_RANGE.0..=TSEG2_RANGE.1 {
+                    for sjw in SJW_RANGE.0..=SJW_RANGE.1.min(tseg2) {
+                        let timing = BMBitTiming { brp, tseg1, tseg2, sjw };
+                        assert!(timing.is_valid());
+                        assert_eq!(BMBitTiming::from_btr(timing.btr0(), timing.btr1()), timing);
+                    }
+                }
+            }
+        }
+
+        // The sampling mode bit of BTR1 is not kept.
+        for btr0 in 0..=u8::MAX {
+            for btr1 in 0..=u8::MAX {
+                let timing = BMBitTiming::from_btr(btr0, btr1);
+                assert_eq!((timing.btr0(), timing.btr1()), (btr0, btr1 & 0x7F));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_fields_are_truncated() {
+        let timing = BMBitTiming { brp: 0, tseg1: 0, tseg2: 0, sjw: 0 };
+        assert!(!timing.is_valid());
+        assert_eq!((timing.btr0(), timing.btr1()), (0xFF, 0x7F));
+
+        let timing = BMBitTiming { brp: 65, tseg1: 17, tseg2: 9, sjw: 5 };
+        assert!(!timing.is_valid());
+        assert_eq!((timing.btr0(), timing.btr1()), (0x00, 0x00));
+
+        assert!(!BMBitTiming { brp: 1, tseg1: 13, tseg2: 2, sjw: 3 }.is_valid());
+        assert!(BMBitTiming { brp: 1, tseg1: 13, tseg2: 2, sjw: 2 }.is_valid());
+    }
+}
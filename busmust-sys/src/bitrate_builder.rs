@@ -1,31 +1,126 @@
-use BMBitrate;
+use std::fmt;
+use ::{BMBitTiming, BMBitrate, BMCanMode};
+
+/// Range of supported nominal bitrates in kbps.
+const NOMINAL_BITRATE_RANGE: (u16, u16) = (10, 1000);
+/// Range of supported data bitrates in kbps.
+const DATA_BITRATE_RANGE: (u16, u16) = (10, 8000);
+/// Range of supported sample positions in percent.
+const SAMPLE_POS_RANGE: (u8, u8) = (50, 95);
 
 impl BMBitrate {
+    /// Classic CAN at 125 kbps, sampled at 87.5% (rounded down) as recommended by CiA 301.
+    pub const CAN_125K: BMBitrate = BMBitrate::preset(125, 87, 125, 87);
+    /// Classic CAN at 250 kbps, sampled at 87.5% (rounded down) as recommended by CiA 301.
+    pub const CAN_250K: BMBitrate = BMBitrate::preset(250, 87, 250, 87);
+    /// Classic CAN at 500 kbps, sampled at 87.5% (rounded down) as recommended by CiA 301.
+    pub const CAN_500K: BMBitrate = BMBitrate::preset(500, 87, 500, 87);
+    /// Classic CAN at 1 Mbps, sampled at 75% as recommended by CiA 301.
+    pub const CAN_1M: BMBitrate = BMBitrate::preset(1000, 75, 1000, 75);
+    /// CAN-FD at 500 kbps nominal and 2 Mbps data bitrate, both sampled at 80% as recommended by CiA 601-3.
+    pub const FD_500K_2M: BMBitrate = BMBitrate::preset(500, 80, 2000, 80);
+    /// CAN-FD at 1 Mbps nominal and 5 Mbps data bitrate, sampled at 80% and 75% as recommended by CiA 601-3.
+    pub const FD_1M_5M: BMBitrate = BMBitrate::preset(1000, 80, 5000, 75);
+
     pub fn builder() -> BMBitrateBuilder {
         BMBitrateBuilder::default()
     }
+
+    const fn preset(n_bitrate: u16, n_sample_pos: u8, d_bitrate: u16, d_sample_pos: u8) -> BMBitrate {
+        BMBitrate {
+            n_bitrate,
+            d_bitrate,
+            n_sample_pos,
+            d_sample_pos,
+            clock_freq: 0,
+            reserved: 0,
+            n_btr0: 0,
+            n_btr1: 0,
+            d_btr0: 0,
+            d_btr1: 0
+        }
+    }
 }
 
-/// Builder for [BMBitrate] structure.
-#[derive(Default)]
+impl Default for BMBitrate {
+    /// 500 kbps nominal and 2 Mbps data bitrate, both sampled at 75%.
+    fn default() -> BMBitrate {
+        BMBitrate::preset(500, 75, 2000, 75)
+    }
+}
+
+/// Invalid configuration found by [BMBitrateBuilder::build].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BMBitrateError {
+    /// Nominal bitrate in kbps is not supported
+    UnsupportedBitrate(u16),
+    /// Data bitrate in kbps is not supported
+    UnsupportedDataBitrate(u16),
+    /// Data bitrate is lower than the nominal bitrate
+    DataBitrateBelowNominal,
+    /// Nominal sample position in percent is out of range
+    InvalidSamplePos(u8),
+    /// Data sample position in percent is out of range
+    InvalidDataSamplePos(u8),
+    /// A data bitrate different from the nominal one is set in [BMCanMode::Classic]
+    FdInClassicMode,
+    /// A bit timing is out of range, or no controller clock is set
+    InvalidTiming
+}
+
+impl fmt::Display for BMBitrateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BMBitrateError::UnsupportedBitrate(bitrate) => write!(f, "unsupported nominal bitrate {} kbps", bitrate),
+            BMBitrateError::UnsupportedDataBitrate(bitrate) => write!(f, "unsupported data bitrate {} kbps", bitrate),
+            BMBitrateError::DataBitrateBelowNominal => write!(f, "data bitrate is lower than nominal bitrate"),
+            BMBitrateError::InvalidSamplePos(pos) => write!(f, "invalid nominal sample position {}%", pos),
+            BMBitrateError::InvalidDataSamplePos(pos) => write!(f, "invalid data sample position {}%", pos),
+            BMBitrateError::FdInClassicMode => write!(f, "data bitrate set in classic CAN mode"),
+            BMBitrateError::InvalidTiming => write!(f, "invalid bit timing")
+        }
+    }
+}
+
+impl std::error::Error for BMBitrateError {}
+
+/// Builder for [BMBitrate] structure, starting from 500 kbps nominal and 2 Mbps data bitrate, both sampled at 75%.
 pub struct BMBitrateBuilder {
     /// Nominal bitrate in kbps, default as 500, note this is the only valid bitrate in CAN CLASSIC mode.
     n_bitrate: u16,
-    /// Data bitrate in kbps, default as 500, note this is ignored in CAN CLASSIC mode.
-    d_bitrate: u16,
+    /// Data bitrate in kbps, default as 2000 (same as nominal in CAN CLASSIC mode), note this is ignored in CAN CLASSIC mode.
+    d_bitrate: Option<u16>,
     /// Nominal sample position (percentage, 0-100, default as 75
     n_sample_pos: u8,
     /// Data sample position (percentage, 0-100, default as 75
-    d_sample_pos: u8
+    d_sample_pos: u8,
+    /// CAN mode the bitrate is used in
+    mode: BMCanMode,
+    /// CAN controller clock in MHz used by the BTR register values
+    clock_freq: u8,
+    /// Nominal bit timing overriding the nominal bitrate
+    n_timing: Option<BMBitTiming>,
+    /// Data bit timing overriding the data bitrate
+    d_timing: Option<BMBitTiming>
+}
+
+impl Default for BMBitrateBuilder {
+    fn default() -> BMBitrateBuilder {
+        BMBitrateBuilder::new()
+    }
 }
 
 impl BMBitrateBuilder {
     pub fn new() -> BMBitrateBuilder {
         BMBitrateBuilder {
             n_bitrate: 500,
-            d_bitrate: 2000,
+            d_bitrate: None,
             n_sample_pos: 75,
-            d_sample_pos: 75
+            d_sample_pos: 75,
+            mode: BMCanMode::Normal,
+            clock_freq: 0,
+            n_timing: None,
+            d_timing: None
         }
     }
 
@@ -35,7 +130,7 @@ impl BMBitrateBuilder {
     }
 
     pub fn data_bitrate(mut self, d_bitrate: u16) -> BMBitrateBuilder {
-        self.d_bitrate = d_bitrate;
+        self.d_bitrate = Some(d_bitrate);
         self
     }
 
@@ -49,18 +144,237 @@ impl BMBitrateBuilder {
         self
     }
 
-    pub fn build(self) -> BMBitrate {
-        BMBitrate {
+    /// Set the CAN mode the bitrate is used in, the data bitrate defaults to the nominal one in [BMCanMode::Classic].
+    pub fn mode(mut self, mode: BMCanMode) -> BMBitrateBuilder {
+        self.mode = mode;
+        self
+    }
+
+    /// Set the CAN controller clock in MHz the bit timings are calculated for.
+    pub fn clock_freq(mut self, clock_freq: u8) -> BMBitrateBuilder {
+        self.clock_freq = clock_freq;
+        self
+    }
+
+    /// Set the nominal bit timing encoded into the BTR registers, overriding the nominal bitrate.
+    pub fn timing(mut self, timing: BMBitTiming) -> BMBitrateBuilder {
+        self.n_timing = Some(timing);
+        self
+    }
+
+    /// Set the data bit timing encoded into the BTR registers, overriding the data bitrate.
+    pub fn data_timing(mut self, timing: BMBitTiming) -> BMBitrateBuilder {
+        self.d_timing = Some(timing);
+        self
+    }
+
+    /// Solve the nominal and data bit timings for the configured bitrates, sample positions and clock,
+    /// using the best solution of [BMBitTiming::solve] with the given synchronization jump width.
+    /// Bit timings without any solution are left unset.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use busmust_sys::BMBitrate;
+    ///
+    /// let bitrate = BMBitrate::builder()
+    ///     .bitrate(500)
+    ///     .sample_pos(87)
+    ///     .data_bitrate(2000)
+    ///     .clock_freq(16)
+    ///     .solve_timing(1)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!((bitrate.n_btr0, bitrate.n_btr1), (0x00, 0x1C));
+    /// ```
+    pub fn solve_timing(mut self, sjw: u8) -> BMBitrateBuilder {
+        let clock_freq = self.clock_freq;
+        let best = |bitrate, sample_pos| {
+            BMBitTiming::solve(clock_freq, bitrate, sample_pos, sjw).first().map(|s| s.timing)
+        };
+
+        self.n_timing = best(self.n_bitrate, self.n_sample_pos);
+        self.d_timing = best(self.resolved_data_bitrate(), self.d_sample_pos);
+        self
+    }
+
+    /// Validate the configuration and build the bitrate.
+    ///
+    /// returns: [BMBitrate], or [BMBitrateError] if a bitrate or sample position is out of the supported range,
+    /// the data bitrate is below the nominal one or set in [BMCanMode::Classic], or a bit timing is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use busmust_sys::{BMBitrate, BMBitrateError, BMCanMode};
+    ///
+    /// let bitrate = BMBitrate::builder().mode(BMCanMode::Classic).bitrate(250).build().unwrap();
+    /// assert_eq!(bitrate.d_bitrate, 250);
+    ///
+    /// let error = BMBitrate::builder().bitrate(1000).data_bitrate(500).build();
+    /// assert_eq!(error.unwrap_err(), BMBitrateError::DataBitrateBelowNominal);
+    /// ```
+    pub fn build(self) -> Result<BMBitrate, BMBitrateError> {
+        let within = |value, range: (u16, u16)| value >= range.0 && value <= range.1;
+        let d_bitrate = self.resolved_data_bitrate();
+
+        if !within(self.n_bitrate, NOMINAL_BITRATE_RANGE) {
+            return Err(BMBitrateError::UnsupportedBitrate(self.n_bitrate));
+        }
+        if !within(d_bitrate, DATA_BITRATE_RANGE) {
+            return Err(BMBitrateError::UnsupportedDataBitrate(d_bitrate));
+        }
+        if matches!(self.mode, BMCanMode::Classic) && d_bitrate != self.n_bitrate {
+            return Err(BMBitrateError::FdInClassicMode);
+        }
+        if d_bitrate < self.n_bitrate {
+            return Err(BMBitrateError::DataBitrateBelowNominal);
+        }
+        if !within(self.n_sample_pos as u16, (SAMPLE_POS_RANGE.0 as u16, SAMPLE_POS_RANGE.1 as u16)) {
+            return Err(BMBitrateError::InvalidSamplePos(self.n_sample_pos));
+        }
+        if !within(self.d_sample_pos as u16, (SAMPLE_POS_RANGE.0 as u16, SAMPLE_POS_RANGE.1 as u16)) {
+            return Err(BMBitrateError::InvalidDataSamplePos(self.d_sample_pos));
+        }
+
+        let timings = [self.n_timing, self.d_timing];
+        if timings.iter().flatten().any(|timing| !timing.is_valid() || self.clock_freq == 0) {
+            return Err(BMBitrateError::InvalidTiming);
+        }
+
+        let btr = |timing: Option<BMBitTiming>| timing.map_or((0, 0), |t| (t.btr0(), t.btr1()));
+        let (n_btr0, n_btr1) = btr(self.n_timing);
+        let (d_btr0, d_btr1) = btr(self.d_timing);
+
+        Ok(BMBitrate {
             n_bitrate: self.n_bitrate,
-            d_bitrate: self.d_bitrate,
+            d_bitrate,
             n_sample_pos: self.n_sample_pos,
             d_sample_pos: self.d_sample_pos,
-            clock_freq: 0,
+            clock_freq: self.clock_freq,
             reserved: 0,
-            n_btr0: 0,
-            n_btr1: 0,
-            d_btr0: 0,
-            d_btr1: 0
+            n_btr0,
+            n_btr1,
+            d_btr0,
+            d_btr1
+        })
+    }
+
+    /// Get the data bitrate, defaulting to 2000 kbps or the nominal bitrate in [BMCanMode::Classic].
+    fn resolved_data_bitrate(&self) -> u16 {
+        match self.d_bitrate {
+            Some(d_bitrate) => d_bitrate,
+            None if matches!(self.mode, BMCanMode::Classic) => self.n_bitrate,
+            None => 2000
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builder with all fields of the given bitrate and mode.
+    fn builder_from(bitrate: &BMBitrate, mode: BMCanMode) -> BMBitrateBuilder {
+        BMBitrate::builder()
+            .mode(mode)
+            .bitrate(bitrate.n_bitrate)
+            .sample_pos(bitrate.n_sample_pos)
+            .data_bitrate(bitrate.d_bitrate)
+            .data_sample_pos(bitrate.d_sample_pos)
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        let classic = [BMBitrate::CAN_125K, BMBitrate::CAN_250K, BMBitrate::CAN_500K, BMBitrate::CAN_1M];
+        for preset in classic.iter() {
+            let bitrate = builder_from(preset, BMCanMode::Classic).build().unwrap();
+            assert_eq!((bitrate.n_bitrate, bitrate.d_bitrate), (preset.n_bitrate, preset.d_bitrate));
         }
+
+        let fd = [BMBitrate::FD_500K_2M, BMBitrate::FD_1M_5M, BMBitrate::default()];
+        for preset in fd.iter() {
+            let bitrate = builder_from(preset, BMCanMode::Normal).build().unwrap();
+            assert_eq!((bitrate.n_bitrate, bitrate.d_bitrate), (preset.n_bitrate, preset.d_bitrate));
+            assert_eq!(builder_from(preset, BMCanMode::Classic).build().unwrap_err(), BMBitrateError::FdInClassicMode);
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let bitrate = BMBitrate::builder().build().unwrap();
+        let default = BMBitrate::default();
+        assert_eq!((bitrate.n_bitrate, bitrate.n_sample_pos), (default.n_bitrate, default.n_sample_pos));
+        assert_eq!((bitrate.d_bitrate, bitrate.d_sample_pos), (default.d_bitrate, default.d_sample_pos));
+
+        let bitrate = BMBitrate::builder().mode(BMCanMode::Classic).bitrate(125).build().unwrap();
+        assert_eq!(bitrate.d_bitrate, 125);
+    }
+
+    #[test]
+    fn unsupported_bitrates() {
+        for &n_bitrate in [0, 9, 1001, u16::MAX].iter() {
+            let error = BMBitrate::builder().bitrate(n_bitrate).build().unwrap_err();
+            assert_eq!(error, BMBitrateError::UnsupportedBitrate(n_bitrate));
+        }
+        for &d_bitrate in [0, 9, 8001].iter() {
+            let error = BMBitrate::builder().bitrate(10).data_bitrate(d_bitrate).build().unwrap_err();
+            assert_eq!(error, BMBitrateError::UnsupportedDataBitrate(d_bitrate));
+        }
+
+        assert!(BMBitrate::builder().bitrate(10).data_bitrate(10).build().is_ok());
+        assert!(BMBitrate::builder().bitrate(1000).data_bitrate(8000).build().is_ok());
+    }
+
+    #[test]
+    fn data_bitrate_below_nominal() {
+        let error = BMBitrate::builder().bitrate(500).data_bitrate(250).build().unwrap_err();
+        assert_eq!(error, BMBitrateError::DataBitrateBelowNominal);
+        assert!(BMBitrate::builder().bitrate(500).data_bitrate(500).build().is_ok());
+    }
+
+    #[test]
+    fn invalid_sample_positions() {
+        for &pos in [0, 49, 96, 100].iter() {
+            let error = BMBitrate::builder().sample_pos(pos).build().unwrap_err();
+            assert_eq!(error, BMBitrateError::InvalidSamplePos(pos));
+
+            let error = BMBitrate::builder().data_sample_pos(pos).build().unwrap_err();
+            assert_eq!(error, BMBitrateError::InvalidDataSamplePos(pos));
+        }
+
+        assert!(BMBitrate::builder().sample_pos(50).data_sample_pos(95).build().is_ok());
+    }
+
+    #[test]
+    fn fd_in_classic_mode() {
+        let error = BMBitrate::builder().mode(BMCanMode::Classic).bitrate(500).data_bitrate(2000).build().unwrap_err();
+        assert_eq!(error, BMBitrateError::FdInClassicMode);
+        assert!(BMBitrate::builder().mode(BMCanMode::Classic).bitrate(500).data_bitrate(500).build().is_ok());
+    }
+
+    #[test]
+    fn invalid_timings() {
+        let valid = BMBitTiming { brp: 1, tseg1: 13, tseg2: 2, sjw: 1 };
+        let invalid = BMBitTiming { brp: 0, ..valid };
+
+        let error = BMBitrate::builder().clock_freq(16).timing(invalid).build().unwrap_err();
+        assert_eq!(error, BMBitrateError::InvalidTiming);
+        let error = BMBitrate::builder().clock_freq(16).data_timing(invalid).build().unwrap_err();
+        assert_eq!(error, BMBitrateError::InvalidTiming);
+
+        // Valid timing without a controller clock.
+        let error = BMBitrate::builder().timing(valid).build().unwrap_err();
+        assert_eq!(error, BMBitrateError::InvalidTiming);
+
+        let bitrate = BMBitrate::builder().clock_freq(16).timing(valid).build().unwrap();
+        assert_eq!((bitrate.n_btr0, bitrate.n_btr1), (valid.btr0(), valid.btr1()));
+        assert_eq!((bitrate.d_btr0, bitrate.d_btr1), (0, 0));
+    }
+
+    #[test]
+    fn errors_are_displayed() {
+        assert_eq!(BMBitrateError::UnsupportedBitrate(5).to_string(), "unsupported nominal bitrate 5 kbps");
+        assert_eq!(BMBitrateError::InvalidDataSamplePos(99).to_string(), "invalid data sample position 99%");
     }
 }
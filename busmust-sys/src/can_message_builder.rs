@@ -1,5 +1,5 @@
 use std::convert::TryFrom;
-use ::{BMCanMessage, BMMessageId};
+use ::{len_to_dlc, BMCanId, BMCanMessage, BMMessageId};
 use ::{BMMessageCtrl, BMTxMessageCtrl};
 
 impl BMCanMessage {
@@ -23,19 +23,7 @@ pub struct BMCanMessageBuilder {
 
 impl BMCanMessageBuilder {
     pub fn new(payload: Vec<u8>) -> BMCanMessageBuilder {
-        let mut vec = Vec::from(payload);
-        vec.resize(64, 0);
-
-        BMCanMessageBuilder {
-            sid: None,
-            eid: None,
-            dlc: None,
-            ide: None,
-            rtr: None,
-            brs: None,
-            fdf: None,
-            payload: vec
-        }
+        BMCanMessageBuilder::default().payload(payload)
     }
 
     pub fn sid(mut self, value: u16) -> BMCanMessageBuilder {
@@ -48,6 +36,32 @@ impl BMCanMessageBuilder {
         self
     }
 
+    /// Set a 29-bit extended identifier, splitting it into SID (11 most significant bits) and EID
+    /// and marking the message as extended.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use busmust_sys::{BMCanId, BMCanMessage};
+    ///
+    /// let message = BMCanMessage::builder().extended_id(0x18DAF110).build();
+    /// assert_eq!(message.sid(), 0x636);
+    /// assert_eq!(message.eid(), 0x2F110);
+    /// assert_eq!(message.id(), BMCanId::Extended(0x18DAF110));
+    /// ```
+    pub fn extended_id(self, value: u32) -> BMCanMessageBuilder {
+        self.id(BMCanId::Extended(value))
+    }
+
+    /// Set a standard or extended identifier, marking the message as extended accordingly.
+    pub fn id(mut self, value: BMCanId) -> BMCanMessageBuilder {
+        let mid = BMMessageId::from(value);
+        self.sid = Some(mid.sid());
+        self.eid = Some(mid.eid());
+        self.ide = Some(value.is_extended());
+        self
+    }
+
     pub fn dlc(mut self, value: u8) -> BMCanMessageBuilder {
         self.dlc = Some(value);
         self
@@ -73,17 +87,18 @@ impl BMCanMessageBuilder {
         self
     }
 
+    /// Set the payload and the smallest DLC able to hold it, payloads of CAN FD lengths which cannot be
+    /// encoded exactly (i.e. 9 bytes) are padded with zeros and payloads above 64 bytes are truncated.
     pub fn payload(mut self, value: Vec<u8>) -> BMCanMessageBuilder {
-        self.dlc = Some(value.len() as u8);
+        self.dlc = Some(len_to_dlc(value.len()).unwrap_or(0x0F));
 
-        let mut vec = Vec::from(value);
-        vec.resize(64, 0);
-
-        self.payload = vec;
+        self.payload = value;
         self
     }
 
-    pub fn build(self) -> BMCanMessage {
+    pub fn build(mut self) -> BMCanMessage {
+        self.payload.resize(64, 0);
+
         BMCanMessage {
             mid: BMMessageId::new()
                 .with_sid(self.sid.unwrap_or_default())
@@ -0,0 +1,234 @@
+use std::{fmt, mem, ptr};
+use ::{BMCanMessage, BMData, BMDataHeader, BMDataType};
+use ::BM_DATA_PAYLOAD_MAX_SIZE;
+
+/// Length of the ID and control fields of [BMCanMessage], which precede its payload.
+const CAN_MESSAGE_HEADER_SIZE: usize = 8;
+
+/// Payload of a [BMData], see [BMData::decode].
+#[derive(Debug, Clone)]
+pub enum BMPayload {
+    /// CAN or CAN-FD message
+    Can(BMCanMessage),
+    /// `TXCMPLT` event, the given message has been transmitted on the bus
+    TxComplete { message: BMCanMessage },
+    /// Raw LIN message
+    Lin(Vec<u8>),
+    /// Data type not decoded by this crate (i.e. [BMDataType::FlexRay]), see [BMDataType]
+    Unknown { kind: u8, bytes: Vec<u8> }
+}
+
+/// Decoded [BMData], see [BMData::decode] and [BMDecodedData::encode].
+#[derive(Debug, Clone)]
+pub struct BMDecodedData {
+    /// Source channel of received data, or destination channel of data to transmit
+    pub channel: u8,
+    /// 32-bit device local high precision timestamp in microseconds
+    pub timestamp: u32,
+    /// Decoded payload
+    pub payload: BMPayload
+}
+
+/// The length of a [BMData] is out of range, or too short for its data type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BMDecodeError {
+    /// Data type, see [BMDataType]
+    pub kind: u8,
+    /// Invalid payload length
+    pub length: u16
+}
+
+impl fmt::Display for BMDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid length {} of data type {}", self.length, self.kind)
+    }
+}
+
+impl std::error::Error for BMDecodeError {}
+
+impl BMData {
+    /// Decode the payload according to the data type found in the header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use busmust_sys::{BMCanMessage, BMData, BMPayload};
+    ///
+    /// let message = BMCanMessage::builder().sid(0x123).payload(vec![1, 2, 3]).build();
+    /// let data = BMData::builder().can_message(message).build();
+    ///
+    /// match data.decode().unwrap().payload {
+    ///     BMPayload::Can(message) => println!("received {:X}", message.id().raw()),
+    ///     BMPayload::TxComplete { message } => println!("transmitted {:X}", message.id().raw()),
+    ///     _ => {}
+    /// }
+    /// ```
+    pub fn decode(&self) -> Result<BMDecodedData, BMDecodeError> {
+        let kind = self.header.kind();
+        let error = BMDecodeError { kind, length: self.length };
+
+        let length = self.length as usize;
+        if length > BM_DATA_PAYLOAD_MAX_SIZE {
+            return Err(error);
+        }
+
+        let bytes = &self.payload[..length];
+        let payload = if kind == BMDataType::Can as u8 {
+            BMPayload::Can(can_message(bytes).ok_or(error)?)
+        } else if kind == BMDataType::Ack as u8 | BMDataType::Can as u8 {
+            BMPayload::TxComplete { message: can_message(bytes).ok_or(error)? }
+        } else if kind == BMDataType::Lin as u8 {
+            BMPayload::Lin(bytes.to_vec())
+        } else {
+            BMPayload::Unknown { kind, bytes: bytes.to_vec() }
+        };
+
+        Ok(BMDecodedData {
+            channel: self.header.schn(),
+            timestamp: self.timestamp,
+            payload
+        })
+    }
+}
+
+impl BMDecodedData {
+    /// Encode back to [BMData], setting both source and destination channel.
+    /// Payloads longer than [BM_DATA_PAYLOAD_MAX_SIZE] are truncated.
+    pub fn encode(&self) -> BMData {
+        let (kind, bytes) = match &self.payload {
+            BMPayload::Can(message) => (BMDataType::Can as u8, message.to_bytes()),
+            BMPayload::TxComplete { message } => (BMDataType::Ack as u8 | BMDataType::Can as u8, message.to_bytes()),
+            BMPayload::Lin(bytes) => (BMDataType::Lin as u8, bytes.clone()),
+            BMPayload::Unknown { kind, bytes } => (*kind, bytes.clone())
+        };
+
+        let length = bytes.len().min(BM_DATA_PAYLOAD_MAX_SIZE);
+        let mut payload = [0; BM_DATA_PAYLOAD_MAX_SIZE];
+        payload[..length].copy_from_slice(&bytes[..length]);
+
+        BMData {
+            header: BMDataHeader::new()
+                .with_kind(kind)
+                .with_dchn(self.channel)
+                .with_schn(self.channel),
+            length: length as u16,
+            timestamp: self.timestamp,
+            payload
+        }
+    }
+}
+
+impl From<BMDecodedData> for BMData {
+    fn from(data: BMDecodedData) -> BMData {
+        data.encode()
+    }
+}
+
+/// Read a CAN message from a possibly truncated payload, which must include at least the bytes selected by its DLC.
+fn can_message(bytes: &[u8]) -> Option<BMCanMessage> {
+    if bytes.len() < CAN_MESSAGE_HEADER_SIZE {
+        return None;
+    }
+
+    let mut raw = [0u8; mem::size_of::<BMCanMessage>()];
+    raw[..bytes.len()].copy_from_slice(bytes);
+
+    let message = unsafe { ptr::read_unaligned(raw.as_ptr() as *const BMCanMessage) };
+    if bytes.len() < CAN_MESSAGE_HEADER_SIZE + message.payload().len() {
+        return None;
+    }
+
+    Some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(payload: Vec<u8>) -> BMCanMessage {
+        BMCanMessage::builder().extended_id(0x18DAF110).fdf(true).brs(true).payload(payload).build()
+    }
+
+    fn decoded(payload: BMPayload) -> BMDecodedData {
+        BMDecodedData { channel: 3, timestamp: 0x12345678, payload }
+    }
+
+    fn assert_same_message(a: &BMCanMessage, b: &BMCanMessage) {
+        assert_eq!(a.to_bytes(), b.to_bytes());
+    }
+
+    #[test]
+    fn can_round_trip() {
+        let message = message((0..12).collect());
+        let data = decoded(BMPayload::Can(message)).encode();
+        assert_eq!(data.header.kind(), BMDataType::Can as u8);
+        assert_eq!((data.header.schn(), data.header.dchn()), (3, 3));
+        assert_eq!(data.length as usize, mem::size_of::<BMCanMessage>());
+
+        let decoded = data.decode().unwrap();
+        assert_eq!((decoded.channel, decoded.timestamp), (3, 0x12345678));
+        match decoded.payload {
+            BMPayload::Can(decoded) => assert_same_message(&decoded, &message),
+            payload => panic!("unexpected payload {:?}", payload)
+        }
+    }
+
+    #[test]
+    fn tx_complete_round_trip() {
+        let message = message(vec![1, 2, 3]);
+        let data = decoded(BMPayload::TxComplete { message }).encode();
+        assert_eq!(data.header.kind(), 10);
+
+        match data.decode().unwrap().payload {
+            BMPayload::TxComplete { message: decoded } => assert_same_message(&decoded, &message),
+            payload => panic!("unexpected payload {:?}", payload)
+        }
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let data = decoded(BMPayload::Lin(vec![0x55, 0x01, 0x02])).encode();
+        assert_eq!(data.header.kind(), BMDataType::Lin as u8);
+        assert!(matches!(data.decode().unwrap().payload, BMPayload::Lin(ref bytes) if bytes == &[0x55, 0x01, 0x02]));
+
+        let data = decoded(BMPayload::Unknown { kind: BMDataType::FlexRay as u8, bytes: vec![0xAA; 4] }).encode();
+        match data.decode().unwrap().payload {
+            BMPayload::Unknown { kind, bytes } => {
+                assert_eq!(kind, BMDataType::FlexRay as u8);
+                assert_eq!(bytes, vec![0xAA; 4]);
+            }
+            payload => panic!("unexpected payload {:?}", payload)
+        }
+
+        // Oversized payloads are truncated when encoding.
+        let data = decoded(BMPayload::Lin(vec![0; BM_DATA_PAYLOAD_MAX_SIZE + 1])).encode();
+        assert_eq!(data.length as usize, BM_DATA_PAYLOAD_MAX_SIZE);
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let message = message(vec![0xAB; 12]);
+        let mut data = decoded(BMPayload::Can(message)).encode();
+
+        // The payload must hold the ID and control fields and the payload selected by the DLC.
+        let required = CAN_MESSAGE_HEADER_SIZE + 12;
+        data.length = required as u16;
+        match data.decode().unwrap().payload {
+            BMPayload::Can(decoded) => assert_eq!(decoded.payload(), &[0xAB; 12]),
+            payload => panic!("unexpected payload {:?}", payload)
+        }
+
+        for &length in [0, CAN_MESSAGE_HEADER_SIZE - 1, CAN_MESSAGE_HEADER_SIZE, required - 1].iter() {
+            data.length = length as u16;
+            let error = data.decode().unwrap_err();
+            assert_eq!(error, BMDecodeError { kind: BMDataType::Can as u8, length: length as u16 });
+        }
+
+        data.header.set_kind(10);
+        data.length = (required - 1) as u16;
+        assert_eq!(data.decode().unwrap_err(), BMDecodeError { kind: 10, length: (required - 1) as u16 });
+
+        data.length = (BM_DATA_PAYLOAD_MAX_SIZE + 1) as u16;
+        assert!(data.decode().is_err());
+    }
+}
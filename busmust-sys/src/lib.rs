@@ -3,21 +3,52 @@ extern crate bitfield_struct;
 #[macro_use]
 extern crate bitflags;
 extern crate core;
+#[cfg(feature = "dynamic")]
+extern crate libloading;
 
+#[macro_use]
+mod macros;
 mod types;
 mod api;
+#[cfg(feature = "dynamic")]
+pub mod loader;
+mod bit_timing;
 mod bitrate_builder;
 mod can_message_builder;
 mod data_builder;
+mod decode;
+mod rx_filter_builder;
 
+use std::convert::TryFrom;
 use std::fmt;
 pub use types::*;
 pub use api::*;
+pub use bit_timing::{BMBitTiming, BMBitTimingSolution};
+pub use bitrate_builder::BMBitrateError;
+pub use decode::{BMDecodeError, BMDecodedData, BMPayload};
 
 impl BMCanMessage {
+    /// Get the payload according to DLC, classic CAN messages with DLC above 8 hold 8 bytes
+    /// and remote messages have no payload.
     pub fn payload(&self) -> &[u8] {
-        unsafe {
-            &self.payload[0..self.ctrl.rx.dlc() as usize]
+        let ctrl = unsafe { self.ctrl.rx };
+        let len = if ctrl.rtr() {
+            0
+        } else if ctrl.fdf() {
+            dlc_to_len(ctrl.dlc())
+        } else {
+            (ctrl.dlc() as usize).min(8)
+        };
+
+        &self.payload[..len]
+    }
+
+    /// Get the message identifier, composed of SID and EID if the message is extended.
+    pub fn id(&self) -> BMCanId {
+        if unsafe { self.ctrl.rx.ide() } {
+            BMCanId::Extended(self.mid.extended())
+        } else {
+            BMCanId::Standard(self.mid.sid())
         }
     }
 
@@ -36,8 +67,314 @@ impl BMCanMessage {
     }
 }
 
+impl fmt::Debug for BMCanMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BMCanMessage")
+            .field("id", &self.id())
+            .field("ctrl", unsafe { &self.ctrl.rx })
+            .field("payload", &self.payload())
+            .finish()
+    }
+}
+
+/// Payload lengths in bytes indexed by CAN (FD) DLC.
+const DLC_LENGTHS: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Get the payload length in bytes of a CAN-FD message with the given DLC (0-F).
+pub fn dlc_to_len(dlc: u8) -> usize {
+    DLC_LENGTHS[(dlc & 0x0F) as usize] as usize
+}
+
+/// Get the smallest DLC of a CAN-FD message able to hold `len` payload bytes, or `None` if `len` exceeds 64.
+pub fn len_to_dlc(len: usize) -> Option<u8> {
+    DLC_LENGTHS.iter().position(|&l| l as usize >= len).map(|dlc| dlc as u8)
+}
+
+impl BMMessageId {
+    /// Create a message ID from an 11-bit standard identifier.
+    pub fn from_standard(id: u16) -> BMMessageId {
+        BMMessageId::new().with_sid(id & 0x7FF)
+    }
+
+    /// Create a message ID from a 29-bit extended identifier, SID holds its 11 most significant bits.
+    pub fn from_extended(id: u32) -> BMMessageId {
+        BMMessageId::new()
+            .with_sid(((id >> 18) & 0x7FF) as u16)
+            .with_eid(id & 0x3FFFF)
+    }
+
+    /// Get the 29-bit extended identifier composed of SID and EID.
+    pub fn extended(&self) -> u32 {
+        ((self.sid() as u32) << 18) | self.eid()
+    }
+}
+
+/// Standard (11-bit) or extended (29-bit) CAN message identifier, see [BMCanMessage::id].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BMCanId {
+    Standard(u16),
+    Extended(u32)
+}
+
+impl BMCanId {
+    /// Get the raw identifier value.
+    pub fn raw(&self) -> u32 {
+        match *self {
+            BMCanId::Standard(id) => id as u32,
+            BMCanId::Extended(id) => id
+        }
+    }
+
+    /// Check whether this is an extended identifier.
+    pub fn is_extended(&self) -> bool {
+        matches!(self, BMCanId::Extended(_))
+    }
+}
+
+impl From<BMCanId> for BMMessageId {
+    fn from(id: BMCanId) -> BMMessageId {
+        match id {
+            BMCanId::Standard(id) => BMMessageId::from_standard(id),
+            BMCanId::Extended(id) => BMMessageId::from_extended(id)
+        }
+    }
+}
+
+impl BMTxTask {
+    /// Get the in-memory representation of the TX task, as passed to [BM_SetTxTasks].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let ptr = self as *const BMTxTask as *const u8;
+        let len = std::mem::size_of::<BMTxTask>();
+        unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec()
+    }
+}
+
+impl BMStatus {
+    /// Convert a raw status code returned by the library, `None` unless it is exactly one of the listed values.
+    /// Combined codes (except for [BMStatus::AnyBusError]) and unknown codes are never transmuted into [BMStatus].
+    pub fn from_raw(code: u32) -> Option<BMStatus> {
+        const ALL: [BMStatus; 27] = [
+            BMStatus::Ok, BMStatus::XmtFull, BMStatus::Overrun, BMStatus::BusLight, BMStatus::BusWarning,
+            BMStatus::BusPassive, BMStatus::BusTimeout, BMStatus::BusOff, BMStatus::AnyBusError,
+            BMStatus::ReceiveBufferEmpty, BMStatus::QueueOverrun, BMStatus::TransmitQueueFull, BMStatus::RegTest,
+            BMStatus::NoDriver, BMStatus::HardwareInUse, BMStatus::NetInUse, BMStatus::HardwareError,
+            BMStatus::InvalidBus, BMStatus::InvalidClient, BMStatus::OutOfResources, BMStatus::InvalidParameterType,
+            BMStatus::InvalidParameterValue, BMStatus::Unknown, BMStatus::InvalidData, BMStatus::Caution,
+            BMStatus::NotInitialized, BMStatus::InvalidOperation
+        ];
+
+        ALL.iter().find(|&&status| status as u32 == code).copied()
+    }
+}
+
+impl TryFrom<u32> for BMStatus {
+    type Error = u32;
+
+    fn try_from(code: u32) -> Result<BMStatus, u32> {
+        BMStatus::from_raw(code).ok_or(code)
+    }
+}
+
+impl BMLogLevel {
+    /// Convert a raw log level returned by [BM_GetLogLevel], `None` if it is unknown.
+    pub fn from_raw(level: u32) -> Option<BMLogLevel> {
+        match level {
+            0 => Some(BMLogLevel::None),
+            1 => Some(BMLogLevel::Error),
+            2 => Some(BMLogLevel::Warning),
+            3 => Some(BMLogLevel::Info),
+            4 => Some(BMLogLevel::Debug),
+            _ => None
+        }
+    }
+}
+
+impl TryFrom<u32> for BMLogLevel {
+    type Error = u32;
+
+    fn try_from(level: u32) -> Result<BMLogLevel, u32> {
+        BMLogLevel::from_raw(level).ok_or(level)
+    }
+}
+
+impl BMChannelInfo {
+    /// Get the known capability flags of the device, unknown bits are dropped, see [BMChannelInfo::cap] for the raw value.
+    pub fn capabilities(&self) -> BMCapability {
+        // BMCapability::ALL covers every bit, mask with the single flags instead.
+        let known = BMCapability::LIN | BMCapability::CAN | BMCapability::CAN_FD | BMCapability::FLEXRAY |
+            BMCapability::MODBUS | BMCapability::ETHERNET;
+        BMCapability::from_bits_truncate(self.cap) & known
+    }
+}
+
 impl fmt::Display for BMStatus {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::mem;
+    use super::*;
+
+    /// Arbitrary codes: all low codes, every single bit or all but one bit, and some combinations of high bits.
+    fn codes() -> Vec<u32> {
+        let mut codes: Vec<u32> = (0..0x20000).collect();
+        codes.extend((0..32).map(|bit| 1u32 << bit));
+        codes.extend((0..32).map(|bit| !(1u32 << bit)));
+        codes.extend(&[0x4001C, 0x80020, 0x2000020, 0x4000000 | 0x200, u32::MAX]);
+        codes.sort_unstable();
+        codes.dedup();
+        codes
+    }
+
+    #[test]
+    fn status_from_known_codes() {
+        let known = [
+            (0x00000, BMStatus::Ok),
+            (0x00020, BMStatus::ReceiveBufferEmpty),
+            (0x80000, BMStatus::BusTimeout),
+            (0x4001C, BMStatus::AnyBusError),
+            (0x01C00, BMStatus::InvalidClient),
+            (0x8000000, BMStatus::InvalidOperation)
+        ];
+
+        for &(code, status) in known.iter() {
+            assert_eq!(BMStatus::from_raw(code).map(|s| s as u32), Some(status as u32));
+            assert_eq!(BMStatus::try_from(code).map(|s| s as u32), Ok(code));
+        }
+    }
+
+    #[test]
+    fn status_from_arbitrary_codes() {
+        let mut known = 0;
+        for code in codes() {
+            match BMStatus::from_raw(code) {
+                Some(status) => {
+                    assert_eq!(status as u32, code);
+                    known += 1;
+                }
+                None => assert_eq!(BMStatus::try_from(code).map(|s| s as u32), Err(code))
+            }
+        }
+        assert_eq!(known, 27);
+    }
+
+    #[test]
+    fn combined_status_codes_are_unknown() {
+        let combined = [
+            BMStatus::BusOff as u32 | BMStatus::ReceiveBufferEmpty as u32,
+            BMStatus::BusWarning as u32 | BMStatus::BusLight as u32,
+            BMStatus::BusTimeout as u32 | BMStatus::XmtFull as u32,
+            BMStatus::Caution as u32 | BMStatus::NoDriver as u32
+        ];
+
+        for &code in combined.iter() {
+            assert!(BMStatus::from_raw(code).is_none());
+            assert_eq!(BMStatus::try_from(code).map(|s| s as u32), Err(code));
+        }
+    }
+
+    #[test]
+    fn log_level_from_raw() {
+        assert_eq!(BMLogLevel::from_raw(0), Some(BMLogLevel::None));
+        assert_eq!(BMLogLevel::from_raw(4), Some(BMLogLevel::Debug));
+
+        for code in codes() {
+            match BMLogLevel::from_raw(code) {
+                Some(level) => assert_eq!(level as u32, code),
+                None => {
+                    assert!(code > 4);
+                    assert_eq!(BMLogLevel::try_from(code), Err(code));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn capabilities_drop_unknown_bits() {
+        let mut info: BMChannelInfo = unsafe { mem::zeroed() };
+        assert!(info.capabilities().is_empty());
+
+        info.cap = (BMCapability::CAN | BMCapability::CAN_FD).bits() | 0x8100;
+        assert_eq!(info.capabilities(), BMCapability::CAN | BMCapability::CAN_FD);
+
+        for cap in 0..=u16::MAX {
+            info.cap = cap;
+            assert_eq!(info.capabilities().bits(), cap & 0x003F);
+        }
+    }
+
+    #[test]
+    fn dlc_lengths() {
+        let lengths = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+        for (dlc, &len) in lengths.iter().enumerate() {
+            assert_eq!(dlc_to_len(dlc as u8), len);
+            assert_eq!(len_to_dlc(len), Some(dlc as u8));
+        }
+
+        // Only the low nibble is used.
+        assert_eq!(dlc_to_len(0x1F), 64);
+
+        // Lengths between two DLCs round up.
+        assert_eq!(len_to_dlc(9), Some(9));
+        assert_eq!(len_to_dlc(13), Some(10));
+        assert_eq!(len_to_dlc(33), Some(14));
+        assert_eq!(len_to_dlc(63), Some(15));
+        assert_eq!(len_to_dlc(65), None);
+    }
+
+    #[test]
+    fn payload_by_dlc() {
+        for len in 0..=8 {
+            let message = BMCanMessage::builder().sid(0x123).payload(vec![0xAA; len]).build();
+            assert_eq!(message.payload(), &vec![0xAA; len][..]);
+        }
+
+        // Classic messages with DLC above 8 hold 8 bytes.
+        let message = BMCanMessage::builder().sid(0x123).payload(vec![0xAA; 8]).dlc(15).build();
+        assert_eq!(message.payload(), &[0xAA; 8]);
+
+        // CAN FD payloads are padded to the next DLC and truncated to 64 bytes.
+        let message = BMCanMessage::builder().sid(0x123).fdf(true).payload(vec![0xAA; 9]).build();
+        assert_eq!(unsafe { message.ctrl.rx.dlc() }, 9);
+        assert_eq!(message.payload(), &[0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0, 0, 0]);
+
+        let message = BMCanMessage::builder().sid(0x123).fdf(true).payload(vec![0xAA; 70]).build();
+        assert_eq!(unsafe { message.ctrl.rx.dlc() }, 15);
+        assert_eq!(message.payload(), &[0xAA; 64][..]);
+
+        // Remote messages have no payload.
+        let message = BMCanMessage::builder().sid(0x123).rtr(true).dlc(8).build();
+        assert!(message.payload().is_empty());
+    }
+
+    #[test]
+    fn message_id_layout() {
+        let id = BMMessageId::from_extended(0x18DAF110);
+        assert_eq!(id.sid(), (0x18DAF110u32 >> 18) as u16);
+        assert_eq!(id.eid(), 0x18DAF110 & 0x3FFFF);
+        assert_eq!(id.extended(), 0x18DAF110);
+        assert_eq!(u32::from(id), (0x18DAF110 & 0x3FFFF) << 11 | 0x18DAF110 >> 18);
+
+        let id = BMMessageId::from_extended(0x1FFFFFFF);
+        assert_eq!((id.sid(), id.eid()), (0x7FF, 0x3FFFF));
+        assert_eq!(id.extended(), 0x1FFFFFFF);
+        assert_eq!(u32::from(id), 0x1FFFFFFF);
+
+        // Bits above 29 are dropped.
+        assert_eq!(BMMessageId::from_extended(0xFFFFFFFF).extended(), 0x1FFFFFFF);
+
+        let id = BMMessageId::from_standard(0x7FF);
+        assert_eq!((id.sid(), id.eid()), (0x7FF, 0));
+        assert_eq!(u32::from(id), 0x7FF);
+        assert_eq!(id.extended(), 0x7FFu32 << 18);
+        assert_eq!(BMMessageId::from_standard(0xFFFF).sid(), 0x7FF);
+
+        for &id in [BMCanId::Standard(0x7FF), BMCanId::Extended(0x18DAF110), BMCanId::Extended(0x1FFFFFFF)].iter() {
+            let message = BMCanMessage::builder().id(id).build();
+            assert_eq!(message.id(), id);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Runtime loading of the Busmust library, enabled by the `dynamic` feature.
+//!
+//! The library is loaded on the first API call from [default_library_path], unless [load_library] has been called
+//! before. If the library could not be loaded, every API call fails with [BMStatus::NoDriver] instead,
+//! use [ensure_loaded] to find out why. Functions are resolved on their first call, so a library missing some
+//! functions (i.e. an older driver) only fails the calls to those with [BMStatus::NoDriver],
+//! use [check_symbols] to find out whether any function is missing.
+
+use std::env;
+use std::error;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use libloading::{self, Library};
+use api::Api;
+use types::BMStatus;
+
+/// Environment variable overriding the path of the library loaded by default.
+pub const LIBRARY_PATH_ENV: &str = "BMAPI_LIBRARY";
+
+/// Errors occurred while loading the Busmust library.
+#[derive(Debug, Clone)]
+pub enum LoadError {
+    /// The library could not be loaded from the given path, i.e. the driver is not installed
+    NotFound { path: OsString, reason: String },
+    /// The library does not export the given function, i.e. the driver is too old
+    MissingSymbol { name: &'static str }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotFound { path, reason } => {
+                write!(f, "Busmust driver not installed, failed to load {}: {}", path.to_string_lossy(), reason)
+            }
+            LoadError::MissingSymbol { name } => write!(f, "Busmust driver too old, function {} not found", name)
+        }
+    }
+}
+
+impl error::Error for LoadError {}
+
+pub(crate) struct Loaded {
+    library: Library,
+    pub(crate) api: Api
+}
+
+impl Loaded {
+    /// Resolve a function by its NUL terminated name, `None` if the library does not export it.
+    pub(crate) unsafe fn resolve<T: Copy>(&self, name: &str) -> Option<T> {
+        self.library.get::<T>(name.as_bytes()).ok().map(|symbol| *symbol)
+    }
+}
+
+static LOADED: AtomicPtr<Loaded> = AtomicPtr::new(ptr::null_mut());
+static DEFAULT_ERROR: Mutex<Option<LoadError>> = Mutex::new(None);
+
+/// Get the path of the library loaded by default: the value of [LIBRARY_PATH_ENV] if set,
+/// otherwise the platform specific file name of `bmapi64` (`bmapi` on 32-bit targets) searched in the default locations.
+pub fn default_library_path() -> OsString {
+    env::var_os(LIBRARY_PATH_ENV).unwrap_or_else(|| {
+        libloading::library_filename(if cfg!(target_pointer_width = "64") { "bmapi64" } else { "bmapi" })
+    })
+}
+
+/// Load the library from the given path, does nothing if a library is already loaded.
+///
+/// # Examples
+///
+/// ```no_run
+/// busmust_sys::loader::load_library("/opt/busmust/lib/libbmapi64.so").unwrap();
+/// ```
+pub fn load_library<P: AsRef<OsStr>>(path: P) -> Result<(), LoadError> {
+    let _lock = DEFAULT_ERROR.lock().unwrap_or_else(|e| e.into_inner());
+    if is_loaded() {
+        return Ok(());
+    }
+
+    let path = path.as_ref();
+    unsafe {
+        let library = Library::new(path)
+            .map_err(|e| LoadError::NotFound { path: path.to_os_string(), reason: e.to_string() })?;
+
+        // Never unloaded, as the resolved functions could be called at any time.
+        LOADED.store(Box::into_raw(Box::new(Loaded { library, api: Api::default() })), Ordering::Release);
+    }
+    Ok(())
+}
+
+/// Check whether the library is loaded.
+pub fn is_loaded() -> bool {
+    !LOADED.load(Ordering::Acquire).is_null()
+}
+
+/// Load the library from [default_library_path] unless already loaded.
+/// A failure is remembered, so the library is only looked up once unless [load_library] is called explicitly.
+pub fn ensure_loaded() -> Result<(), LoadError> {
+    if is_loaded() {
+        return Ok(());
+    }
+
+    if let Some(e) = DEFAULT_ERROR.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+        return Err(e);
+    }
+
+    load_library(default_library_path()).inspect_err(|e| {
+        *DEFAULT_ERROR.lock().unwrap_or_else(|e| e.into_inner()) = Some(e.clone());
+    })
+}
+
+/// Load the library like [ensure_loaded] and check that it exports every function of the API.
+///
+/// returns: [LoadError::NotFound] if the library could not be loaded, [LoadError::MissingSymbol] with the first
+/// missing function if it is too old.
+///
+/// # Examples
+///
+/// ```no_run
+/// use busmust_sys::loader::{self, LoadError};
+///
+/// match loader::check_symbols() {
+///     Ok(()) => {}
+///     Err(LoadError::MissingSymbol { name }) => eprintln!("please update the Busmust driver, {} is missing", name),
+///     Err(e) => panic!("{}", e)
+/// }
+/// ```
+pub fn check_symbols() -> Result<(), LoadError> {
+    let loaded = match loaded() {
+        Some(loaded) => loaded,
+        None => return ensure_loaded()
+    };
+
+    loaded.api.resolve_all(loaded).map_err(|name| LoadError::MissingSymbol { name })
+}
+
+pub(crate) fn loaded() -> Option<&'static Loaded> {
+    ensure_loaded().ok()?;
+    unsafe { LOADED.load(Ordering::Acquire).as_ref() }
+}
+
+/// Value returned by API calls if the library is not loaded.
+pub(crate) trait Fallback {
+    fn fallback() -> Self;
+}
+
+impl Fallback for u32 {
+    fn fallback() -> Self {
+        BMStatus::NoDriver as u32
+    }
+}
+
+impl Fallback for *const c_void {
+    fn fallback() -> Self {
+        ptr::null()
+    }
+}
+
+impl Fallback for c_int {
+    fn fallback() -> Self {
+        -1
+    }
+}
+
+impl Fallback for () {
+    fn fallback() -> Self {}
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use api::BM_Init;
+    use super::*;
+
+    #[test]
+    fn missing_functions_fall_back() {
+        // Any library loads, functions it does not export fail on their own.
+        load_library("libc.so.6").unwrap();
+        assert!(is_loaded());
+        assert!(ensure_loaded().is_ok());
+
+        assert_eq!(unsafe { BM_Init() }, BMStatus::NoDriver as u32);
+    }
+
+    #[test]
+    fn missing_functions_are_reported() {
+        load_library("libc.so.6").unwrap();
+
+        let error = check_symbols().unwrap_err();
+        assert!(matches!(error, LoadError::MissingSymbol { name: "BM_Init" }));
+        assert_eq!(error.to_string(), "Busmust driver too old, function BM_Init not found");
+    }
+}
@@ -0,0 +1,51 @@
+/// Declare the Busmust library functions.
+///
+/// By default the functions are linked at build time (on x86_64), with the `dynamic` feature they are wrappers
+/// calling into the function table resolved at runtime by [super::loader], returning a fallback
+/// value (i.e. [super::BMStatus::NoDriver]) if the library could not be loaded or does not export the function.
+macro_rules! bmapi {
+    ($($(#[$attr:meta])* pub fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {
+        #[cfg(all(not(feature = "dynamic"), target_arch = "x86_64"))]
+        #[link(name = "bmapi64")]
+        #[allow(non_snake_case)]
+        extern "C" {
+            $($(#[$attr])* pub fn $name($($arg: $ty),*) $(-> $ret)?;)*
+        }
+
+        /// Function table of the Busmust library, each function is resolved on its first call.
+        #[cfg(feature = "dynamic")]
+        #[allow(non_snake_case)]
+        #[derive(Default)]
+        pub(crate) struct Api {
+            $($name: ::std::sync::OnceLock<Option<unsafe extern "C" fn($($ty),*) $(-> $ret)?>>,)*
+        }
+
+        #[cfg(feature = "dynamic")]
+        impl Api {
+            /// Resolve every function, returning the name of the first one the library does not export.
+            pub(crate) fn resolve_all(&self, loaded: &::loader::Loaded) -> Result<(), &'static str> {
+                $(
+                    if self.$name.get_or_init(|| unsafe { loaded.resolve(concat!(stringify!($name), "\0")) }).is_none() {
+                        return Err(stringify!($name));
+                    }
+                )*
+                Ok(())
+            }
+        }
+
+        $(
+            #[cfg(feature = "dynamic")]
+            $(#[$attr])*
+            #[allow(non_snake_case, clippy::missing_safety_doc)]
+            pub unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+                let function = ::loader::loaded().and_then(|loaded| {
+                    *loaded.api.$name.get_or_init(|| loaded.resolve(concat!(stringify!($name), "\0")))
+                });
+                match function {
+                    Some(function) => function($($arg),*),
+                    None => ::loader::Fallback::fallback()
+                }
+            }
+        )*
+    }
+}
@@ -0,0 +1,187 @@
+use ::{BMRxFilter, BMRxFilterType};
+use ::{BMMessageFlags, BMMessageId};
+
+impl BMRxFilter {
+    pub fn builder() -> BMRxFilterBuilder {
+        BMRxFilterBuilder::default()
+    }
+}
+
+/// Builder for [BMRxFilter] structure.
+///
+/// If no filter type is given explicitly, [BMRxFilterType::Advanced] is used as soon as any payload mask bit is set,
+/// otherwise the filter is built as [BMRxFilterType::Basic].
+#[derive(Default)]
+pub struct BMRxFilterBuilder {
+    kind: Option<BMRxFilterType>,
+    flags_mask: u8,
+    flags_value: u8,
+    id_mask: u32,
+    id_value: u32,
+    payload_mask: [u8; 8],
+    payload_value: [u8; 8]
+}
+
+impl BMRxFilterBuilder {
+    pub fn new() -> BMRxFilterBuilder {
+        BMRxFilterBuilder::default()
+    }
+
+    pub fn kind(mut self, kind: BMRxFilterType) -> BMRxFilterBuilder {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Accept only standard (11-bit) messages whose `ID & mask == id & mask`.
+    pub fn standard_id(mut self, id: u16, mask: u16) -> BMRxFilterBuilder {
+        self.id_mask = (mask & 0x7FF) as u32;
+        self.id_value = (id & mask & 0x7FF) as u32;
+        self.flag(BMMessageFlags::Extended, false)
+    }
+
+    /// Accept only extended (29-bit) messages whose `ID & mask == id & mask`.
+    pub fn extended_id(mut self, id: u32, mask: u32) -> BMRxFilterBuilder {
+        self.id_mask = BMMessageId::from_extended(mask).into();
+        self.id_value = BMMessageId::from_extended(id & mask).into();
+        self.flag(BMMessageFlags::Extended, true)
+    }
+
+    /// Require the given message flag to be either set or cleared, see [BMMessageFlags] for details.
+    pub fn flag(mut self, flag: BMMessageFlags, value: bool) -> BMRxFilterBuilder {
+        let bit = flag as u8;
+        self.flags_mask |= bit;
+
+        if value {
+            self.flags_value |= bit;
+        } else {
+            self.flags_value &= !bit;
+        }
+        self
+    }
+
+    /// Set raw message flags mask and value, see [BMMessageFlags] for details.
+    pub fn flags(mut self, mask: u8, value: u8) -> BMRxFilterBuilder {
+        self.flags_mask = mask;
+        self.flags_value = value & mask;
+        self
+    }
+
+    /// Set the mask and expected value of the first 8 payload bytes.
+    pub fn payload(mut self, mask: [u8; 8], value: [u8; 8]) -> BMRxFilterBuilder {
+        self.payload_mask = mask;
+        for (v, (m, x)) in self.payload_value.iter_mut().zip(mask.iter().zip(value.iter())) {
+            *v = x & m;
+        }
+        self
+    }
+
+    /// Set the mask and expected value of a single payload byte, `index` must be less than 8.
+    pub fn payload_byte(mut self, index: usize, mask: u8, value: u8) -> BMRxFilterBuilder {
+        self.payload_mask[index] = mask;
+        self.payload_value[index] = value & mask;
+        self
+    }
+
+    pub fn build(self) -> BMRxFilter {
+        let kind = self.kind.unwrap_or_else(|| {
+            if self.payload_mask.iter().any(|&b| b != 0) {
+                BMRxFilterType::Advanced
+            } else {
+                BMRxFilterType::Basic
+            }
+        });
+
+        BMRxFilter {
+            kind: kind as u8,
+            unused: 0,
+            flags_mask: self.flags_mask,
+            flags_value: self.flags_value,
+            reserved: [0; 4],
+            id_mask: self.id_mask,
+            id_value: self.id_value,
+            payload_mask: self.payload_mask,
+            payload_value: self.payload_value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind_selection() {
+        assert_eq!(BMRxFilter::builder().standard_id(0x123, 0x7FF).build().kind, BMRxFilterType::Basic as u8);
+        assert_eq!(BMRxFilter::builder().payload_byte(0, 0xFF, 0x10).build().kind, BMRxFilterType::Advanced as u8);
+        assert_eq!(BMRxFilter::builder().payload([0, 0, 0, 0, 0, 0, 0, 1], [0; 8]).build().kind, BMRxFilterType::Advanced as u8);
+
+        // An all-zero payload mask does not check the payload.
+        assert_eq!(BMRxFilter::builder().payload([0; 8], [0xFF; 8]).build().kind, BMRxFilterType::Basic as u8);
+
+        // An explicit kind is kept.
+        let filter = BMRxFilter::builder().kind(BMRxFilterType::Basic).payload_byte(0, 0xFF, 0x10).build();
+        assert_eq!(filter.kind, BMRxFilterType::Basic as u8);
+        let filter = BMRxFilter::builder().kind(BMRxFilterType::E2EPass).build();
+        assert_eq!(filter.kind, BMRxFilterType::E2EPass as u8);
+    }
+
+    #[test]
+    fn payload_masking() {
+        let filter = BMRxFilter::builder()
+            .payload([0xFF, 0x0F, 0xF0, 0, 0xFF, 0, 0, 0x81], [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xFF])
+            .build();
+        assert_eq!(filter.payload_mask, [0xFF, 0x0F, 0xF0, 0, 0xFF, 0, 0, 0x81]);
+        assert_eq!(filter.payload_value, [0x12, 0x04, 0x50, 0, 0x9A, 0, 0, 0x81]);
+
+        let filter = BMRxFilter::builder()
+            .payload_byte(1, 0xF0, 0xAB)
+            .payload_byte(7, 0x01, 0xFF)
+            .payload_byte(1, 0x0F, 0xAB)
+            .build();
+        assert_eq!(filter.payload_mask, [0, 0x0F, 0, 0, 0, 0, 0, 0x01]);
+        assert_eq!(filter.payload_value, [0, 0x0B, 0, 0, 0, 0, 0, 0x01]);
+    }
+
+    #[test]
+    fn standard_id() {
+        let filter = BMRxFilter::builder().standard_id(0x7E8, 0x7F0).build();
+        assert_eq!(filter.id_mask, 0x7F0);
+        assert_eq!(filter.id_value, 0x7E0);
+        assert_eq!(filter.flags_mask, BMMessageFlags::Extended as u8);
+        assert_eq!(filter.flags_value, 0);
+
+        // Bits above the 11-bit ID are dropped.
+        let filter = BMRxFilter::builder().standard_id(0xFFFF, 0xFFFF).build();
+        assert_eq!(filter.id_mask, 0x7FF);
+        assert_eq!(filter.id_value, 0x7FF);
+    }
+
+    #[test]
+    fn extended_id() {
+        let filter = BMRxFilter::builder().extended_id(0x18DAF110, 0x1FFFFF00).build();
+        assert_eq!(filter.id_mask, u32::from(BMMessageId::from_extended(0x1FFFFF00)));
+        assert_eq!(filter.id_value, u32::from(BMMessageId::from_extended(0x18DAF100)));
+        assert_eq!(BMMessageId::from(filter.id_value).extended(), 0x18DAF100);
+        assert_eq!(filter.flags_mask, BMMessageFlags::Extended as u8);
+        assert_eq!(filter.flags_value, BMMessageFlags::Extended as u8);
+
+        // The upper 11 bits of the extended ID are matched against the SID part of the raw ID.
+        let filter = BMRxFilter::builder().extended_id(0x1FFC0000, 0x1FFC0000).build();
+        assert_eq!(filter.id_mask, 0x7FF);
+        assert_eq!(filter.id_value, 0x7FF);
+    }
+
+    #[test]
+    fn flags() {
+        let filter = BMRxFilter::builder()
+            .flag(BMMessageFlags::CanFD, true)
+            .flag(BMMessageFlags::Remote, false)
+            .build();
+        assert_eq!(filter.flags_mask, BMMessageFlags::CanFD as u8 | BMMessageFlags::Remote as u8);
+        assert_eq!(filter.flags_value, BMMessageFlags::CanFD as u8);
+
+        let filter = BMRxFilter::builder().flags(0x0C, 0xFF).build();
+        assert_eq!(filter.flags_mask, 0x0C);
+        assert_eq!(filter.flags_value, 0x0C);
+    }
+}
@@ -16,6 +16,7 @@ pub const BM_DATA_MAX_SIZE: usize = BM_DATA_HEADER_SIZE + BM_DATA_PAYLOAD_MAX_SI
 
 /// Busmust library log level, see [super::api::BM_SetLogLevel] for details.
 #[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BMLogLevel {
     /// Show nothing on debug console
     None = 0,
@@ -171,6 +172,7 @@ pub enum BMTerminalResistor {
 
 /// CAN Message type flags, used in [BMCanMessage]
 #[repr(u8)]
+#[derive(Debug, Copy, Clone)]
 pub enum BMMessageFlags {
     /// Normal CAN message
     Normal = 0,
@@ -188,6 +190,7 @@ pub enum BMMessageFlags {
 
 /// CAN RX filter type IDs, used in [BMRxFilter]
 #[repr(u32)]
+#[derive(Debug, Copy, Clone)]
 pub enum BMRxFilterType {
     /// Invalid (unused) RX filter entry
     Invalid = 0,
@@ -203,6 +206,7 @@ pub enum BMRxFilterType {
 
 /// CAN TX task type IDs, used in [BMTxTask].
 #[repr(u32)]
+#[derive(Debug, Copy, Clone)]
 pub enum BMTxTaskType {
     /// Invalid (unused) TX task entry
     Invalid = 0,
@@ -239,6 +243,7 @@ pub enum BMStatType {
 
 /// ISO-TP operation mode.
 #[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BMIsotpMode {
     /// Default mode: normal (non-extended-addressing) UDS client(tester)
     NormalTester = 0,
@@ -270,6 +275,7 @@ pub struct BMDataHeader {
 
 /// Busmust data, abstract structure which holds concrete payload messages of various types (i.e. CAN messages).
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct BMData {
     /// Data header, see [BMDataHeader] for details.
     pub header: BMDataHeader,
@@ -400,15 +406,15 @@ pub struct BMChannelInfo {
     pub pid: u16,
     /// Port ID (0-7) of the device, note a multi-port device is enumerated as multiple dedicated [BMChannelInfo] entries
     pub port: u16,
-    /// Device Capability flags, see [BMCapability] for details.
-    pub cap: BMCapability,
+    /// Raw device capability flags, see [BMChannelInfo::capabilities].
+    pub cap: u16,
     /// Reserved
     pub reserved: [u8; 4]
 }
 
 /// CAN channel status detailed information, retrieved by calling [super::api::BM_GetStatus], see ISO11898 for details.
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct BMCanStatusInfo {
     /// The CAN channel is in BUS-OFF state
     pub tx_bus_off: u8,
@@ -430,6 +436,7 @@ pub struct BMCanStatusInfo {
 
 /// CAN channel bitrate configuration, used by [super::api::BM_SetBitrate]
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct BMBitrate {
     /// Nominal bitrate in kbps, default as 500, note this is the only valid bitrate in CAN CLASSIC mode.
     pub n_bitrate: u16,
@@ -459,6 +466,7 @@ pub struct BMBitrate {
 /// in order for a message to be accepted, all the fields are masked using AND logic:
 /// `(flags & filter.flags_mask == filter.flags_value) AND (ID & filter.id_mask == filter.id_value) AND (payload & filter.payload_mask == filter.payload_value)`
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct BMRxFilter {
     /// Type ID of the RX filter, see [BMRxFilterType] for details.
     pub kind: u8,
@@ -470,6 +478,7 @@ pub struct BMRxFilter {
     pub flags_value: u8,
     /// Reserved
     pub reserved: [u8; 4],
+    /// CAN message ID masks, see [BMMessageId] for details.
     pub id_mask: u32,
     /// CAN message ID values, see [BMMessageId] for details.
     pub id_value: u32,
@@ -477,16 +486,17 @@ pub struct BMRxFilter {
     pub payload_mask: [u8; 8],
     /// CAN message payload values, for CAN-FD messages, only the first 8 bytes are checked.
     pub payload_value: [u8; 8]
-
 }
 
+/// Pattern of a [BMTxTaskType::IncData] TX task.
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct BMTxTaskIncDataPattern {
     /// Start bit of data increment, currently only 8-bit aligned value is accepted
     pub start_bit: u16,
     /// Number of bits of data increment, currently only 32 is accepted
     pub nbits: u8,
-    /// Number of bits of data increment, currently only 32 is accepted
+    /// 0x80=Intel, 0x00=Motorola
     pub format: u8,
     /// Minimum value of the Increment range
     pub min: u32,
@@ -496,7 +506,9 @@ pub struct BMTxTaskIncDataPattern {
     pub step: u32
 }
 
+/// Pattern of a [BMTxTaskType::IncId] TX task.
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct BMTxTaskIncIdPattern {
     /// Minimum value of the Increment range
     pub min: u32,
@@ -506,7 +518,9 @@ pub struct BMTxTaskIncIdPattern {
     pub step: u32
 }
 
+/// Pattern of a [BMTxTaskType::RandomData] TX task.
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct BMTxTaskRndDataPattern {
     /// Start bit of data Random, currently only 8-bit aligned value is accepted
     pub start_bit: u16,
@@ -522,7 +536,9 @@ pub struct BMTxTaskRndDataPattern {
     pub seed: u32
 }
 
+/// Pattern of a [BMTxTaskType::RandomId] TX task.
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct BMTxTaskRndIdPattern {
     /// Minimum value of the Increment range
     pub min: u32,
@@ -532,11 +548,28 @@ pub struct BMTxTaskRndIdPattern {
     pub seed: u32
 }
 
+/// TX task pattern, the valid member is selected by [BMTxTask::kind].
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union BMTxTaskPattern {
+    /// Pattern of a [BMTxTaskType::IncData] TX task
+    pub inc_data: BMTxTaskIncDataPattern,
+    /// Pattern of a [BMTxTaskType::IncId] TX task
+    pub inc_id: BMTxTaskIncIdPattern,
+    /// Pattern of a [BMTxTaskType::RandomData] TX task
+    pub random_data: BMTxTaskRndDataPattern,
+    /// Pattern of a [BMTxTaskType::RandomId] TX task
+    pub random_id: BMTxTaskRndIdPattern,
+    /// Reserved, defines the total size of the pattern
+    pub unused: [u8; 32]
+}
+
 /// CAN channel TX task item structure, used by [super::api::BM_SetTxTasks]
 /// Once the CAN device is armed with TX tasks, it will try to parse the TX task and send CAN messages automatically.
 /// The difference with a software triggered CAN message in BusMaster is that hardware triggered CAN messages are
 /// more precise in time and could reach a higher throughput.
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct BMTxTask {
     /// Type ID of the TX task, see [BMTxTaskType] for details.
     pub kind: u8,
@@ -558,15 +591,15 @@ pub struct BMTxTask {
     pub n_messages: u16,
     /// CAN message arbitration ID, see [BMMessageId] for details.
     pub id: u32,
-    /// TX task pattern data
-    pub pattern: u32,
-    //TODO
+    /// TX task pattern data, see [BMTxTaskPattern] for details.
+    pub pattern: BMTxTaskPattern,
     /// Default payload data, note this is also the template payload of the unchanged part in a volatile TX task
     pub payload: [u8; 64]
 }
 
 /// ISO-TP status report, used by ISO-TP operation callback function.
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct BMIsoTPStatus {
     /// Currently always 0x01
     pub version: u8,
@@ -587,9 +620,11 @@ pub struct BMIsoTPStatus {
 }
 
 /// Callback type for ISO-TP status callback
-type BMIsotpCallbackHandle = extern fn(status: *const BMIsoTPStatus, arg: *const c_void);
+pub type BMIsotpCallbackHandle = extern "C" fn(status: *const BMIsoTPStatus, arg: *const c_void);
 
+/// ISO-TP timeout configuration, used in [BMIsotpConfig].
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct BMIsotpTimeoutConfig {
     /// `A` timeout in milliseconds: `=N_As` if writing as tester or reading as ECU, otherwise `=N_Ar`
     pub a: u16,
@@ -599,8 +634,9 @@ pub struct BMIsotpTimeoutConfig {
     pub c: u16
 }
 
-
+/// ISO-TP flow control configuration, used in [BMIsotpConfig].
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct BMIsotpFlowControlConfig {
     /// STmin raw value (0x00-0x7F or 0xF1-0xF9) if Busmust device is acting as UDS server
     pub st_min: u8,
@@ -613,6 +649,7 @@ pub struct BMIsotpFlowControlConfig {
 }
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 /// ISOTP Protocol (See ISO15765-2 for details) configuration.
 pub struct BMIsotpConfig {
     /// Currently must be set to 0x01
@@ -636,7 +673,7 @@ pub struct BMIsotpConfig {
     /// Reserved for future
     pub padding: [u8; 2],
     /// Callback function when any progress is made, used typically by GUI to show progress bar
-    pub callback: BMIsotpCallbackHandle,
+    pub callback: Option<BMIsotpCallbackHandle>,
     /// Callback user arg when any progress is made, used typically by GUI to show progress bar
     pub callback_user_arg: *const c_void,
     /// All tester messages will be formatted/checked using this template, configure CAN message ID and IDE/FDF flags here
@@ -1,13 +1,14 @@
 extern crate busmust;
 extern crate busmust_sys;
 
-use busmust::dmgr;
+use busmust::dmgr::BusMust;
 use busmust_sys::{BMBitrate, BMCanMessage, BMCanMode, BMData, BMLogLevel};
 
 fn main() {
-    dmgr::initialize().unwrap();
+    let busmust = BusMust::new().unwrap();
+    busmust.set_log_level(BMLogLevel::Info);
 
-    for mut device in dmgr::enum_devices().unwrap() {
+    for device in busmust.enum_devices().unwrap() {
         println!("name: {}", device.name());
         println!("serial num: {}", device.serial_number());
         println!("unique id: {}", device.unique_id());
@@ -16,10 +17,9 @@ fn main() {
         println!("pid: {}", device.product_id());
         println!("caps: {:?}", device.caps());
 
-        device.set_log_level(BMLogLevel::Info);
-        device.open_ex().unwrap();
-        device.set_bitrate(BMBitrate::builder().bitrate(250).build()).unwrap();
-        device.set_can_mode(BMCanMode::InternalLoopback).unwrap();
+        let channel = device.open_ex().unwrap();
+        channel.set_bitrate(BMBitrate::CAN_250K).unwrap();
+        channel.set_can_mode(BMCanMode::InternalLoopback).unwrap();
 
         for _ in 0..500 {
             let msg = BMCanMessage::builder()
@@ -27,9 +27,9 @@ fn main() {
                 .payload(vec![1, 2, 3, 4, 5, 6, 7, 8])
                 .build();
 
-            device.write_can_message(msg, Some(100)).unwrap();
-            device.wait_for_notification(Some(100));
-            device.read_can_message().unwrap().expect("no message");
+            channel.write_can_message(msg, Some(100)).unwrap();
+            channel.wait_for_notification(Some(100));
+            channel.read_can_message().unwrap().expect("no message");
         }
 
         let msg = BMCanMessage::builder()
@@ -41,10 +41,8 @@ fn main() {
             .can_message(msg)
             .build();
 
-        device.write(data, Some(1000)).unwrap();
-        device.clear_buffer().unwrap();
-        device.close().unwrap();
+        channel.write(data, Some(1000)).unwrap();
+        channel.clear_buffer().unwrap();
+        channel.close().unwrap();
     }
-
-    dmgr::terminate().unwrap();
 }
@@ -0,0 +1,436 @@
+//! Asynchronous access to an opened channel, enabled by the `tokio` feature.
+//!
+//! [AsyncDevice] receives frames on a dedicated notification thread and exposes them as a
+//! [Stream], frames are transmitted through its [Sink] implementation without blocking the calling task.
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use ffi::*;
+use dmgr::OpenChannel;
+use ::{Error, Result};
+
+/// Maximum time the notification thread waits for a notification before checking for shutdown.
+const NOTIFICATION_TIMEOUT_MS: u32 = 50;
+
+/// Value received at the given device timestamp.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timestamped<T> {
+    /// 32-bit device local timestamp in microseconds
+    pub timestamp: u32,
+    pub value: T
+}
+
+/// State shared with the notification thread.
+struct Shared {
+    channel: OpenChannel,
+    stop: AtomicBool,
+    tx_waker: Mutex<Option<Waker>>
+}
+
+impl Shared {
+    /// Wake the task waiting for space in the transmit queue.
+    fn wake_tx(&self) {
+        if let Some(waker) = self.tx_waker.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Opened channel driven by a notification thread.
+///
+/// Received CAN messages are yielded by the [Stream] implementation, other events are dropped.
+/// The stream ends after yielding an error reported by the device. Messages are queued for
+/// transmission by the [Sink] implementation, which waits while the device transmit queue is full.
+///
+/// The notification thread is stopped and the channel closed when the value is dropped.
+///
+/// # Examples
+///
+/// ```edition2018
+/// use busmust::aio::AsyncDevice;
+/// use busmust::backend::VirtualBus;
+/// use busmust_sys::BMCanMessage;
+/// use futures::executor::block_on;
+/// use futures::{SinkExt, StreamExt};
+///
+/// let bus = VirtualBus::new(2);
+/// let mut devices = bus.enum_devices().unwrap();
+/// let mut tx = AsyncDevice::new(devices.next().unwrap().open_ex().unwrap());
+/// let mut rx = AsyncDevice::new(devices.next().unwrap().open_ex().unwrap());
+///
+/// block_on(async {
+///     tx.send(BMCanMessage::builder().sid(0x123).payload(vec![1, 2, 3]).build()).await.unwrap();
+///
+///     let frame = rx.next().await.unwrap().unwrap();
+///     println!("{}: {:X}", frame.timestamp, frame.value.id().raw());
+///     assert_eq!(frame.value.payload(), &[1, 2, 3]);
+/// });
+/// ```
+pub struct AsyncDevice {
+    shared: Arc<Shared>,
+    rx: UnboundedReceiver<Result<Timestamped<BMCanMessage>>>,
+    pending: Option<BMCanMessage>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl AsyncDevice {
+    /// Start the notification thread for the given channel.
+    pub fn new(channel: OpenChannel) -> AsyncDevice {
+        let shared = Arc::new(Shared {
+            channel,
+            stop: AtomicBool::new(false),
+            tx_waker: Mutex::new(None)
+        });
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || notification_thread(&thread_shared, &tx));
+
+        AsyncDevice { shared, rx, pending: None, thread: Some(thread) }
+    }
+
+    /// Get the underlying channel, i.e. to change its configuration.
+    pub fn channel(&self) -> &OpenChannel {
+        &self.shared.channel
+    }
+
+    fn stop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Try to hand the pending message to the device, registering the task to be woken if its queue is full.
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        let message = match self.pending {
+            Some(message) => message,
+            None => return Poll::Ready(Ok(()))
+        };
+
+        for attempt in 0..2 {
+            match self.shared.channel.write_can_message(message, Some(0)) {
+                Ok(_) => {
+                    self.pending = None;
+                    return Poll::Ready(Ok(()));
+                }
+                Err(ref e) if (e.is(BMStatus::XmtFull) || e.is(BMStatus::TransmitQueueFull)) && attempt == 0 => {
+                    // Retry once after registering, the queue may have drained in the meantime.
+                    *self.shared.tx_waker.lock().unwrap_or_else(|e| e.into_inner()) = Some(cx.waker().clone());
+                }
+                Err(ref e) if e.is(BMStatus::XmtFull) || e.is(BMStatus::TransmitQueueFull) => return Poll::Pending,
+                Err(e) => {
+                    self.pending = None;
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for AsyncDevice {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Stream for AsyncDevice {
+    type Item = Result<Timestamped<BMCanMessage>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.get_mut().rx.poll_recv(cx)
+    }
+}
+
+impl Sink<BMCanMessage> for AsyncDevice {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: BMCanMessage) -> Result<()> {
+        let this = self.get_mut();
+        this.pending = Some(item);
+
+        match this.shared.channel.write_can_message(item, Some(0)) {
+            Ok(_) => {
+                this.pending = None;
+                Ok(())
+            }
+            Err(ref e) if e.is(BMStatus::XmtFull) || e.is(BMStatus::TransmitQueueFull) => Ok(()),
+            Err(e) => {
+                this.pending = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+/// Wait for notifications until stopped, forwarding received CAN messages and waking the transmitting task.
+fn notification_thread(shared: &Shared, tx: &UnboundedSender<Result<Timestamped<BMCanMessage>>>) {
+    while !shared.stop.load(Ordering::SeqCst) {
+        if !shared.channel.wait_for_notification(Some(NOTIFICATION_TIMEOUT_MS)) {
+            shared.wake_tx();
+            continue;
+        }
+
+        loop {
+            let data = match shared.channel.read() {
+                Ok(data) => data,
+                Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) => break,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    shared.wake_tx();
+                    return;
+                }
+            };
+
+            if let Ok(BMDecodedData { timestamp, payload: BMPayload::Can(message), .. }) = data.decode() {
+                if tx.send(Ok(Timestamped { timestamp, value: message })).is_err() {
+                    return;
+                }
+            }
+        }
+
+        shared.wake_tx();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::mem;
+    use std::sync::atomic::{AtomicU32, AtomicUsize};
+    use std::time::{Duration, Instant};
+    use futures::executor::block_on;
+    use futures::task::{self, ArcWake};
+    use futures::StreamExt;
+    use backend::{can_message, Backend, Channel};
+    use dmgr::enum_devices_with;
+    use super::*;
+
+    /// State of a [ScriptedChannel], shared with the test.
+    #[derive(Default)]
+    struct Script {
+        /// Raw status returned by writes, zero to accept them
+        write_status: AtomicU32,
+        written: Mutex<Vec<BMCanMessage>>,
+        rx: Mutex<VecDeque<Result<BMData>>>,
+        waits: AtomicUsize,
+        closed: AtomicBool
+    }
+
+    /// Channel replaying a [Script].
+    struct ScriptedChannel(Arc<Script>);
+
+    impl Channel for ScriptedChannel {
+        fn close(&self) -> Result<()> {
+            self.0.closed.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn reset(&self) -> Result<()> { Ok(()) }
+        fn activate(&self) -> Result<()> { Ok(()) }
+        fn deactivate(&self) -> Result<()> { Ok(()) }
+        fn clear_buffer(&self) -> Result<()> { Ok(()) }
+        fn status(&self) -> Result<BMCanStatusInfo> { Ok(BMCanStatusInfo::default()) }
+        fn timestamp(&self) -> Result<u32> { Ok(0) }
+        fn set_bitrate(&self, _bitrate: &BMBitrate) -> Result<()> { Ok(()) }
+        fn set_terminal_resistor(&self, _value: BMTerminalResistor) -> Result<()> { Ok(()) }
+        fn set_can_mode(&self, _mode: BMCanMode) -> Result<()> { Ok(()) }
+        fn set_rx_filters(&self, _filters: &[BMRxFilter]) -> Result<()> { Ok(()) }
+
+        fn write(&self, data: &BMData, _timeout: i32) -> Result<u32> {
+            match BMStatus::from_raw(self.0.write_status.load(Ordering::SeqCst)) {
+                Some(BMStatus::Ok) | None => {
+                    self.0.written.lock().unwrap().push(can_message(data));
+                    Ok(0)
+                }
+                Some(status) => Err(Error::new(status))
+            }
+        }
+
+        fn read(&self) -> Result<BMData> {
+            self.0.rx.lock().unwrap().pop_front().unwrap_or(Err(Error::new(BMStatus::ReceiveBufferEmpty)))
+        }
+
+        fn wait_for_notification(&self, timeout: i32) -> bool {
+            self.0.waits.fetch_add(1, Ordering::SeqCst);
+
+            let deadline = Instant::now() + Duration::from_millis(timeout as u64);
+            while self.0.rx.lock().unwrap().is_empty() {
+                if Instant::now() >= deadline {
+                    return false;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            true
+        }
+    }
+
+    struct ScriptedBackend(Arc<Script>);
+
+    impl Backend for ScriptedBackend {
+        fn enumerate(&self) -> Result<Vec<BMChannelInfo>> {
+            Ok(vec![unsafe { mem::zeroed() }])
+        }
+
+        fn open(&self, _info: &BMChannelInfo) -> Result<Box<dyn Channel>> {
+            Ok(Box::new(ScriptedChannel(self.0.clone())))
+        }
+
+        fn open_ex(&self, info: &BMChannelInfo, _mode: BMCanMode, _term: BMTerminalResistor,
+                   _bitrate: &BMBitrate, _filters: &[BMRxFilter]) -> Result<Box<dyn Channel>> {
+            self.open(info)
+        }
+    }
+
+    /// Waker counting its wake-ups.
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl ArcWake for CountingWaker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn open(script: &Arc<Script>) -> AsyncDevice {
+        let device = enum_devices_with(Arc::new(ScriptedBackend(script.clone()))).unwrap().next().unwrap();
+        AsyncDevice::new(device.open().unwrap())
+    }
+
+    fn can_data(id: u16, timestamp: u32) -> BMData {
+        let mut data = BMData::builder().can_message(BMCanMessage::builder().sid(id).payload(vec![1, 2]).build()).build();
+        data.timestamp = timestamp;
+        data
+    }
+
+    fn wait_until<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn sink_waits_while_queue_full() {
+        for &status in [BMStatus::XmtFull, BMStatus::TransmitQueueFull].iter() {
+            let script = Arc::new(Script::default());
+            let mut device = open(&script);
+
+            let counter = Arc::new(CountingWaker::default());
+            let waker = task::waker(counter.clone());
+            let mut cx = Context::from_waker(&waker);
+
+            let message = BMCanMessage::builder().sid(0x123).payload(vec![1]).build();
+            script.write_status.store(status as u32, Ordering::SeqCst);
+
+            assert!(matches!(Pin::new(&mut device).poll_ready(&mut cx), Poll::Ready(Ok(()))));
+            assert!(Pin::new(&mut device).start_send(message).is_ok());
+            assert!(matches!(Pin::new(&mut device).poll_flush(&mut cx), Poll::Pending));
+            assert!(matches!(Pin::new(&mut device).poll_ready(&mut cx), Poll::Pending));
+            assert!(script.written.lock().unwrap().is_empty());
+
+            // The notification thread wakes the task once the device may have drained its queue.
+            let woken = counter.0.load(Ordering::SeqCst);
+            wait_until(|| counter.0.load(Ordering::SeqCst) > woken);
+
+            script.write_status.store(0, Ordering::SeqCst);
+            assert!(matches!(Pin::new(&mut device).poll_flush(&mut cx), Poll::Ready(Ok(()))));
+            assert_eq!(script.written.lock().unwrap().len(), 1);
+            assert!(matches!(Pin::new(&mut device).poll_close(&mut cx), Poll::Ready(Ok(()))));
+        }
+    }
+
+    #[test]
+    fn sink_reports_other_errors() {
+        let script = Arc::new(Script::default());
+        let mut device = open(&script);
+        let message = BMCanMessage::builder().sid(0x123).build();
+
+        script.write_status.store(BMStatus::BusOff as u32, Ordering::SeqCst);
+        assert!(Pin::new(&mut device).start_send(message).err().unwrap().is(BMStatus::BusOff));
+
+        // Errors are reported once, the message is not retried.
+        let waker = task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(matches!(Pin::new(&mut device).poll_flush(&mut cx), Poll::Ready(Ok(()))));
+
+        // A message queued while the queue is full fails once the device reports another error.
+        script.write_status.store(BMStatus::XmtFull as u32, Ordering::SeqCst);
+        assert!(Pin::new(&mut device).start_send(message).is_ok());
+        script.write_status.store(BMStatus::BusOff as u32, Ordering::SeqCst);
+        match Pin::new(&mut device).poll_flush(&mut cx) {
+            Poll::Ready(Err(e)) => assert!(e.is(BMStatus::BusOff)),
+            _ => panic!("flush should fail")
+        }
+        assert!(matches!(Pin::new(&mut device).poll_flush(&mut cx), Poll::Ready(Ok(()))));
+    }
+
+    #[test]
+    fn stream_ends_after_device_error() {
+        let script = Arc::new(Script::default());
+        let event = BMData::builder().kind(BMDataType::Ack).build();
+        script.rx.lock().unwrap().extend(vec![
+            Ok(can_data(0x100, 10)),
+            Ok(event),
+            Ok(can_data(0x200, 20)),
+            Err(Error::new(BMStatus::BusOff)),
+            Ok(can_data(0x300, 30))
+        ]);
+        let mut device = open(&script);
+
+        let frame = block_on(device.next()).unwrap().unwrap();
+        assert_eq!((frame.timestamp, frame.value.id()), (10, BMCanId::Standard(0x100)));
+        assert_eq!(frame.value.payload(), &[1, 2]);
+
+        // Other events are dropped.
+        let frame = block_on(device.next()).unwrap().unwrap();
+        assert_eq!((frame.timestamp, frame.value.id()), (20, BMCanId::Standard(0x200)));
+
+        assert!(block_on(device.next()).unwrap().err().unwrap().is(BMStatus::BusOff));
+        assert!(block_on(device.next()).is_none());
+
+        // Nothing is read after the error.
+        assert_eq!(script.rx.lock().unwrap().len(), 1);
+        assert!(device.thread.as_ref().unwrap().is_finished());
+    }
+
+    #[test]
+    fn drop_stops_notification_thread() {
+        let script = Arc::new(Script::default());
+        let device = open(&script);
+        wait_until(|| script.waits.load(Ordering::SeqCst) > 0);
+
+        let start = Instant::now();
+        drop(device);
+        assert!(start.elapsed() < Duration::from_millis(NOTIFICATION_TIMEOUT_MS as u64 * 4));
+        assert!(script.closed.load(Ordering::SeqCst));
+
+        // The thread has been joined, the channel is no longer waited for.
+        let waits = script.waits.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(NOTIFICATION_TIMEOUT_MS as u64 * 2));
+        assert_eq!(script.waits.load(Ordering::SeqCst), waits);
+        assert_eq!(Arc::strong_count(&script), 1);
+    }
+}
@@ -0,0 +1,276 @@
+//! Backends implementing the device channel operations used by [super::dmgr::OpenChannel].
+//!
+//! [Native] forwards every call to the Busmust library, [VirtualBus] is a pure Rust in-memory bus
+//! which allows testing code written on top of [super::dmgr::OpenChannel] without any hardware.
+
+use std::ffi::c_void;
+use std::ptr;
+use ffi::*;
+use ::{Error, Result};
+
+mod native;
+mod virtual_bus;
+
+pub use self::native::Native;
+pub use self::virtual_bus::VirtualBus;
+
+/// Source of device channels, see [super::dmgr::enum_devices_with].
+pub trait Backend: Send + Sync {
+    /// Enumerate all available device channels.
+    fn enumerate(&self) -> Result<Vec<BMChannelInfo>>;
+
+    /// Open the given channel with default parameters.
+    fn open(&self, info: &BMChannelInfo) -> Result<Box<dyn Channel>>;
+
+    /// Open the given channel using the given configuration.
+    fn open_ex(&self, info: &BMChannelInfo, mode: BMCanMode, term: BMTerminalResistor,
+               bitrate: &BMBitrate, filters: &[BMRxFilter]) -> Result<Box<dyn Channel>>;
+}
+
+/// Operations on an opened device channel, mirroring the channel functions of the Busmust library.
+///
+/// All timeouts are given in `ms`, use `-1` to wait indefinitely or `0` to operate asynchronously.
+pub trait Channel: Send + Sync {
+    /// Close the channel, see [BM_Close].
+    fn close(&self) -> Result<()>;
+
+    /// Reset the channel keeping its configuration, see [BM_Reset].
+    fn reset(&self) -> Result<()>;
+
+    /// Go on bus, see [BM_Activate].
+    fn activate(&self) -> Result<()>;
+
+    /// Go off bus, see [BM_Deactivate].
+    fn deactivate(&self) -> Result<()>;
+
+    /// Clear TX & RX message buffers, see [BM_ClearBuffer].
+    fn clear_buffer(&self) -> Result<()>;
+
+    /// Get current CAN status, see [BM_GetStatus].
+    fn status(&self) -> Result<BMCanStatusInfo>;
+
+    /// Get current device timestamp in microseconds, see [BM_GetTimestamp].
+    fn timestamp(&self) -> Result<u32>;
+
+    /// See [BM_SetBitrate].
+    fn set_bitrate(&self, bitrate: &BMBitrate) -> Result<()>;
+
+    /// See [BM_SetTerminalRegister].
+    fn set_terminal_resistor(&self, value: BMTerminalResistor) -> Result<()>;
+
+    /// See [BM_SetCanMode].
+    fn set_can_mode(&self, mode: BMCanMode) -> Result<()>;
+
+    /// See [BM_SetRxFilters].
+    fn set_rx_filters(&self, filters: &[BMRxFilter]) -> Result<()>;
+
+    /// See [BM_SetTxTasks], unsupported unless implemented by the backend.
+    fn set_tx_tasks(&self, _tasks: &[BMTxTask]) -> Result<()> {
+        Err(Error::new(BMStatus::InvalidOperation))
+    }
+
+    /// Write a message/event, returns its transmit timestamp, see [BM_Write].
+    fn write(&self, data: &BMData, timeout: i32) -> Result<u32>;
+
+    /// Write multiple messages/events, filling `timestamps` and returning the number of written messages,
+    /// which is less than requested if the bus timed out after writing some of them, see [BM_WriteMultiple].
+    fn write_multiple(&self, data: &[BMData], timeout: i32, timestamps: &mut [u32]) -> Result<usize> {
+        for (index, (data, timestamp)) in data.iter().zip(timestamps.iter_mut()).enumerate() {
+            match self.write(data, timeout) {
+                Ok(value) => *timestamp = value,
+                Err(ref e) if e.is(BMStatus::BusTimeout) && index > 0 => return Ok(index),
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(data.len().min(timestamps.len()))
+    }
+
+    /// Write a CAN message, returns its transmit timestamp, see [BM_WriteCanMessage].
+    fn write_can(&self, message: &BMCanMessage, timeout: i32) -> Result<u32> {
+        self.write(&BMData::builder().can_message(*message).build(), timeout)
+    }
+
+    /// Write multiple CAN messages, see [BM_WriteMultipleCanMessage].
+    fn write_can_multiple(&self, messages: &[BMCanMessage], timeout: i32, timestamps: &mut [u32]) -> Result<usize> {
+        let data: Vec<BMData> = messages.iter().map(|m| BMData::builder().can_message(*m).build()).collect();
+        self.write_multiple(&data, timeout, timestamps)
+    }
+
+    /// Read a message/event without blocking, see [BM_Read].
+    fn read(&self) -> Result<BMData>;
+
+    /// Read up to `data.len()` messages/events, returns the number of messages read, see [BM_ReadMultiple].
+    fn read_multiple(&self, data: &mut [BMData], timeout: i32) -> Result<usize> {
+        let mut count = 0;
+
+        while count < data.len() {
+            match self.read() {
+                Ok(message) => {
+                    data[count] = message;
+                    count += 1;
+                }
+                Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) && timeout != 0 && self.wait_for_notification(timeout) => {}
+                Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) && count > 0 => break,
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Read a CAN message without blocking, skipping other events, see [BM_ReadCanMessage].
+    fn read_can(&self) -> Result<BMCanMessage> {
+        loop {
+            let data = self.read()?;
+            if data.header.kind() == BMDataType::Can as u8 {
+                return Ok(can_message(&data));
+            }
+        }
+    }
+
+    /// Read up to `messages.len()` CAN messages, skipping other events, filling `channels` and `timestamps`
+    /// with the source channel and receive timestamp of each message, see [BM_ReadMultipleCanMessage].
+    ///
+    /// All slices must have the same length, returns the number of messages read.
+    fn read_can_multiple(&self, messages: &mut [BMCanMessage], channels: &mut [u32], timestamps: &mut [u32],
+                         timeout: i32) -> Result<usize> {
+        let len = messages.len().min(channels.len()).min(timestamps.len());
+        let mut count = 0;
+
+        while count < len {
+            match self.read() {
+                Ok(data) => {
+                    if data.header.kind() == BMDataType::Can as u8 {
+                        messages[count] = can_message(&data);
+                        channels[count] = data.header.schn() as u32;
+                        timestamps[count] = data.timestamp;
+                        count += 1;
+                    }
+                }
+                Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) && timeout != 0 && self.wait_for_notification(timeout) => {}
+                Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) && count > 0 => break,
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Write a data block using the device ISO-TP implementation, see [BM_WriteIsotp].
+    fn write_isotp(&self, _data: &[u8], _timeout: i32, _config: &BMIsotpConfig) -> Result<()> {
+        Err(Error::new(BMStatus::InvalidOperation))
+    }
+
+    /// Read a data block using the device ISO-TP implementation, returns its length, see [BM_ReadIsotp].
+    fn read_isotp(&self, _data: &mut [u8], _timeout: i32, _config: &BMIsotpConfig) -> Result<usize> {
+        Err(Error::new(BMStatus::InvalidOperation))
+    }
+
+    /// Wait at most `timeout` ms until a message/event is available, see [BM_WaitForNotifications].
+    fn wait_for_notification(&self, timeout: i32) -> bool;
+
+    /// Platform-independent notification handle which could be passed to [BM_WaitForNotifications] together
+    /// with handles of other channels, `None` unless the channel is opened by the Busmust library.
+    fn notification_handle(&self) -> Option<*const c_void> {
+        None
+    }
+}
+
+/// Get the CAN message carried by the payload of a [BMDataType::Can] data.
+pub(crate) fn can_message(data: &BMData) -> BMCanMessage {
+    unsafe { ptr::read_unaligned(data.payload.as_ptr() as *const BMCanMessage) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use super::*;
+
+    /// Channel reading queued data and acknowledging a limited number of writes.
+    struct ScriptedChannel {
+        rx: Mutex<VecDeque<BMData>>,
+        acks: Mutex<usize>
+    }
+
+    impl ScriptedChannel {
+        fn new(rx: Vec<BMData>, acks: usize) -> ScriptedChannel {
+            ScriptedChannel { rx: Mutex::new(rx.into()), acks: Mutex::new(acks) }
+        }
+    }
+
+    impl Channel for ScriptedChannel {
+        fn close(&self) -> Result<()> { Ok(()) }
+        fn reset(&self) -> Result<()> { Ok(()) }
+        fn activate(&self) -> Result<()> { Ok(()) }
+        fn deactivate(&self) -> Result<()> { Ok(()) }
+        fn clear_buffer(&self) -> Result<()> { Ok(()) }
+        fn status(&self) -> Result<BMCanStatusInfo> { Ok(BMCanStatusInfo::default()) }
+        fn timestamp(&self) -> Result<u32> { Ok(0) }
+        fn set_bitrate(&self, _bitrate: &BMBitrate) -> Result<()> { Ok(()) }
+        fn set_terminal_resistor(&self, _value: BMTerminalResistor) -> Result<()> { Ok(()) }
+        fn set_can_mode(&self, _mode: BMCanMode) -> Result<()> { Ok(()) }
+        fn set_rx_filters(&self, _filters: &[BMRxFilter]) -> Result<()> { Ok(()) }
+
+        fn write(&self, _data: &BMData, _timeout: i32) -> Result<u32> {
+            let mut acks = self.acks.lock().unwrap();
+            if *acks == 0 {
+                return Err(Error::new(BMStatus::BusTimeout));
+            }
+            *acks -= 1;
+            Ok(100 + *acks as u32)
+        }
+
+        fn read(&self) -> Result<BMData> {
+            self.rx.lock().unwrap().pop_front().ok_or(Error::new(BMStatus::ReceiveBufferEmpty))
+        }
+
+        fn wait_for_notification(&self, _timeout: i32) -> bool {
+            !self.rx.lock().unwrap().is_empty()
+        }
+    }
+
+    fn can_data(id: u16, channel: u8, timestamp: u32) -> BMData {
+        let message = BMCanMessage::builder().sid(id).payload(vec![id as u8]).build();
+        let mut data = BMData::builder().can_message(message).src_chn(channel).build();
+        data.timestamp = timestamp;
+        data
+    }
+
+    #[test]
+    fn read_multiple_keeps_partial_batch() {
+        let channel = ScriptedChannel::new(vec![can_data(1, 0, 10), can_data(2, 0, 20), can_data(3, 0, 30)], 0);
+        let mut data = [BMData::builder().build(); 10];
+
+        assert_eq!(channel.read_multiple(&mut data, 0).unwrap(), 3);
+        assert_eq!(data[2].timestamp, 30);
+        assert!(channel.read_multiple(&mut data, 10).unwrap_err().is(BMStatus::ReceiveBufferEmpty));
+    }
+
+    #[test]
+    fn read_can_multiple_skips_events() {
+        let event = BMData::builder().kind(BMDataType::Ack).build();
+        let channel = ScriptedChannel::new(vec![can_data(1, 2, 10), event, can_data(2, 3, 20)], 0);
+
+        let mut messages = [BMCanMessage::builder().build(); 4];
+        let (mut channels, mut timestamps) = ([0; 4], [0; 4]);
+        let count = channel.read_can_multiple(&mut messages, &mut channels, &mut timestamps, 100).unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(messages[1].id(), BMCanId::Standard(2));
+        assert_eq!(&channels[..2], &[2, 3]);
+        assert_eq!(&timestamps[..2], &[10, 20]);
+    }
+
+    #[test]
+    fn write_multiple_keeps_partial_batch() {
+        let data = [can_data(1, 0, 0); 3];
+        let mut timestamps = [0; 3];
+
+        let channel = ScriptedChannel::new(vec![], 2);
+        assert_eq!(channel.write_multiple(&data, 100, &mut timestamps).unwrap(), 2);
+        assert_eq!(&timestamps[..2], &[101, 100]);
+
+        assert!(channel.write_multiple(&data, 100, &mut timestamps).unwrap_err().is(BMStatus::BusTimeout));
+    }
+}
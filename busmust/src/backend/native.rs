@@ -0,0 +1,352 @@
+use std::ffi::c_void;
+use std::ptr;
+use std::mem::{self, MaybeUninit};
+use std::os::raw::c_int;
+use call::cvt_r;
+use ffi::*;
+use ::{Error, Result};
+use dmgr::BusMust;
+use super::{Backend, Channel};
+
+/// Initial number of channels passed to [BM_Enumerate].
+const ENUMERATE_CAPACITY: usize = 64;
+
+/// Maximum number of channels passed to [BM_Enumerate].
+const ENUMERATE_MAX_CAPACITY: usize = 4096;
+
+/// Backend using the Busmust library, i.e. real devices.
+/// The library is kept initialized as long as the backend and channels opened by it are alive.
+#[derive(Clone)]
+pub struct Native(BusMust);
+
+impl Native {
+    /// Create a backend using the given library guard.
+    pub fn new(library: BusMust) -> Native {
+        Native(library)
+    }
+}
+
+impl Backend for Native {
+    fn enumerate(&self) -> Result<Vec<BMChannelInfo>> {
+        enumerate_with(|infos, count| unsafe { cvt_r(BM_Enumerate(infos.as_mut_ptr(), count)) })
+    }
+
+    fn open(&self, info: &BMChannelInfo) -> Result<Box<dyn Channel>> {
+        let handle = unsafe { BM_OpenCan(info.port) };
+        if handle.is_null() {
+            return Err(Error::new(BMStatus::InvalidOperation));
+        }
+
+        NativeChannel::from_handle(handle, self.0.clone())
+    }
+
+    fn open_ex(&self, info: &BMChannelInfo, mode: BMCanMode, term: BMTerminalResistor,
+               bitrate: &BMBitrate, filters: &[BMRxFilter]) -> Result<Box<dyn Channel>> {
+        let mut handle: *mut c_void = ptr::null_mut();
+
+        unsafe {
+            cvt_r(BM_OpenEx(
+                &mut handle,
+                info,
+                mode,
+                term,
+                bitrate,
+                if filters.is_empty() { ptr::null() } else { filters.as_ptr() },
+                filters.len() as c_int))?;
+        }
+
+        NativeChannel::from_handle(handle.cast_const(), self.0.clone())
+    }
+}
+
+/// Convert the status of a batch read/write, which reports the number of messages processed in `n_messages`.
+/// The library returns the `partial` status if only some messages are processed, those are kept.
+fn cvt_batch(status: u32, n_messages: c_int, partial: BMStatus) -> Result<usize> {
+    match cvt_r(status) {
+        Err(ref e) if e.is(partial) && n_messages > 0 => Ok(n_messages as usize),
+        result => result.map(|_| n_messages.max(0) as usize)
+    }
+}
+
+/// Channel opened by the Busmust library, holding its channel and notification handles.
+struct NativeChannel {
+    handle: *const c_void,
+    notification: *const c_void,
+    _library: BusMust
+}
+
+// The library handles are plain identifiers which could be used from any thread.
+unsafe impl Send for NativeChannel {}
+unsafe impl Sync for NativeChannel {}
+
+impl NativeChannel {
+    /// Wrap an opened channel handle, getting its platform-independent notification handle.
+    fn from_handle(handle: *const c_void, library: BusMust) -> Result<Box<dyn Channel>> {
+        let mut notification: *mut c_void = ptr::null_mut();
+
+        unsafe {
+            cvt_r(BM_GetNotification(handle, &mut notification))?;
+        }
+
+        Ok(Box::new(NativeChannel { handle, notification: notification.cast_const(), _library: library }))
+    }
+}
+
+impl Channel for NativeChannel {
+    fn close(&self) -> Result<()> {
+        unsafe { cvt_r(BM_Close(self.handle)) }
+    }
+
+    fn reset(&self) -> Result<()> {
+        unsafe { cvt_r(BM_Reset(self.handle)) }
+    }
+
+    fn activate(&self) -> Result<()> {
+        unsafe { cvt_r(BM_Activate(self.handle)) }
+    }
+
+    fn deactivate(&self) -> Result<()> {
+        unsafe { cvt_r(BM_Deactivate(self.handle)) }
+    }
+
+    fn clear_buffer(&self) -> Result<()> {
+        unsafe { cvt_r(BM_ClearBuffer(self.handle)) }
+    }
+
+    fn status(&self) -> Result<BMCanStatusInfo> {
+        unsafe {
+            let mut status_info = MaybeUninit::<BMCanStatusInfo>::uninit();
+            cvt_r(BM_GetStatus(self.handle, status_info.as_mut_ptr()))?;
+            Ok(status_info.assume_init())
+        }
+    }
+
+    fn timestamp(&self) -> Result<u32> {
+        unsafe {
+            let mut timestamp = 0;
+            cvt_r(BM_GetTimestamp(self.handle, &mut timestamp))?;
+            Ok(timestamp as u32)
+        }
+    }
+
+    fn set_bitrate(&self, bitrate: &BMBitrate) -> Result<()> {
+        unsafe { cvt_r(BM_SetBitrate(self.handle, bitrate)) }
+    }
+
+    fn set_terminal_resistor(&self, value: BMTerminalResistor) -> Result<()> {
+        unsafe { cvt_r(BM_SetTerminalRegister(self.handle, value)) }
+    }
+
+    fn set_can_mode(&self, mode: BMCanMode) -> Result<()> {
+        unsafe { cvt_r(BM_SetCanMode(self.handle, mode)) }
+    }
+
+    fn set_rx_filters(&self, filters: &[BMRxFilter]) -> Result<()> {
+        unsafe { cvt_r(BM_SetRxFilters(self.handle, filters.as_ptr(), filters.len() as c_int)) }
+    }
+
+    fn set_tx_tasks(&self, tasks: &[BMTxTask]) -> Result<()> {
+        unsafe { cvt_r(BM_SetTxTasks(self.handle, tasks.as_ptr(), tasks.len() as c_int)) }
+    }
+
+    fn write(&self, data: &BMData, timeout: i32) -> Result<u32> {
+        unsafe {
+            let mut timestamp = 0;
+            cvt_r(BM_Write(self.handle, data, timeout, &mut timestamp))?;
+            Ok(timestamp as u32)
+        }
+    }
+
+    fn write_multiple(&self, data: &[BMData], timeout: i32, timestamps: &mut [u32]) -> Result<usize> {
+        let mut n_messages = data.len().min(timestamps.len()) as c_int;
+
+        let status = unsafe {
+            BM_WriteMultiple(
+                self.handle,
+                data.as_ptr(),
+                &mut n_messages,
+                timeout,
+                timestamps.as_mut_ptr() as *mut c_int
+            )
+        };
+
+        cvt_batch(status, n_messages, BMStatus::BusTimeout)
+    }
+
+    fn write_can(&self, message: &BMCanMessage, timeout: i32) -> Result<u32> {
+        unsafe {
+            let mut timestamp = 0;
+            cvt_r(BM_WriteCanMessage(self.handle, message, 0, timeout, &mut timestamp))?;
+            Ok(timestamp as u32)
+        }
+    }
+
+    fn write_can_multiple(&self, messages: &[BMCanMessage], timeout: i32, timestamps: &mut [u32]) -> Result<usize> {
+        let mut n_messages = messages.len().min(timestamps.len()) as c_int;
+
+        let status = unsafe {
+            BM_WriteMultipleCanMessage(
+                self.handle,
+                messages.as_ptr(),
+                &mut n_messages,
+                0,
+                timeout,
+                timestamps.as_mut_ptr() as *mut c_int
+            )
+        };
+
+        cvt_batch(status, n_messages, BMStatus::BusTimeout)
+    }
+
+    fn read(&self) -> Result<BMData> {
+        unsafe {
+            let mut data = MaybeUninit::<BMData>::uninit();
+            cvt_r(BM_Read(self.handle, data.as_mut_ptr()))?;
+            Ok(data.assume_init())
+        }
+    }
+
+    fn read_multiple(&self, data: &mut [BMData], timeout: i32) -> Result<usize> {
+        let mut n_messages = data.len() as c_int;
+
+        let status = unsafe { BM_ReadMultiple(self.handle, data.as_mut_ptr(), &mut n_messages, timeout) };
+
+        cvt_batch(status, n_messages, BMStatus::ReceiveBufferEmpty)
+    }
+
+    fn read_can(&self) -> Result<BMCanMessage> {
+        unsafe {
+            let mut message = MaybeUninit::<BMCanMessage>::uninit();
+            cvt_r(BM_ReadCanMessage(self.handle, message.as_mut_ptr(), ptr::null_mut(), ptr::null_mut()))?;
+            Ok(message.assume_init())
+        }
+    }
+
+    fn read_can_multiple(&self, messages: &mut [BMCanMessage], channels: &mut [u32], timestamps: &mut [u32],
+                         timeout: i32) -> Result<usize> {
+        let mut n_messages = messages.len().min(channels.len()).min(timestamps.len()) as c_int;
+
+        let status = unsafe {
+            BM_ReadMultipleCanMessage(
+                self.handle,
+                messages.as_mut_ptr(),
+                &mut n_messages,
+                timeout,
+                channels.as_mut_ptr() as *mut c_int,
+                timestamps.as_mut_ptr() as *mut c_int
+            )
+        };
+
+        cvt_batch(status, n_messages, BMStatus::ReceiveBufferEmpty)
+    }
+
+    fn write_isotp(&self, data: &[u8], timeout: i32, config: &BMIsotpConfig) -> Result<()> {
+        unsafe { cvt_r(BM_WriteIsotp(self.handle, data.as_ptr(), data.len() as c_int, timeout, config)) }
+    }
+
+    fn read_isotp(&self, data: &mut [u8], timeout: i32, config: &BMIsotpConfig) -> Result<usize> {
+        let mut n_bytes = data.len() as c_int;
+
+        unsafe {
+            cvt_r(BM_ReadIsotp(self.handle, data.as_mut_ptr(), &mut n_bytes, timeout, config))?;
+        }
+
+        Ok(n_bytes as usize)
+    }
+
+    fn wait_for_notification(&self, timeout: i32) -> bool {
+        unsafe { BM_WaitForNotifications(&self.notification, 1, timeout) >= 0 }
+    }
+
+    fn notification_handle(&self) -> Option<*const c_void> {
+        Some(self.notification)
+    }
+}
+
+/// Enumerate channels using [BM_Enumerate] or an equivalent function, which fills the buffer
+/// and replaces the buffer length with the number of channels written.
+fn enumerate_with<F>(mut enumerate: F) -> Result<Vec<BMChannelInfo>>
+    where F: FnMut(&mut [BMChannelInfo], &mut c_int) -> Result<()>
+{
+    let mut capacity = ENUMERATE_CAPACITY;
+
+    loop {
+        let mut infos: Vec<BMChannelInfo> = vec![unsafe { mem::zeroed() }; capacity];
+        let mut count = capacity as c_int;
+        enumerate(&mut infos, &mut count)?;
+
+        // The buffer may have been too small, retry with a larger one unless the limit is reached.
+        let count = count.max(0) as usize;
+        if count < capacity || capacity >= ENUMERATE_MAX_CAPACITY {
+            infos.truncate(count);
+            return Ok(infos);
+        }
+
+        capacity = (capacity * 2).max(count).min(ENUMERATE_MAX_CAPACITY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_status() {
+        assert_eq!(cvt_batch(BMStatus::Ok as u32, 4, BMStatus::ReceiveBufferEmpty).unwrap(), 4);
+        assert_eq!(cvt_batch(BMStatus::ReceiveBufferEmpty as u32, 3, BMStatus::ReceiveBufferEmpty).unwrap(), 3);
+        assert_eq!(cvt_batch(BMStatus::BusTimeout as u32, 1, BMStatus::BusTimeout).unwrap(), 1);
+
+        let empty = cvt_batch(BMStatus::ReceiveBufferEmpty as u32, 0, BMStatus::ReceiveBufferEmpty);
+        assert!(empty.unwrap_err().is(BMStatus::ReceiveBufferEmpty));
+
+        let error = cvt_batch(BMStatus::BusOff as u32, 3, BMStatus::ReceiveBufferEmpty);
+        assert!(error.unwrap_err().is(BMStatus::BusOff));
+    }
+
+    /// Enumerate `channels` fake channels with ports 0, 1, ..., returning the channels and the buffer sizes passed.
+    fn enumerate_fake(channels: usize) -> (Vec<BMChannelInfo>, Vec<usize>) {
+        let mut capacities = Vec::new();
+        let infos = enumerate_with(|infos, count| {
+            capacities.push(*count as usize);
+
+            let written = channels.min(infos.len());
+            for (port, info) in infos[..written].iter_mut().enumerate() {
+                info.port = port as u16;
+            }
+            *count = written as c_int;
+            Ok(())
+        }).unwrap();
+
+        (infos, capacities)
+    }
+
+    #[test]
+    fn enumerate_grows_buffer() {
+        let (infos, capacities) = enumerate_fake(5);
+        assert_eq!(infos.len(), 5);
+        assert_eq!(capacities, vec![64]);
+
+        // A completely filled buffer might have been too small.
+        let (infos, capacities) = enumerate_fake(64);
+        assert_eq!(infos.len(), 64);
+        assert_eq!(capacities, vec![64, 128]);
+
+        let (infos, capacities) = enumerate_fake(300);
+        assert_eq!(infos.len(), 300);
+        assert_eq!(capacities, vec![64, 128, 256, 512]);
+        assert!(infos.iter().enumerate().all(|(port, info)| info.port as usize == port));
+
+        let (infos, capacities) = enumerate_fake(10_000);
+        assert_eq!(infos.len(), ENUMERATE_MAX_CAPACITY);
+        assert_eq!(capacities, vec![64, 128, 256, 512, 1024, 2048, 4096]);
+    }
+
+    #[test]
+    fn enumerate_errors() {
+        let error = enumerate_with(|_, _| Err(Error::new(BMStatus::NoDriver))).err().unwrap();
+        assert!(error.is(BMStatus::NoDriver));
+
+        let infos = enumerate_with(|_, count| { *count = -1; Ok(()) });
+        assert_eq!(infos.map(|infos| infos.len()).unwrap(), 0);
+    }
+}
@@ -0,0 +1,651 @@
+use std::collections::VecDeque;
+use std::os::raw::c_char;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use ffi::*;
+use ::{Error, Result};
+use dmgr::{enum_devices_with, Devices};
+use super::{can_message, Backend, Channel};
+
+/// Nominal and data bitrate (kbps) of a channel opened without explicit bitrate.
+const DEFAULT_BITRATE: u16 = 500;
+
+/// Pure Rust in-memory CAN bus with a number of virtual channels, enumerated as ports of a single device.
+///
+/// Every frame written to an opened channel is received by all other opened channels of the same bus,
+/// timestamped in microseconds since the creation of the bus. Frames are transmitted one at a time,
+/// so all channels receive them in the same order. The virtual bus honors:
+///
+/// * [BMCanMode]: [BMCanMode::ListenOnly] channels receive without acknowledging and cannot transmit,
+///   [BMCanMode::InternalLoopback] channels only receive their own frames, [BMCanMode::ExternalLoopback]
+///   channels additionally receive their own frames, [BMCanMode::BufOff] channels are off the bus and
+///   [BMCanMode::Classic] channels reject CAN-FD frames.
+/// * Bitrates: channels using a different bitrate than the transmitter do not receive the frame and
+///   count an RX error instead.
+/// * Acknowledgement: frames not acknowledged by any other channel fail with [BMStatus::BusTimeout]
+///   and increase the TX error counter.
+/// * RX filters, see [BMRxFilter].
+///
+/// Hardware TX tasks and the device ISO-TP implementation are not supported, use [super::super::isotp::IsoTpSocket]
+/// for ISO-TP instead.
+///
+/// # Examples
+///
+/// ```
+/// extern crate busmust;
+/// extern crate busmust_sys;
+///
+/// use busmust::backend::VirtualBus;
+/// use busmust_sys::BMCanMessage;
+///
+/// let bus = VirtualBus::new(2);
+/// let mut devices = bus.enum_devices().unwrap();
+/// let tx = devices.next().unwrap().open_ex().unwrap();
+/// let rx = devices.next().unwrap().open_ex().unwrap();
+///
+/// let msg = BMCanMessage::builder().sid(0x123).payload(vec![1, 2, 3]).build();
+/// tx.write_can_message(msg, Some(100)).unwrap();
+/// assert!(rx.wait_for_notification(Some(100)));
+/// assert_eq!(rx.read_can_message().unwrap().unwrap().payload(), &[1, 2, 3]);
+/// ```
+#[derive(Clone)]
+pub struct VirtualBus(Arc<Bus>);
+
+struct Bus {
+    start: Instant,
+    infos: Vec<BMChannelInfo>,
+    nodes: Mutex<Vec<Option<Arc<Node>>>>,
+    /// Held while a frame is delivered, like arbitration on a real bus
+    transmit: Mutex<()>
+}
+
+impl VirtualBus {
+    /// Create a bus with `channels` virtual channels, at most 16.
+    pub fn new(channels: u16) -> VirtualBus {
+        VirtualBus::with_channels((0..channels.min(16)).map(channel_info).collect())
+    }
+
+    /// Create a bus enumerating the given channels, i.e. to emulate several (multi-port) devices with
+    /// different capabilities. Channels are identified by serial number and port.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::backend::{Backend, VirtualBus};
+    /// use busmust_sys::BMCapability;
+    ///
+    /// // A second single-port device without CAN FD.
+    /// let mut infos = VirtualBus::new(2).enumerate().unwrap();
+    /// infos[1].sn[..10].copy_from_slice(b"VIRTUAL001");
+    /// infos[1].port = 0;
+    /// infos[1].cap = BMCapability::CAN.bits();
+    ///
+    /// let bus = VirtualBus::with_channels(infos);
+    /// let devices: Vec<_> = bus.enum_devices().unwrap().collect();
+    /// assert_eq!(devices[1].caps(), BMCapability::CAN);
+    /// assert!(devices[1].open_ex().is_ok());
+    /// ```
+    pub fn with_channels(infos: Vec<BMChannelInfo>) -> VirtualBus {
+        let channels = infos.len();
+
+        VirtualBus(Arc::new(Bus {
+            start: Instant::now(),
+            infos,
+            nodes: Mutex::new(vec![None; channels]),
+            transmit: Mutex::new(())
+        }))
+    }
+
+    /// Enumerate the virtual channels of the bus.
+    pub fn enum_devices(&self) -> Result<Devices> {
+        enum_devices_with(Arc::new(self.clone()))
+    }
+
+    fn attach(&self, info: &BMChannelInfo, mode: BMCanMode, bitrate: Option<&BMBitrate>,
+              filters: &[BMRxFilter]) -> Result<Box<dyn Channel>> {
+        let mut nodes = self.0.nodes.lock().unwrap();
+        let index = match self.0.infos.iter().position(|i| i.sn == info.sn && i.port == info.port) {
+            Some(index) => index,
+            None => return Err(Error::new(BMStatus::HardwareError))
+        };
+
+        if nodes[index].is_some() {
+            return Err(Error::new(BMStatus::HardwareInUse));
+        }
+
+        let node = Arc::new(Node {
+            index,
+            port: info.port,
+            state: Mutex::new(NodeState {
+                mode,
+                active: true,
+                n_bitrate: bitrate.map_or(DEFAULT_BITRATE, |b| b.n_bitrate),
+                d_bitrate: bitrate.map_or(DEFAULT_BITRATE, |b| b.d_bitrate),
+                filters: filters.to_vec(),
+                rx: VecDeque::new(),
+                tx_errors: 0,
+                rx_errors: 0
+            }),
+            notify: Condvar::new()
+        });
+        nodes[index] = Some(node.clone());
+
+        Ok(Box::new(VirtualChannel { bus: self.0.clone(), node }))
+    }
+}
+
+impl Backend for VirtualBus {
+    fn enumerate(&self) -> Result<Vec<BMChannelInfo>> {
+        Ok(self.0.infos.clone())
+    }
+
+    fn open(&self, info: &BMChannelInfo) -> Result<Box<dyn Channel>> {
+        self.attach(info, BMCanMode::Normal, None, &[])
+    }
+
+    fn open_ex(&self, info: &BMChannelInfo, mode: BMCanMode, _term: BMTerminalResistor,
+               bitrate: &BMBitrate, filters: &[BMRxFilter]) -> Result<Box<dyn Channel>> {
+        self.attach(info, mode, Some(bitrate), filters)
+    }
+}
+
+impl Bus {
+    fn timestamp(&self) -> u32 {
+        self.start.elapsed().as_micros() as u32
+    }
+
+    fn detach(&self, node: &Arc<Node>) {
+        let mut nodes = self.nodes.lock().unwrap();
+        let slot = &mut nodes[node.index];
+
+        if slot.as_ref().is_some_and(|n| Arc::ptr_eq(n, node)) {
+            *slot = None;
+        }
+    }
+}
+
+fn channel_info(port: u16) -> BMChannelInfo {
+    let mut name = [0 as c_char; 64];
+    for (c, b) in name.iter_mut().zip(b"Busmust Virtual CAN".iter()) {
+        *c = *b as c_char;
+    }
+
+    let mut sn = [0u8; 16];
+    sn[..10].copy_from_slice(b"VIRTUAL000");
+
+    BMChannelInfo {
+        name,
+        sn,
+        uid: [0; 12],
+        version: [1, 0, 0, 0],
+        vid: 0,
+        pid: 0,
+        port,
+        cap: (BMCapability::CAN | BMCapability::CAN_FD).bits(),
+        reserved: [0; 4]
+    }
+}
+
+struct Node {
+    /// Index of the channel among the bus channels
+    index: usize,
+    port: u16,
+    state: Mutex<NodeState>,
+    notify: Condvar
+}
+
+struct NodeState {
+    mode: BMCanMode,
+    active: bool,
+    n_bitrate: u16,
+    d_bitrate: u16,
+    filters: Vec<BMRxFilter>,
+    rx: VecDeque<BMData>,
+    tx_errors: u8,
+    rx_errors: u8
+}
+
+impl NodeState {
+    fn is_bus_off(&self) -> bool {
+        self.tx_errors == u8::MAX
+    }
+}
+
+impl Node {
+    /// Offer a frame transmitted on the bus to this node, returns whether the node acknowledged it.
+    fn receive(&self, message: &BMCanMessage, timestamp: u32, n_bitrate: u16, d_bitrate: u16, own: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.active || state.is_bus_off() {
+            return false;
+        }
+
+        let ctrl = unsafe { message.ctrl.tx };
+        if !own {
+            match state.mode {
+                BMCanMode::BufOff | BMCanMode::Configuration | BMCanMode::InternalLoopback => return false,
+                _ => {}
+            }
+
+            let classic = matches!(state.mode, BMCanMode::Classic);
+            if state.n_bitrate != n_bitrate || (ctrl.brs() && state.d_bitrate != d_bitrate) || (ctrl.fdf() && classic) {
+                state.rx_errors = state.rx_errors.saturating_add(1);
+                return false;
+            }
+            state.rx_errors = state.rx_errors.saturating_sub(1);
+        }
+
+        if let Some(index) = accept(&state.filters, message) {
+            let mut message = *message;
+            message.ctrl.rx = BMRxMessageCtrl::from(u32::from(ctrl) & 0x1FF).with_rx_filter(index);
+
+            let mut data = BMData::builder().can_message(message).build();
+            data.header.set_schn((self.port & 0x0F) as u8);
+            data.timestamp = timestamp;
+
+            state.rx.push_back(data);
+            self.notify.notify_all();
+        }
+
+        !matches!(state.mode, BMCanMode::ListenOnly)
+    }
+}
+
+/// Get the index of the first RX filter accepting the message, all messages are accepted if there are no valid filters.
+fn accept(filters: &[BMRxFilter], message: &BMCanMessage) -> Option<u8> {
+    let ctrl = u32::from(unsafe { message.ctrl.tx });
+    let flags = ((ctrl >> 4) & 0x1F) as u8;
+    let id = u32::from(message.mid);
+
+    let mut valid = false;
+    for (index, filter) in filters.iter().enumerate() {
+        if filter.kind == BMRxFilterType::Invalid as u8 {
+            continue;
+        }
+        valid = true;
+
+        let payload = filter.kind != BMRxFilterType::Advanced as u8 || message.payload[..8].iter()
+            .zip(filter.payload_mask.iter().zip(filter.payload_value.iter()))
+            .all(|(b, (m, v))| b & m == *v);
+
+        if flags & filter.flags_mask == filter.flags_value && id & filter.id_mask == filter.id_value && payload {
+            return Some(index as u8);
+        }
+    }
+
+    if valid { None } else { Some(0) }
+}
+
+/// Channel opened on a [VirtualBus], detached from the bus when closed or dropped.
+struct VirtualChannel {
+    bus: Arc<Bus>,
+    node: Arc<Node>
+}
+
+impl Drop for VirtualChannel {
+    fn drop(&mut self) {
+        self.bus.detach(&self.node);
+    }
+}
+
+impl Channel for VirtualChannel {
+    fn close(&self) -> Result<()> {
+        self.bus.detach(&self.node);
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        let mut state = self.node.state.lock().unwrap();
+        state.rx.clear();
+        state.tx_errors = 0;
+        state.rx_errors = 0;
+        state.active = true;
+        Ok(())
+    }
+
+    fn activate(&self) -> Result<()> {
+        self.node.state.lock().unwrap().active = true;
+        Ok(())
+    }
+
+    fn deactivate(&self) -> Result<()> {
+        self.node.state.lock().unwrap().active = false;
+        Ok(())
+    }
+
+    fn clear_buffer(&self) -> Result<()> {
+        self.node.state.lock().unwrap().rx.clear();
+        Ok(())
+    }
+
+    fn status(&self) -> Result<BMCanStatusInfo> {
+        let state = self.node.state.lock().unwrap();
+
+        let mut status = BMCanStatusInfo::default();
+        status.tx_bus_off = state.is_bus_off() as u8;
+        status.tx_bus_passive = (state.tx_errors >= 128) as u8;
+        status.rx_bus_passive = (state.rx_errors >= 128) as u8;
+        status.tx_warn = (state.tx_errors >= 96) as u8;
+        status.rx_warn = (state.rx_errors >= 96) as u8;
+        status.tx_errors = state.tx_errors;
+        status.rx_errors = state.rx_errors;
+        Ok(status)
+    }
+
+    fn timestamp(&self) -> Result<u32> {
+        Ok(self.bus.timestamp())
+    }
+
+    fn set_bitrate(&self, bitrate: &BMBitrate) -> Result<()> {
+        let mut state = self.node.state.lock().unwrap();
+        state.n_bitrate = bitrate.n_bitrate;
+        state.d_bitrate = bitrate.d_bitrate;
+        Ok(())
+    }
+
+    fn set_terminal_resistor(&self, _value: BMTerminalResistor) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_can_mode(&self, mode: BMCanMode) -> Result<()> {
+        self.node.state.lock().unwrap().mode = mode;
+        Ok(())
+    }
+
+    fn set_rx_filters(&self, filters: &[BMRxFilter]) -> Result<()> {
+        self.node.state.lock().unwrap().filters = filters.to_vec();
+        Ok(())
+    }
+
+    fn write(&self, data: &BMData, _timeout: i32) -> Result<u32> {
+        if data.header.kind() != BMDataType::Can as u8 {
+            return Err(Error::new(BMStatus::InvalidParameterType));
+        }
+
+        let message = can_message(data);
+        let fd = unsafe { message.ctrl.tx.fdf() };
+
+        let (mode, n_bitrate, d_bitrate) = {
+            let state = self.node.state.lock().unwrap();
+            if !state.active || state.is_bus_off() {
+                return Err(Error::new(BMStatus::BusOff));
+            }
+            (state.mode, state.n_bitrate, state.d_bitrate)
+        };
+
+        match mode {
+            BMCanMode::BufOff => return Err(Error::new(BMStatus::BusOff)),
+            BMCanMode::Configuration | BMCanMode::Restricted => return Err(Error::new(BMStatus::InvalidOperation)),
+            BMCanMode::ListenOnly => return Err(Error::new(BMStatus::BusTimeout)),
+            BMCanMode::Classic if fd => return Err(Error::new(BMStatus::InvalidParameterValue)),
+            _ => {}
+        }
+
+        let transmit = self.bus.transmit.lock().unwrap();
+        let timestamp = self.bus.timestamp();
+        let mut acked = false;
+
+        if !matches!(mode, BMCanMode::InternalLoopback) {
+            let nodes: Vec<Arc<Node>> = self.bus.nodes.lock().unwrap().iter().flatten().cloned().collect();
+            for node in nodes.iter().filter(|n| !Arc::ptr_eq(n, &self.node)) {
+                acked |= node.receive(&message, timestamp, n_bitrate, d_bitrate, false);
+            }
+        }
+
+        if matches!(mode, BMCanMode::InternalLoopback | BMCanMode::ExternalLoopback) {
+            self.node.receive(&message, timestamp, n_bitrate, d_bitrate, true);
+            acked = true;
+        }
+        drop(transmit);
+
+        let mut state = self.node.state.lock().unwrap();
+        if acked {
+            state.tx_errors = state.tx_errors.saturating_sub(1);
+            Ok(timestamp)
+        } else {
+            state.tx_errors = state.tx_errors.saturating_add(8);
+            Err(Error::new(BMStatus::BusTimeout))
+        }
+    }
+
+    fn read(&self) -> Result<BMData> {
+        let mut state = self.node.state.lock().unwrap();
+        if !state.active {
+            return Err(Error::new(BMStatus::BusOff));
+        }
+
+        state.rx.pop_front().ok_or(Error::new(BMStatus::ReceiveBufferEmpty))
+    }
+
+    fn wait_for_notification(&self, timeout: i32) -> bool {
+        let deadline = Instant::now() + Duration::from_millis(timeout.max(0) as u64);
+        let mut state = self.node.state.lock().unwrap();
+
+        while state.rx.is_empty() {
+            if timeout < 0 {
+                state = self.node.notify.wait(state).unwrap();
+                continue;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self.node.notify.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dmgr::{OpenChannel, OpenOptions};
+    use super::*;
+
+    fn open(bus: &VirtualBus, port: usize, options: OpenOptions) -> OpenChannel {
+        options.open(&bus.enum_devices().unwrap().nth(port).unwrap()).unwrap()
+    }
+
+    fn message(id: u16) -> BMCanMessage {
+        BMCanMessage::builder().sid(id).payload(vec![1, 2, 3, 4]).build()
+    }
+
+    fn received(channel: &OpenChannel) -> Vec<u32> {
+        let mut ids = Vec::new();
+        while let Some(message) = channel.read_can_message().unwrap() {
+            ids.push(message.id().raw());
+        }
+        ids
+    }
+
+    #[test]
+    fn normal_mode_acknowledges() {
+        let bus = VirtualBus::new(3);
+        let tx = open(&bus, 0, OpenOptions::new());
+        let rx_a = open(&bus, 1, OpenOptions::new());
+        let rx_b = open(&bus, 2, OpenOptions::new());
+
+        tx.write_can_message(message(0x123), Some(100)).unwrap();
+
+        assert_eq!(received(&rx_a), vec![0x123]);
+        assert_eq!(received(&rx_b), vec![0x123]);
+        assert!(received(&tx).is_empty());
+        assert_eq!(tx.get_status_info().unwrap().tx_errors, 0);
+    }
+
+    #[test]
+    fn listen_only_does_not_acknowledge() {
+        let bus = VirtualBus::new(2);
+        let tx = open(&bus, 0, OpenOptions::new());
+        let rx = open(&bus, 1, OpenOptions::new().mode(BMCanMode::ListenOnly));
+
+        let error = tx.write_can_message(message(0x123), Some(100)).unwrap_err();
+        assert!(error.is(BMStatus::BusTimeout));
+        assert_eq!(tx.get_status_info().unwrap().tx_errors, 8);
+
+        // The frame is still received, just not acknowledged.
+        assert_eq!(received(&rx), vec![0x123]);
+
+        // Listen-only channels cannot transmit.
+        assert!(rx.write_can_message(message(0x456), Some(100)).unwrap_err().is(BMStatus::BusTimeout));
+        assert!(received(&tx).is_empty());
+    }
+
+    #[test]
+    fn internal_loopback() {
+        let bus = VirtualBus::new(2);
+        let tx = open(&bus, 0, OpenOptions::new().mode(BMCanMode::InternalLoopback));
+        let rx = open(&bus, 1, OpenOptions::new());
+
+        tx.write_can_message(message(0x123), Some(100)).unwrap();
+        assert_eq!(received(&tx), vec![0x123]);
+        assert!(received(&rx).is_empty());
+
+        // Frames of other channels do not reach the loopback channel either.
+        assert!(rx.write_can_message(message(0x456), Some(100)).unwrap_err().is(BMStatus::BusTimeout));
+        assert!(received(&tx).is_empty());
+    }
+
+    #[test]
+    fn external_loopback() {
+        let bus = VirtualBus::new(2);
+        let tx = open(&bus, 0, OpenOptions::new().mode(BMCanMode::ExternalLoopback));
+        let rx = open(&bus, 1, OpenOptions::new());
+
+        tx.write_can_message(message(0x123), Some(100)).unwrap();
+        assert_eq!(received(&tx), vec![0x123]);
+        assert_eq!(received(&rx), vec![0x123]);
+
+        rx.write_can_message(message(0x456), Some(100)).unwrap();
+        assert_eq!(received(&tx), vec![0x456]);
+    }
+
+    #[test]
+    fn bitrate_mismatch() {
+        let bus = VirtualBus::new(2);
+        let tx = open(&bus, 0, OpenOptions::new().bitrate(BMBitrate::CAN_500K));
+        let rx = open(&bus, 1, OpenOptions::new().bitrate(BMBitrate::CAN_250K));
+
+        let error = tx.write_can_message(message(0x123), Some(100)).unwrap_err();
+        assert!(error.is(BMStatus::BusTimeout));
+        assert!(received(&rx).is_empty());
+        assert_eq!(rx.get_status_info().unwrap().rx_errors, 1);
+        assert_eq!(tx.get_status_info().unwrap().tx_errors, 8);
+
+        // Matching the bitrate heals the error counters frame by frame.
+        rx.set_bitrate(BMBitrate::CAN_500K).unwrap();
+        tx.write_can_message(message(0x123), Some(100)).unwrap();
+        assert_eq!(received(&rx), vec![0x123]);
+        assert_eq!(rx.get_status_info().unwrap().rx_errors, 0);
+        assert_eq!(tx.get_status_info().unwrap().tx_errors, 7);
+    }
+
+    #[test]
+    fn data_bitrate_mismatch() {
+        let bus = VirtualBus::new(2);
+        let tx = open(&bus, 0, OpenOptions::new().bitrate(BMBitrate::FD_500K_2M));
+        let rx = open(&bus, 1, OpenOptions::new().bitrate(BMBitrate::CAN_500K));
+
+        // Frames without bitrate switch only use the nominal bitrate.
+        tx.write_can_message(message(0x123), Some(100)).unwrap();
+        assert_eq!(received(&rx), vec![0x123]);
+
+        let fd = BMCanMessage::builder().sid(0x124).fdf(true).brs(true).payload(vec![0; 16]).build();
+        assert!(tx.write_can_message(fd, Some(100)).unwrap_err().is(BMStatus::BusTimeout));
+        assert!(received(&rx).is_empty());
+    }
+
+    #[test]
+    fn classic_mode_rejects_fd() {
+        let bus = VirtualBus::new(2);
+        let tx = open(&bus, 0, OpenOptions::new().mode(BMCanMode::Classic).bitrate(BMBitrate::CAN_500K));
+        let _rx = open(&bus, 1, OpenOptions::new().bitrate(BMBitrate::CAN_500K));
+
+        let fd = BMCanMessage::builder().sid(0x123).fdf(true).payload(vec![0; 12]).build();
+        assert!(tx.write_can_message(fd, Some(100)).unwrap_err().is(BMStatus::InvalidParameterValue));
+    }
+
+    #[test]
+    fn rx_filters() {
+        let bus = VirtualBus::new(2);
+        let filters = vec![
+            BMRxFilter::builder().standard_id(0x100, 0x7F0).build(),
+            BMRxFilter::builder().standard_id(0x200, 0x7FF).payload_byte(0, 0xFF, 0x01).build()
+        ];
+        let tx = open(&bus, 0, OpenOptions::new());
+        let rx = open(&bus, 1, OpenOptions::new().rx_filters(filters));
+
+        for id in [0x105, 0x200, 0x300] {
+            // Filtered frames are still acknowledged.
+            tx.write_can_message(message(id), Some(100)).unwrap();
+        }
+        let other = BMCanMessage::builder().sid(0x200).payload(vec![2]).build();
+        tx.write_can_message(other, Some(100)).unwrap();
+
+        let first = rx.read_can_message().unwrap().unwrap();
+        assert_eq!((first.id().raw(), unsafe { first.ctrl.rx.rx_filter() }), (0x105, 0));
+
+        let second = rx.read_can_message().unwrap().unwrap();
+        assert_eq!((second.id().raw(), unsafe { second.ctrl.rx.rx_filter() }), (0x200, 1));
+
+        assert!(rx.read_can_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_are_ordered() {
+        let bus = VirtualBus::new(3);
+        let a = open(&bus, 0, OpenOptions::new());
+        let b = open(&bus, 1, OpenOptions::new());
+        let sniffer = open(&bus, 2, OpenOptions::new().mode(BMCanMode::ListenOnly));
+
+        let writer = ::std::thread::spawn(move || {
+            for id in 0..200 {
+                a.write_can_message(message(id), Some(100)).unwrap();
+            }
+            a
+        });
+        for id in 0..200 {
+            b.write_can_message(message(0x400 + id), Some(100)).unwrap();
+        }
+        let _a = writer.join().unwrap();
+
+        let timestamps = sniffer.read_can_messages(400, Some(0)).unwrap().timestamps().to_vec();
+        assert_eq!(timestamps.len(), 400);
+        assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn custom_channels() {
+        // Two devices with the same port numbers, the second one without CAN FD.
+        let mut infos = VirtualBus::new(2).enumerate().unwrap();
+        infos.extend(infos.clone());
+        for info in &mut infos[2..] {
+            info.sn[..10].copy_from_slice(b"VIRTUAL001");
+            info.cap = BMCapability::CAN.bits();
+        }
+        let bus = VirtualBus::with_channels(infos);
+
+        let devices: Vec<_> = bus.enum_devices().unwrap().collect();
+        assert_eq!(devices.len(), 4);
+        assert_eq!(devices[2].caps(), BMCapability::CAN);
+
+        // Channels of both devices are attached independently.
+        let a = open(&bus, 0, OpenOptions::new());
+        let b = open(&bus, 2, OpenOptions::new());
+        assert!(OpenOptions::new().open(&devices[0]).err().unwrap().is(BMStatus::HardwareInUse));
+
+        a.write_can_message(message(0x123), Some(100)).unwrap();
+        assert_eq!(received(&b), vec![0x123]);
+
+        b.close().unwrap();
+        assert!(OpenOptions::new().open(&devices[2]).is_ok());
+
+        // Channels not enumerated by the bus cannot be opened.
+        let mut unknown = bus.enumerate().unwrap()[0];
+        unknown.port = 5;
+        assert!(bus.open(&unknown).err().unwrap().is(BMStatus::HardwareError));
+    }
+}
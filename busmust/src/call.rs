@@ -1,10 +1,10 @@
-use super::{Error, Result};
-use ffi::BMStatus;
+use super::{Error, Result, Status};
 
-pub fn cvt_r(r: BMStatus) -> Result<()> {
-     if matches!(r, BMStatus::Ok) {
+pub fn cvt_r(r: u32) -> Result<()> {
+    let status = Status::from_raw(r);
+    if status.is_ok() {
         Ok(())
     } else {
-        Err(Error(r).into())
+        Err(Error::new(status))
     }
 }
@@ -1,100 +1,285 @@
 use std::ffi::{c_char, c_void};
-use super::{Error, Result};
-use std::{mem, ptr};
-use std::mem::MaybeUninit;
 use std::os::raw::c_int;
+use std::thread;
+use std::time::{Duration, Instant};
+use super::{Error, Result, Status};
+use std::{fmt, mem};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::JoinHandle;
 use call::cvt_r;
 use ffi::*;
 
 use util::StringExt;
+use backend::{Backend, Channel, Native};
+use txtask::TxTask;
+use isotp::IsotpConfig;
 
-pub fn initialize() -> Result<()> {
-    unsafe {
-        cvt_r(BM_Init())?;
+/// Number of RX acceptance filters supported by a Busmust device channel.
+///
+/// The limit is fixed because the library offers no way to query it, [BMChannelInfo] and the capability flags do not
+/// describe the filter table and the device only reports an error once too many filters are installed.
+const MAX_RX_FILTERS: usize = 2;
+
+/// Number of live [BusMust] guards, the library is initialized while it is non-zero.
+static INIT_COUNT: Mutex<usize> = Mutex::new(0);
+
+/// Guard keeping the Busmust library initialized.
+/// [BM_Init] is called when the first guard is created and [BM_UnInit] when the last one is dropped,
+/// devices enumerated using a guard and channels opened on them keep a clone of it.
+///
+/// # Examples
+///
+/// ```no_run
+/// use busmust::dmgr::BusMust;
+///
+/// let busmust = BusMust::new().unwrap();
+/// for device in busmust.enum_devices().unwrap() {
+///     println!("{}", device.name());
+/// }
+/// ```
+pub struct BusMust(());
+
+impl BusMust {
+    /// Initialize the library unless another guard is alive.
+    pub fn new() -> Result<BusMust> {
+        let mut count = INIT_COUNT.lock().unwrap_or_else(|e| e.into_inner());
+        if *count == 0 {
+            unsafe {
+                cvt_r(BM_Init()).map_err(|e| e.context("initialize", None))?;
+            }
+        }
+
+        *count += 1;
+        Ok(BusMust(()))
+    }
+
+    /// Enumerate all connected Busmust devices.
+    pub fn enum_devices(&self) -> Result<Devices> {
+        enum_devices_with(Arc::new(Native::new(self.clone())))
+    }
+
+    /// Set library log level. See [BMLogLevel].
+    ///
+    /// # Arguments
+    ///
+    /// * `level`: Log level to set. See [BMLogLevel].
+    ///
+    /// returns: ()
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::BusMust;
+    /// use busmust_sys::BMLogLevel;
+    ///
+    /// let busmust = BusMust::new().unwrap();
+    /// busmust.set_log_level(BMLogLevel::Info);
+    /// ```
+    pub fn set_log_level(&self, level: BMLogLevel) {
+        unsafe {
+            BM_SetLogLevel(level)
+        }
+    }
+
+    /// Get library log level. See [BMLogLevel].
+    ///
+    /// returns: [BMLogLevel], `None` if the library reports an unknown level
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use busmust::dmgr::BusMust;
+    ///
+    /// let busmust = BusMust::new().unwrap();
+    /// println!("{:?}", busmust.get_log_level());
+    /// ```
+    pub fn get_log_level(&self) -> Option<BMLogLevel> {
+        BMLogLevel::from_raw(unsafe { BM_GetLogLevel() })
     }
-    Ok(())
 }
 
-pub fn terminate() -> Result<()> {
-    unsafe {
-        cvt_r(BM_UnInit())?;
+impl Clone for BusMust {
+    fn clone(&self) -> BusMust {
+        *INIT_COUNT.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        BusMust(())
+    }
+}
+
+impl Drop for BusMust {
+    fn drop(&mut self) {
+        let mut count = INIT_COUNT.lock().unwrap_or_else(|e| e.into_inner());
+        *count -= 1;
+
+        if *count == 0 {
+            unsafe {
+                BM_UnInit();
+            }
+        }
     }
-    Ok(())
 }
 
 pub fn desc_from_error(err: &Error) -> String {
+    desc_from_status(err.status())
+}
+
+/// Get the description of the status from the library, combined statuses are described flag by flag.
+pub fn desc_from_status(status: Status) -> String {
+    let status = match status.to_bm_status() {
+        Some(status) => status,
+        None => {
+            return status.flags().into_iter()
+                .map(|flag| desc_from_status(flag.into()))
+                .collect::<Vec<String>>()
+                .join("; ")
+        }
+    };
+
     unsafe {
         let mut desc: [c_char; ffi::BM_ERROR_DESC_MAX_SIZE] = mem::zeroed();
 
-        BM_GetErrorText(err.0, desc[..].as_mut_ptr(), BM_ERROR_DESC_MAX_SIZE, 0);
+        BM_GetErrorText(status as u32, desc[..].as_mut_ptr(), BM_ERROR_DESC_MAX_SIZE, 0);
         let desc = String::from_slice(&desc[..]);
 
         desc
     }
 }
 
-pub struct Device (BMChannelInfo, Option<*const c_void>, Option<*const c_void>);
+/// Device channel found by [enum_devices], open it using [Device::open] or [Device::open_ex].
+#[derive(Clone)]
+pub struct Device (BMChannelInfo, Arc<dyn Backend>);
 
 impl Device {
     /// Open the device channel with default parameters.
     ///
+    /// returns: The opened channel, closed when dropped.
+    ///
     /// # Examples
     ///
+    /// ```no_run
+    /// use busmust::dmgr::enum_devices;
+    ///
+    /// let device = enum_devices().unwrap().next().unwrap();
+    /// let channel = device.open().unwrap();
     /// ```
-    /// device.open.unwrap()
-    /// ```
-    pub fn open(&mut self) -> Result<()> {
-        unsafe {
-            let handle = BM_OpenCan(self.0.port);
-            if !handle.is_null() {
-                self.1 = Some(handle);
-                self.get_notification()
-            } else {
-                Err(Error(BMStatus::InvalidOperation).into())
-            }
-        }
+    pub fn open(&self) -> Result<OpenChannel> {
+        let channel = self.1.open(&self.0).map_err(|e| e.context("open", Some(self.port())))?;
+        Ok(OpenChannel { device: self.clone(), channel, closed: false })
     }
 
-    pub fn open_ex(&mut self) -> Result<()> {
-        let bitrate = BMBitrate::builder().build();
+    /// Open the device channel in normal mode, with 120 Ohm terminal resistor and default bitrate.
+    ///
+    /// returns: The opened channel, closed when dropped.
+    pub fn open_ex(&self) -> Result<OpenChannel> {
+        self.open_with(&OpenOptions::new())
+    }
 
-        unsafe {
-            let mut handle: *mut c_void = ptr::null_mut();
-            let handle_ptr: *mut *mut c_void = &mut handle;
+    /// Open the device channel using the given options, validated against the device capabilities first.
+    ///
+    /// returns: The opened channel, closed when dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::{enum_devices, OpenOptions};
+    /// use busmust_sys::BMCanMode;
+    ///
+    /// let device = enum_devices().unwrap().next().unwrap();
+    /// let channel = device.open_with(&OpenOptions::new().mode(BMCanMode::ListenOnly)).unwrap();
+    /// ```
+    pub fn open_with(&self, options: &OpenOptions) -> Result<OpenChannel> {
+        let context = |e: Error| e.context("open_ex", Some(self.port()));
 
-            cvt_r(BM_OpenEx(
-                handle_ptr,
-                &self.0,
-                BMCanMode::Normal,
-                BMTerminalResistor::Enabled120,
-                &bitrate,
-                0 as *const BMRxFilter,
-                0)).and_then(|_| {
-                    self.1 = Some(handle.cast_const());
-                    self.get_notification()
-                })
+        options.validate(self.caps()).map_err(context)?;
+
+        if let Some(level) = options.log_level {
+            unsafe { BM_SetLogLevel(level) }
         }
-    }
 
+        let channel = self.1.open_ex(
+            &self.0,
+            options.mode,
+            options.terminal_resistor,
+            &options.bitrate_for(self.caps()),
+            &options.filters).map_err(context)?;
+        let channel = OpenChannel { device: self.clone(), channel, closed: false };
 
-    /// Set bitrate option of the opened channel.
+        if !options.tx_tasks.is_empty() {
+            channel.set_tx_tasks(&options.tx_tasks).map_err(context)?;
+        }
+
+        Ok(channel)
+    }
+
+    /// Detect the bitrate of the bus the device channel is connected to, without ever transmitting on it.
+    ///
+    /// The channel is opened in [BMCanMode::ListenOnly] with terminal resistor disabled using each candidate in turn,
+    /// the first candidate receiving frames for `dwell` without any bus error is returned. Candidates not supported by
+    /// the device, or failing to open, are skipped.
     ///
     /// # Arguments
     ///
-    /// * `bitrate`: Expected bitrate, see [BMBitrate] for details.
+    /// * `candidates`: Bitrates to try, in order.
+    /// * `dwell`: Time to listen using each candidate.
     ///
-    /// returns: [Result<()>]
+    /// returns: The detected bitrate, `None` if no candidate receives valid traffic, or the last error if the channel
+    /// cannot be opened with any candidate.
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use std::time::Duration;
+    /// use busmust::dmgr::enum_devices;
     /// use busmust_sys::BMBitrate;
     ///
-    /// device.set_bitrate(BMBitrate::builder().bitrate(250).build()).unwrap();
+    /// let device = enum_devices().unwrap().next().unwrap();
+    /// let candidates = [BMBitrate::CAN_500K, BMBitrate::CAN_250K, BMBitrate::FD_500K_2M];
+    /// if let Some(detected) = device.detect_bitrate(&candidates, Duration::from_millis(500)).unwrap() {
+    ///     println!("{} kbps, data {:?} kbps", detected.bitrate.n_bitrate, detected.data_bitrate);
+    /// }
     /// ```
-    pub fn set_bitrate(&self, bitrate: BMBitrate) -> Result<()> {
-        unsafe {
-            cvt_r(BM_SetBitrate(self.1.expect("not opened"), &bitrate))
+    pub fn detect_bitrate(&self, candidates: &[BMBitrate], dwell: Duration) -> Result<Option<DetectedBitrate>> {
+        let mut opened = false;
+        let mut error = None;
+
+        for candidate in candidates {
+            let options = OpenOptions::new()
+                .mode(BMCanMode::ListenOnly)
+                .terminal_resistor(BMTerminalResistor::Disabled)
+                .bitrate(*candidate);
+            let channel = match self.open_with(&options) {
+                Ok(channel) => channel,
+                Err(e) => {
+                    error = Some(e);
+                    continue;
+                }
+            };
+            opened = true;
+
+            if let Some(brs) = listen(&channel, dwell).map_err(|e| e.context("detect_bitrate", Some(self.port())))? {
+                // The data bitrate is confirmed only by frames actually switching to it.
+                return Ok(Some(DetectedBitrate {
+                    bitrate: *candidate,
+                    data_bitrate: if brs { Some(candidate.d_bitrate) } else { None }
+                }));
+            }
+        }
+
+        match error {
+            Some(e) if !opened => Err(e),
+            _ => Ok(None)
         }
     }
 
@@ -105,18 +290,24 @@ impl Device {
 
     /// Get device serial number as string
     pub fn serial_number(&self) -> String {
-        String::from_utf8_lossy(&self.0.sn[..]).to_string()
+        string_from_bytes(&self.0.sn[..])
     }
 
     /// Get the unique ID of the device as string
     pub fn unique_id(&self) -> String {
-        String::from_utf8_lossy(&self.0.uid[..]).to_string()
+        string_from_bytes(&self.0.uid[..])
     }
 
+    /// Get the raw firmware version, see [Device::firmware_version].
     pub fn version(&self) -> Vec<u8> {
         self.0.version.to_vec()
     }
 
+    /// Get the firmware version of the device
+    pub fn firmware_version(&self) -> FirmwareVersion {
+        FirmwareVersion::from(self.0.version)
+    }
+
     /// Get the USB vendor ID of the device
     pub fn vendor_id(&self) -> u16 {
         self.0.vid
@@ -134,181 +325,609 @@ impl Device {
 
     /// Get device capabilities
     pub fn caps(&self) -> BMCapability {
-        self.0.cap
+        self.0.capabilities()
     }
+}
 
-    /// Close previously opened channel
-    pub fn close(&self) -> Result<()> {
-        unsafe {
-            cvt_r(BM_Close(self.1.expect("not opened")))
-        }
+/// Read a NUL padded string.
+fn string_from_bytes(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).to_string()
+}
+
+/// Firmware version of a device, decoded from [BMChannelInfo::version].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u8,
+    pub build: u8
+}
+
+impl From<[u8; 4]> for FirmwareVersion {
+    fn from(version: [u8; 4]) -> FirmwareVersion {
+        FirmwareVersion { major: version[0], minor: version[1], revision: version[2], build: version[3] }
     }
+}
 
-    /// Reset the opened channel.
-    /// The configuration options will not be lost when the channel is reset, so [Device::reset] is basically identical to [Device::close] and [Device::open].
-    pub fn reset(&self) -> Result<()> {
-        unsafe {
-            cvt_r(BM_Reset(self.1.expect("not opened")))
-        }
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.major, self.minor, self.revision, self.build)
     }
+}
 
-    /// Activate the opened channel. After that the user can transmit and receive messages on the bus.
-    /// Channel will be active by default after [Device::open_ex] is called.
-    pub fn activate(&self) -> Result<()> {
-        unsafe {
-            cvt_r(BM_Activate(self.1.expect("not opened")))
-        }
+/// Criteria selecting device channels, see [Devices::select]. Channels must match all given criteria.
+///
+/// # Examples
+///
+/// ```no_run
+/// extern crate busmust;
+/// extern crate busmust_sys;
+///
+/// use busmust::dmgr::{enum_devices, DeviceQuery};
+/// use busmust_sys::BMCapability;
+///
+/// let query = DeviceQuery::new()
+///     .serial_number("0123456789")
+///     .capabilities(BMCapability::CAN_FD);
+///
+/// for device in enum_devices().unwrap().select(&query) {
+///     println!("{} port {}", device.name(), device.port());
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DeviceQuery {
+    serial_number: Option<String>,
+    unique_id: Option<String>,
+    name: Option<String>,
+    port: Option<u16>,
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    capabilities: Option<BMCapability>
+}
+
+impl DeviceQuery {
+    /// Create a query matching every channel.
+    pub fn new() -> DeviceQuery {
+        DeviceQuery::default()
     }
 
-    /// Deactivate the opened channel. The channel will stay in BUS OFF state until re-activation.
-    /// Any read/write call will raise a [BMStatus::BusOff] error immediately if the channel is deactivated.
-    pub fn deactivate(&self) -> Result<()> {
-        unsafe {
-            cvt_r(BM_Deactivate(self.1.expect("not opened")))
-        }
+    /// Match channels of the device with the given serial number.
+    pub fn serial_number(mut self, value: &str) -> DeviceQuery {
+        self.serial_number = Some(value.to_string());
+        self
     }
 
-    /// Clear internal TX & RX message buffer of the opened channel.
-    pub fn clear_buffer(&self) -> Result<()> {
-        unsafe {
-            cvt_r(BM_ClearBuffer(self.1.expect("not opened")))
+    /// Match channels of the device with the given unique ID.
+    pub fn unique_id(mut self, value: &str) -> DeviceQuery {
+        self.unique_id = Some(value.to_string());
+        self
+    }
+
+    /// Match channels whose device name contains the given string.
+    pub fn name(mut self, value: &str) -> DeviceQuery {
+        self.name = Some(value.to_string());
+        self
+    }
+
+    /// Match channels with the given port index.
+    pub fn port(mut self, value: u16) -> DeviceQuery {
+        self.port = Some(value);
+        self
+    }
+
+    /// Match channels of devices with the given USB vendor ID.
+    pub fn vendor_id(mut self, value: u16) -> DeviceQuery {
+        self.vendor_id = Some(value);
+        self
+    }
+
+    /// Match channels of devices with the given USB product ID.
+    pub fn product_id(mut self, value: u16) -> DeviceQuery {
+        self.product_id = Some(value);
+        self
+    }
+
+    /// Match channels supporting all of the given capabilities.
+    pub fn capabilities(mut self, value: BMCapability) -> DeviceQuery {
+        self.capabilities = Some(value);
+        self
+    }
+
+    /// Check whether the channel matches all criteria.
+    pub fn matches(&self, device: &Device) -> bool {
+        self.serial_number.as_ref().map_or(true, |sn| device.serial_number() == *sn) &&
+            self.unique_id.as_ref().map_or(true, |uid| device.unique_id() == *uid) &&
+            self.name.as_ref().map_or(true, |name| device.name().contains(name.as_str())) &&
+            self.port.map_or(true, |port| device.port() == port) &&
+            self.vendor_id.map_or(true, |vid| device.vendor_id() == vid) &&
+            self.product_id.map_or(true, |pid| device.product_id() == pid) &&
+            self.capabilities.map_or(true, |caps| device.caps().contains(caps))
+    }
+}
+
+/// Physical device with all its channels, grouped by serial number, see [Devices::physical_devices].
+#[derive(Clone)]
+pub struct PhysicalDevice {
+    serial_number: String,
+    channels: Vec<Device>
+}
+
+impl PhysicalDevice {
+    /// Get device serial number as string
+    pub fn serial_number(&self) -> &str {
+        &self.serial_number
+    }
+
+    /// Get the string name of the device
+    pub fn name(&self) -> String {
+        self.channels[0].name()
+    }
+
+    /// Get the firmware version of the device
+    pub fn firmware_version(&self) -> FirmwareVersion {
+        self.channels[0].firmware_version()
+    }
+
+    /// Get the channels of the device, ordered by port index.
+    pub fn channels(&self) -> &[Device] {
+        &self.channels
+    }
+
+    /// Get the channel with the given port index.
+    pub fn channel(&self, port: u16) -> Option<&Device> {
+        self.channels.iter().find(|device| device.port() == port)
+    }
+}
+
+/// Bitrate found by [Device::detect_bitrate].
+#[derive(Debug, Copy, Clone)]
+pub struct DetectedBitrate {
+    /// The candidate receiving valid traffic
+    pub bitrate: BMBitrate,
+    /// Data bitrate in kbps of the candidate, if CAN-FD frames with bitrate switch were received
+    pub data_bitrate: Option<u16>
+}
+
+/// Listen on the channel for `dwell`, stopping early on bus errors.
+///
+/// returns: `None` unless frames are received without errors, otherwise whether any of them used bitrate switch.
+fn listen(channel: &OpenChannel, dwell: Duration) -> Result<Option<bool>> {
+    let deadline = Instant::now() + dwell;
+    let mut frames = 0;
+    let mut brs = false;
+
+    loop {
+        let status = channel.get_status_info()?;
+        let errors = status.rx_errors != 0 || status.tx_errors != 0 || status.tx_bus_off != 0 ||
+            status.rx_bus_passive != 0 || status.tx_bus_passive != 0;
+        if errors {
+            return Ok(None);
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        if !channel.wait_for_notification(Some(remaining.as_millis().max(1) as u32)) {
+            continue;
+        }
+
+        loop {
+            let data = match channel.read() {
+                Ok(data) => data,
+                Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) => break,
+                Err(e) => return Err(e)
+            };
+
+            if let Ok(BMDecodedData { payload: BMPayload::Can(message), .. }) = data.decode() {
+                let ctrl = unsafe { message.ctrl.rx };
+                brs |= ctrl.fdf() && ctrl.brs();
+                frames += 1;
+            }
         }
     }
 
-    /// Get current CAN status of the opened channel.
-    pub fn get_status_info(&self) -> Result<BMCanStatusInfo> {
-        unsafe {
-            let mut status_info = MaybeUninit::<BMCanStatusInfo>::uninit();
-            cvt_r(BM_GetStatus(
-                self.1.expect("not opened"),
-                status_info.as_mut_ptr(),
-            )).and_then(|_| Ok(status_info.assume_init()))
+    Ok(if frames > 0 { Some(brs) } else { None })
+}
+
+/// Options used to open a device channel, see [Device::open_with].
+///
+/// Defaults to normal mode, with 120 Ohm terminal resistor, default bitrate, no RX filters and no TX tasks,
+/// like [Device::open_ex].
+///
+/// # Examples
+///
+/// ```no_run
+/// extern crate busmust;
+/// extern crate busmust_sys;
+///
+/// use busmust::dmgr::{enum_devices, OpenOptions};
+/// use busmust_sys::{BMBitrate, BMCanMode, BMTerminalResistor};
+///
+/// let device = enum_devices().unwrap().next().unwrap();
+/// let channel = OpenOptions::new()
+///     .mode(BMCanMode::Classic)
+///     .terminal_resistor(BMTerminalResistor::Disabled)
+///     .bitrate(BMBitrate::CAN_250K)
+///     .open(&device)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    mode: BMCanMode,
+    terminal_resistor: BMTerminalResistor,
+    bitrate: Option<BMBitrate>,
+    filters: Vec<BMRxFilter>,
+    log_level: Option<BMLogLevel>,
+    tx_tasks: Vec<TxTask>
+}
+
+impl Default for OpenOptions {
+    fn default() -> OpenOptions {
+        OpenOptions {
+            mode: BMCanMode::Normal,
+            terminal_resistor: BMTerminalResistor::Enabled120,
+            bitrate: None,
+            filters: Vec::new(),
+            log_level: None,
+            tx_tasks: Vec::new()
         }
     }
+}
 
-    /// Get current value of tge high precision device timestamp, in microseconds.
-    pub fn get_timestamp(&self) -> Result<u32> {
-        unsafe {
-            let mut timestamp = 0;
-            cvt_r(BM_GetTimestamp(
-                self.1.expect("not opened"),
-                &mut timestamp
-            )).and_then(|_| Ok(timestamp as u32))
+impl OpenOptions {
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    pub fn mode(mut self, mode: BMCanMode) -> OpenOptions {
+        self.mode = mode;
+        self
+    }
+
+    pub fn terminal_resistor(mut self, terminal_resistor: BMTerminalResistor) -> OpenOptions {
+        self.terminal_resistor = terminal_resistor;
+        self
+    }
+
+    /// Set nominal and data bitrate, by default the data bitrate of devices without [BMCapability::CAN_FD]
+    /// is set to the nominal one.
+    pub fn bitrate(mut self, bitrate: BMBitrate) -> OpenOptions {
+        self.bitrate = Some(bitrate);
+        self
+    }
+
+    /// Set RX acceptance filters, at most two filters are supported.
+    pub fn rx_filters(mut self, filters: Vec<BMRxFilter>) -> OpenOptions {
+        self.filters = filters;
+        self
+    }
+
+    /// Set the library log level before opening the channel, see [BusMust::set_log_level].
+    ///
+    /// The level is global to the Busmust library: it applies to all devices and channels and is kept after the
+    /// channel is closed. It is left unchanged unless set here.
+    pub fn log_level(mut self, level: BMLogLevel) -> OpenOptions {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Set TX tasks started right after opening the channel, see [OpenChannel::set_tx_tasks].
+    pub fn tx_tasks(mut self, tasks: Vec<TxTask>) -> OpenOptions {
+        self.tx_tasks = tasks;
+        self
+    }
+
+    /// Check whether the options are supported by a device with the given capabilities.
+    ///
+    /// returns: [BMStatus::InvalidOperation] if the device does not support CAN,
+    /// [BMStatus::InvalidParameterValue] if CAN-FD is requested (i.e. an FD TX task, or a data bitrate different from
+    /// the nominal one unless in [BMCanMode::Classic]) on a device without [BMCapability::CAN_FD],
+    /// or if too many RX filters are given.
+    pub fn validate(&self, caps: BMCapability) -> Result<()> {
+        if !caps.contains(BMCapability::CAN) {
+            return Err(Error::new(BMStatus::InvalidOperation));
+        }
+
+        if !caps.contains(BMCapability::CAN_FD) {
+            let fd_bitrate = match self.bitrate {
+                Some(ref bitrate) if !matches!(self.mode, BMCanMode::Classic) => {
+                    bitrate.d_bitrate != bitrate.n_bitrate || bitrate.d_btr0 != 0 || bitrate.d_btr1 != 0
+                }
+                _ => false
+            };
+
+            if fd_bitrate || self.tx_tasks.iter().any(TxTask::is_fd) {
+                return Err(Error::new(BMStatus::InvalidParameterValue));
+            }
+        }
+
+        if self.filters.len() > MAX_RX_FILTERS {
+            return Err(Error::new(BMStatus::InvalidParameterValue));
         }
+
+        Ok(())
     }
 
-    /// Set terminal resistor option of the opened channel.
+    /// Get the bitrate passed to the device, see [OpenOptions::bitrate].
+    fn bitrate_for(&self, caps: BMCapability) -> BMBitrate {
+        self.bitrate.unwrap_or_else(|| {
+            let mut bitrate = BMBitrate::default();
+            if !caps.contains(BMCapability::CAN_FD) {
+                bitrate.d_bitrate = bitrate.n_bitrate;
+            }
+            bitrate
+        })
+    }
+
+    /// Open the given device channel using these options, see [Device::open_with].
+    pub fn open(&self, device: &Device) -> Result<OpenChannel> {
+        device.open_with(self)
+    }
+}
+
+/// Device channel opened by [Device::open] or [Device::open_ex], closed when dropped.
+pub struct OpenChannel {
+    device: Device,
+    channel: Box<dyn Channel>,
+    closed: bool
+}
+
+impl OpenChannel {
+    /// Get the device this channel was opened on.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Set bitrate option of the opened channel.
     ///
     /// # Arguments
     ///
-    /// * `value`: Expected terminal resistor value, see [BMTerminalResistor] for details.
+    /// * `bitrate`: Expected bitrate, see [BMBitrate] for details.
     ///
     /// returns: [Result<()>]
     ///
     /// # Examples
     ///
-    /// ```
-    /// use busmust_sys::BMTerminalResistor;
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::enum_devices;
+    /// use busmust_sys::BMBitrate;
     ///
-    /// device.set_terminal_resistor(BMTerminalResistor::Enabled120).unwrap()
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// channel.set_bitrate(BMBitrate::builder().bitrate(250).build().unwrap()).unwrap();
     /// ```
-    pub fn set_terminal_resistor(&self, value: BMTerminalResistor) -> Result<()> {
-        unsafe {
-            cvt_r(BM_SetTerminalRegister(
-                self.1.expect("not opened"),
-                value
-            ))
-        }
+    pub fn set_bitrate(&self, bitrate: BMBitrate) -> Result<()> {
+        self.channel.set_bitrate(&bitrate).map_err(self.context("set_bitrate"))
     }
 
-    /// Set CAN mode option of the opened channel.
+    /// Set RX acceptance filters of the opened channel, replacing any previously installed filters.
+    /// Pass an empty slice to remove all filters and accept every message.
     ///
     /// # Arguments
     ///
-    /// * `mode`: Expected CAN mode, see [BMCanMode] for details.
+    /// * `filters`: At most [OpenChannel::max_rx_filters] filters, see [BMRxFilter] for details.
     ///
     /// returns: [Result<()>]
     ///
     /// # Examples
     ///
-    /// ```
-    /// use busmust_sys::BMCanMode;
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
     ///
-    /// device.set_can_mode(BMCanMode::Normal).unwrap()
+    /// use busmust::dmgr::enum_devices;
+    /// use busmust_sys::BMRxFilter;
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let filter = BMRxFilter::builder()
+    ///     .standard_id(0x700, 0x700)
+    ///     .build();
+    /// channel.set_rx_filters(&[filter]).unwrap();
     /// ```
-    pub fn set_can_mode(&self, mode: BMCanMode) -> Result<()> {
-        unsafe {
-            cvt_r(BM_SetCanMode(
-                self.1.expect("not opened"),
-                mode
-            ))
+    pub fn set_rx_filters(&self, filters: &[BMRxFilter]) -> Result<()> {
+        if filters.len() > self.max_rx_filters() {
+            return Err(self.context("set_rx_filters")(Error::new(BMStatus::InvalidParameterValue)));
         }
+
+        self.channel.set_rx_filters(filters).map_err(self.context("set_rx_filters"))
     }
 
-    /// Write a message/event to the opened channel.
+    /// Get the maximum number of RX filters which could be installed on the channel.
+    ///
+    /// This is the same for every Busmust CAN channel, the value is not reported by the device.
+    pub fn max_rx_filters(&self) -> usize {
+        MAX_RX_FILTERS
+    }
+
+    /// Set hardware TX tasks of the opened channel, replacing any previously configured tasks.
+    /// Pass an empty slice to stop all TX tasks.
     ///
     /// # Arguments
     ///
-    /// * `message`: A [BMData] struct which represents the message.
-    /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely or `0` to send asynchronously.
+    /// * `tasks`: Tasks to be executed by the device, see [TxTask] for details.
     ///
-    /// returns: The device local high precision timestamp in microseconds, when the message is physically transmitted on the CAN bus, or error.
+    /// returns: [Result<()>]
     ///
     /// # Examples
     ///
-    /// ```
-    /// use busmust_sys::{BMCanMessage, BMData};
-    ///
-    /// let msg = BMCanMessage::builder()
-    ///  .sid(0x123)
-    ///  .payload(vec![1, 2, 3, 4, 5, 6, 7, 8])
-    ///  .build();
-    ///
-    /// let data = BMData::builder()
-    ///  .can_message(msg)
-    ///  .build();
+    /// ```no_run
+    /// use busmust::dmgr::enum_devices;
+    /// use busmust::txtask::{TxTask, TxTaskKind};
     ///
-    /// device.write(data, Some(1000)).unwrap();
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let task = TxTask::builder()
+    ///     .sid(0x123)
+    ///     .payload(vec![0; 8])
+    ///     .kind(TxTaskKind::IncId { min: 0x100, max: 0x1FF, step: 1 })
+    ///     .cycle(10)
+    ///     .rounds(100)
+    ///     .build()
+    ///     .unwrap();
+    /// channel.set_tx_tasks(&[task]).unwrap();
     /// ```
-    pub fn write(&self, message: BMData, timeout: Option<i32>) -> Result<u32> {
-        unsafe {
-            let mut timestamp = 0;
-            cvt_r(BM_Write(
-                self.1.expect("not opened"),
-                &message,
-                timeout.unwrap_or_default(),
-                &mut timestamp
-            )).and_then(|_| Ok(timestamp as u32))
-        }
+    pub fn set_tx_tasks(&self, tasks: &[TxTask]) -> Result<()> {
+        let tasks: Vec<BMTxTask> = tasks.iter().map(TxTask::to_raw).collect();
+        self.channel.set_tx_tasks(&tasks).map_err(self.context("set_tx_tasks"))
     }
 
-    /// Write multiple message/event to the opened channel.
-    ///
-    /// # Arguments
-    ///
-    /// * `messages`: An array of messages/events to be sent.
-    /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely or `0` to send asynchronously.
-    ///
-    /// returns: An array of device local high precision timestamps in microseconds, when the messages are physically transmitted, or error.
-    ///
-    pub fn write_multiple(&self, messages: Vec<BMData>, timeout: Option<i32>) -> Result<Vec<u32>> {
-        unsafe {
-            let mut n_messages = 0;
-
-            let mut timestamps = Vec::<u32>::with_capacity(messages.len());
-            timestamps.reserve_exact(messages.len());
-
-            cvt_r(BM_WriteMultiple(
-                self.1.expect("not opened"),
-                messages.as_ptr(),
-                &mut n_messages,
-                timeout.unwrap_or_default(),
-                timestamps.as_mut_ptr() as *mut c_int
-            )).and_then(|_| {
-                timestamps.truncate(n_messages as usize);
-                Ok(timestamps)
-            })
-        }
+    /// Close the channel, same as dropping it but reports errors.
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.channel.close().map_err(self.context("close"))
+    }
+
+    /// Reset the opened channel.
+    /// The configuration options will not be lost when the channel is reset, so [OpenChannel::reset] is basically identical to [OpenChannel::close] and [Device::open].
+    pub fn reset(&self) -> Result<()> {
+        self.channel.reset().map_err(self.context("reset"))
+    }
+
+    /// Activate the opened channel. After that the user can transmit and receive messages on the bus.
+    /// Channel will be active by default after [Device::open_ex] is called.
+    pub fn activate(&self) -> Result<()> {
+        self.channel.activate().map_err(self.context("activate"))
+    }
+
+    /// Deactivate the opened channel. The channel will stay in BUS OFF state until re-activation.
+    /// Any read/write call will raise a [BMStatus::BusOff] error immediately if the channel is deactivated.
+    pub fn deactivate(&self) -> Result<()> {
+        self.channel.deactivate().map_err(self.context("deactivate"))
+    }
+
+    /// Clear internal TX & RX message buffer of the opened channel.
+    pub fn clear_buffer(&self) -> Result<()> {
+        self.channel.clear_buffer().map_err(self.context("clear_buffer"))
+    }
+
+    /// Get current CAN status of the opened channel.
+    pub fn get_status_info(&self) -> Result<BMCanStatusInfo> {
+        self.channel.status().map_err(self.context("get_status_info"))
+    }
+
+    /// Get current value of tge high precision device timestamp, in microseconds.
+    pub fn get_timestamp(&self) -> Result<u32> {
+        self.channel.timestamp().map_err(self.context("get_timestamp"))
+    }
+
+    /// Set terminal resistor option of the opened channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `value`: Expected terminal resistor value, see [BMTerminalResistor] for details.
+    ///
+    /// returns: [Result<()>]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::enum_devices;
+    /// use busmust_sys::BMTerminalResistor;
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// channel.set_terminal_resistor(BMTerminalResistor::Enabled120).unwrap()
+    /// ```
+    pub fn set_terminal_resistor(&self, value: BMTerminalResistor) -> Result<()> {
+        self.channel.set_terminal_resistor(value).map_err(self.context("set_terminal_resistor"))
+    }
+
+    /// Set CAN mode option of the opened channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode`: Expected CAN mode, see [BMCanMode] for details.
+    ///
+    /// returns: [Result<()>]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::enum_devices;
+    /// use busmust_sys::BMCanMode;
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// channel.set_can_mode(BMCanMode::Normal).unwrap()
+    /// ```
+    pub fn set_can_mode(&self, mode: BMCanMode) -> Result<()> {
+        self.channel.set_can_mode(mode).map_err(self.context("set_can_mode"))
+    }
+
+    /// Write a message/event to the opened channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `message`: A [BMData] struct which represents the message.
+    /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely or `0` to send asynchronously.
+    ///
+    /// returns: The device local high precision timestamp in microseconds, when the message is physically transmitted on the CAN bus, or error.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::enum_devices;
+    /// use busmust_sys::{BMCanMessage, BMData};
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let msg = BMCanMessage::builder()
+    ///  .sid(0x123)
+    ///  .payload(vec![1, 2, 3, 4, 5, 6, 7, 8])
+    ///  .build();
+    ///
+    /// let data = BMData::builder()
+    ///  .can_message(msg)
+    ///  .build();
+    ///
+    /// channel.write(data, Some(1000)).unwrap();
+    /// ```
+    pub fn write(&self, message: BMData, timeout: Option<i32>) -> Result<u32> {
+        self.channel.write(&message, timeout.unwrap_or_default()).map_err(self.context("write"))
+    }
+
+    /// Write multiple message/event to the opened channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages`: An array of messages/events to be sent.
+    /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely or `0` to send asynchronously.
+    ///
+    /// returns: An array of device local high precision timestamps in microseconds, when the messages are physically transmitted, or error.
+    /// It is shorter than `messages` if not all messages could be sent.
+    ///
+    pub fn write_multiple(&self, messages: &[BMData], timeout: Option<i32>) -> Result<Vec<u32>> {
+        let mut timestamps = vec![0; messages.len()];
+        let n_messages = self.write_multiple_into(messages, &mut timestamps, timeout)?;
+
+        timestamps.truncate(n_messages);
+        Ok(timestamps)
+    }
+
+    /// Write multiple message/event to the opened channel without allocating.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages`: An array of messages/events to be sent.
+    /// * `timestamps`: Receives the transmit timestamp of each message, at most `timestamps.len()` messages are sent.
+    /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely or `0` to send asynchronously.
+    ///
+    /// returns: Number of messages sent, or error.
+    ///
+    pub fn write_multiple_into(&self, messages: &[BMData], timestamps: &mut [u32], timeout: Option<i32>) -> Result<usize> {
+        let len = messages.len().min(timestamps.len());
+        self.channel.write_multiple(&messages[..len], timeout.unwrap_or_default(), &mut timestamps[..len])
+            .map_err(self.context("write_multiple"))
     }
 
     /// Write single CAN message to the opened channel.
@@ -322,26 +941,22 @@ impl Device {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::enum_devices;
     /// use busmust_sys::BMCanMessage;
     ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
     /// let msg = BMCanMessage::builder()
     ///     .sid(0x123)
     ///     .payload(vec![1, 2, 3, 4, 5, 6, 7, 8])
     ///     .build();
-    /// device.write_can_message(msg, Some(1000)).unwrap();
+    /// channel.write_can_message(msg, Some(1000)).unwrap();
     /// ```
     pub fn write_can_message(&self, message: BMCanMessage, timeout: Option<i32>) -> Result<u32> {
-        unsafe {
-            let mut timestamp = 0;
-            cvt_r(BM_WriteCanMessage(
-                self.1.expect("not opened"),
-                &message,
-                0,
-                timeout.unwrap_or_default(),
-                &mut timestamp
-            )).and_then(|_| Ok(timestamp as u32))
-        }
+        self.channel.write_can(&message, timeout.unwrap_or_default()).map_err(self.context("write_can_message"))
     }
 
     /// Write multiple CAN messages to the opened channel.
@@ -352,37 +967,47 @@ impl Device {
     /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely or `0` to send asynchronously.
     ///
     /// returns: An array of device local high precision timestamps in microseconds, when the messages are physically transmitted on the CAN bus, or error.
+    /// It is shorter than `messages` if not all messages could be sent.
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::enum_devices;
     /// use busmust_sys::BMCanMessage;
     ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
     /// let msg = BMCanMessage::builder()
     ///     .sid(0x123)
     ///     .payload(vec![1, 2, 3, 4, 5, 6, 7, 8])
     ///     .build();
-    /// device.write_can_messages(vec![msg, msg, msg], Some(1000)).unwrap();
+    /// channel.write_can_messages(&[msg, msg, msg], Some(1000)).unwrap();
     /// ```
-    pub fn write_can_messages(&self, messages: Vec<BMCanMessage>, timeout: Option<i32>) -> Result<Vec<u32>> {
-        unsafe {
-            let mut n_messages = 0;
-
-            let mut timestamps = Vec::<u32>::with_capacity(messages.len());
-            timestamps.reserve_exact(messages.len());
-
-            cvt_r(BM_WriteMultipleCanMessage(
-                self.1.expect("not opened"),
-                messages.as_ptr(),
-                &mut n_messages,
-                0,
-                timeout.unwrap_or_default(),
-                timestamps.as_mut_ptr() as *mut c_int
-            )).and_then(|_| {
-                timestamps.truncate(n_messages as usize);
-                Ok(timestamps)
-            })
-        }
+    pub fn write_can_messages(&self, messages: &[BMCanMessage], timeout: Option<i32>) -> Result<Vec<u32>> {
+        let mut timestamps = vec![0; messages.len()];
+        let n_messages = self.write_can_messages_into(messages, &mut timestamps, timeout)?;
+
+        timestamps.truncate(n_messages);
+        Ok(timestamps)
+    }
+
+    /// Write multiple CAN messages to the opened channel without allocating.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages`: An array of messages to be sent.
+    /// * `timestamps`: Receives the transmit timestamp of each message, at most `timestamps.len()` messages are sent.
+    /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely or `0` to send asynchronously.
+    ///
+    /// returns: Number of messages sent, or error.
+    ///
+    pub fn write_can_messages_into(&self, messages: &[BMCanMessage], timestamps: &mut [u32],
+                                   timeout: Option<i32>) -> Result<usize> {
+        let len = messages.len().min(timestamps.len());
+        self.channel.write_can_multiple(&messages[..len], timeout.unwrap_or_default(), &mut timestamps[..len])
+            .map_err(self.context("write_can_messages"))
     }
 
     /// Read a message/event out of the opened channel.
@@ -393,11 +1018,7 @@ impl Device {
     /// returns: [`Result<BMData>`]
     ///
     pub fn read(&self) -> Result<BMData> {
-        unsafe {
-            let mut data = MaybeUninit::<BMData>::uninit();
-            cvt_r(BM_Read(self.1.expect("not opened"), data.as_mut_ptr()))
-                .and_then(|_| Ok(data.assume_init()))
-        }
+        self.channel.read().map_err(self.context("read"))
     }
 
     /// Read multiple messages/events out of the given channel.
@@ -407,58 +1028,76 @@ impl Device {
     /// * `n_messages`: Number of messages to read.
     /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely or `0` to receive asynchronously.
     ///
-    /// returns: [`Result<Vec<BMData>>`]
+    /// returns: [`Result<Vec<BMData>>`], empty if no message is received
     ///
     /// # Examples
     ///
-    /// ```
-    /// let messages = device.read_multiple(10, Some(1000)).unwrap();
+    /// ```no_run
+    /// use busmust::dmgr::enum_devices;
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let messages = channel.read_multiple(10, Some(1000)).unwrap();
     /// ```
     pub fn read_multiple(&self, n_messages: usize, timeout: Option<i32>) -> Result<Vec<BMData>> {
-        unsafe {
-            let mut read_messages = n_messages as c_int;
-            let mut messages = Vec::<BMData>::with_capacity(n_messages);
-
-            cvt_r(BM_ReadMultiple(self.1.expect("not opened"),
-                                  messages.as_mut_ptr(),
-                                  &mut read_messages,
-                                  timeout.unwrap_or_default()
-            )).and_then(|_| {
-                messages.truncate(read_messages as usize);
-                Ok(messages)
-            })
+        let mut messages = vec![BMData::builder().build(); n_messages];
+        let read_messages = self.read_multiple_into(&mut messages, timeout)?;
+
+        messages.truncate(read_messages);
+        Ok(messages)
+    }
+
+    /// Read multiple messages/events out of the given channel into a caller-provided buffer.
+    /// The source channel and timestamp of each message are found in its [BMData::header] and [BMData::timestamp].
+    ///
+    /// # Arguments
+    ///
+    /// * `messages`: Buffer receiving at most `messages.len()` messages.
+    /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely or `0` to receive asynchronously.
+    ///
+    /// returns: Number of messages read, `0` if no message is received
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::enum_devices;
+    /// use busmust_sys::BMData;
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let mut messages = [BMData::builder().build(); 10];
+    /// let n = channel.read_multiple_into(&mut messages, Some(1000)).unwrap();
+    /// for data in &messages[..n] {
+    ///     println!("{:?}", data.decode());
+    /// }
+    /// ```
+    pub fn read_multiple_into(&self, messages: &mut [BMData], timeout: Option<i32>) -> Result<usize> {
+        match self.channel.read_multiple(messages, timeout.unwrap_or_default()) {
+            Ok(n_messages) => Ok(n_messages),
+            Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) => Ok(0),
+            Err(e) => Err(self.context("read_multiple")(e))
         }
     }
 
     /// Read CAN message out of the opened channel.
-    /// This function is non-blocking, use [Device::wait_for_notification] to wait for a message first.
+    /// This function is non-blocking, use [OpenChannel::wait_for_notification] to wait for a message first.
     ///
     /// returns: [`Result<Option<BMCanMessage>>`]
     ///
     /// # Examples
     ///
-    /// ```
-    /// let msg = device.read_can_message().unwrap();
+    /// ```no_run
+    /// use busmust::dmgr::enum_devices;
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let msg = channel.read_can_message().unwrap();
     /// ```
     pub fn read_can_message(&self) -> Result<Option<BMCanMessage>> {
-        unsafe {
-            let mut message = MaybeUninit::<BMCanMessage>::uninit();
-            cvt_r(BM_ReadCanMessage(self.1.expect("not opened"),
-                message.as_mut_ptr(),
-                ptr::null_mut(),
-                ptr::null_mut()
-            )).map_or_else (
-                |e| match e {
-                    Error(status) => {
-                        if matches!(status, BMStatus::ReceiveBufferEmpty) {
-                            Ok(None)
-                        } else {
-                            Err(Error(status))
-                        }
-                    }
-                },
-                |_| Ok(Some(message.assume_init()))
-            )
+        match self.channel.read_can() {
+            Ok(message) => Ok(Some(message)),
+            Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) => Ok(None),
+            Err(e) => Err(self.context("read_can_message")(e))
         }
     }
 
@@ -469,115 +1108,469 @@ impl Device {
     /// * `n_messages`: Read at most `n_messages` messages.
     /// * `timeout`: Maximum time in `ms` to wait until all requested messages are read.
     ///
-    /// returns: [`Result<Vec<BMCanMessage>>`]
+    /// returns: [`Result<CanMessageBuffer>`] holding the messages with their source channels and timestamps
     ///
     /// # Examples
     ///
+    /// ```no_run
+    /// use busmust::dmgr::enum_devices;
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let messages = channel.read_can_messages(10, Some(1000)).unwrap();
+    /// for (message, source, timestamp) in messages.iter() {
+    ///     println!("{} {} {:X}", timestamp, source, message.id().raw());
+    /// }
     /// ```
-    /// let messages = device.read_can_messages(10, Some(1000)).unwrap();
-    /// ```
-    pub fn read_can_messages(&self, n_messages: usize, timeout: Option<i32>) -> Result<Vec<BMCanMessage>> {
-        unsafe {
-            let mut read_messages = n_messages as c_int;
-            let mut messages = Vec::<BMCanMessage>::with_capacity(n_messages);
-
-            cvt_r(BM_ReadMultipleCanMessage(self.1.expect("not opened"),
-                messages.as_mut_ptr(),
-                &mut read_messages,
-                timeout.unwrap_or_default(),
-                ptr::null_mut(),
-                ptr::null_mut()
-            )).and_then(|_| {
-                messages.truncate(read_messages as usize);
-                Ok(messages)
-            })
-        }
+    pub fn read_can_messages(&self, n_messages: usize, timeout: Option<i32>) -> Result<CanMessageBuffer> {
+        let mut buffer = CanMessageBuffer::with_capacity(n_messages);
+        self.read_can_messages_into(&mut buffer, timeout)?;
+        Ok(buffer)
     }
 
-    /// Wait for event/message notification on the opened channel.
+    /// Read multiple CAN messages from the opened channel into a reusable buffer, replacing its contents.
     ///
     /// # Arguments
     ///
-    /// * `timeout`: This function will block the current thread for at most `timeout` milliseconds if no notification is received.
+    /// * `buffer`: Buffer receiving at most [CanMessageBuffer::capacity] messages.
+    /// * `timeout`: Maximum time in `ms` to wait until all requested messages are read.
     ///
-    /// returns: bool
+    /// returns: Number of messages read, `0` if no message is received
     ///
     /// # Examples
     ///
+    /// ```no_run
+    /// use busmust::dmgr::{enum_devices, CanMessageBuffer};
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let mut buffer = CanMessageBuffer::with_capacity(64);
+    /// loop {
+    ///     channel.read_can_messages_into(&mut buffer, Some(100)).unwrap();
+    ///     for (message, _, timestamp) in buffer.iter() {
+    ///         println!("{} {:X}", timestamp, message.id().raw());
+    ///     }
+    /// }
     /// ```
-    /// device.wait_for_notification(Some(1000));
-    /// ```
-    pub fn wait_for_notification(&self, timeout: Option<u32>) -> bool {
-        unsafe {
-            let index = BM_WaitForNotifications(
-                &self.2.expect("not opened"),
-                1,
-                timeout.unwrap_or_default() as c_int
-            );
-            index >= 0
+    pub fn read_can_messages_into(&self, buffer: &mut CanMessageBuffer, timeout: Option<i32>) -> Result<usize> {
+        buffer.len = 0;
+
+        let result = self.channel.read_can_multiple(
+            &mut buffer.messages,
+            &mut buffer.channels,
+            &mut buffer.timestamps,
+            timeout.unwrap_or_default()
+        );
+
+        match result {
+            Ok(n_messages) => buffer.len = n_messages.min(buffer.capacity()),
+            Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) => {}
+            Err(e) => return Err(self.context("read_can_messages")(e))
         }
+
+        Ok(buffer.len)
     }
 
-    /// Set library log level. See [BMLogLevel].
+    /// Write a data block to the opened channel using the ISO-TP protocol implemented by the device.
     ///
     /// # Arguments
     ///
-    /// * `level`: Log level to set. See [BMLogLevel].
+    /// * `data`: The data block to be sent.
+    /// * `timeout`: Optional timeout in `ms` before any message segment is transmitted, for protocol timeouts see [IsotpConfig].
+    /// * `config`: ISO-TP configuration used by the transfer.
     ///
-    /// returns: ()
+    /// returns: [Result<()>]
     ///
     /// # Examples
     ///
-    /// ```
-    /// use busmust_sys::BMLogLevel;
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
     ///
-    /// device.set_log_level(BMLogLevel::Info);
+    /// use busmust::dmgr::enum_devices;
+    /// use busmust::isotp::IsotpConfig;
+    /// use busmust_sys::{BMCanMessage, BMData};
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let config = IsotpConfig::builder()
+    ///     .tester_template(BMData::builder().can_message(BMCanMessage::builder().sid(0x7E0).build()).build())
+    ///     .ecu_template(BMData::builder().can_message(BMCanMessage::builder().sid(0x7E8).build()).build())
+    ///     .padding(Some(0xCC))
+    ///     .build()
+    ///     .unwrap();
+    /// channel.write_isotp(&[0x10, 0x03], Some(1000), &config).unwrap();
     /// ```
-    pub fn set_log_level(&self, level: BMLogLevel) {
-        unsafe {
-            BM_SetLogLevel(level)
-        }
+    pub fn write_isotp(&self, data: &[u8], timeout: Option<i32>, config: &IsotpConfig) -> Result<()> {
+        self.write_isotp_with_progress(data, timeout, config, |_| {})
     }
 
-    /// Get library log level. See [BMLogLevel].
+    /// Same as [OpenChannel::write_isotp], but calls `progress` whenever the device reports transfer progress.
+    pub fn write_isotp_with_progress<F>(&self, data: &[u8], timeout: Option<i32>, config: &IsotpConfig, progress: F) -> Result<()>
+        where F: FnMut(&BMIsoTPStatus)
+    {
+        let channel = &self.channel;
+
+        with_progress(config, progress, |config| {
+            channel.write_isotp(data, timeout.unwrap_or_default(), config)
+        }).map_err(self.context("write_isotp"))
+    }
+
+    /// Read a data block from the opened channel using the ISO-TP protocol implemented by the device.
+    ///
+    /// # Arguments
     ///
-    /// returns: [BMLogLevel]
+    /// * `max_len`: Maximum length of the data block in bytes.
+    /// * `timeout`: Optional timeout in `ms`, use `-1` to wait indefinitely.
+    /// * `config`: ISO-TP configuration used by the transfer.
+    ///
+    /// returns: [`Result<Vec<u8>>`]
     ///
     /// # Examples
     ///
+    /// ```no_run
+    /// extern crate busmust;
+    /// extern crate busmust_sys;
+    ///
+    /// use busmust::dmgr::enum_devices;
+    /// use busmust::isotp::IsotpConfig;
+    /// use busmust_sys::{BMCanMessage, BMData};
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// let config = IsotpConfig::builder()
+    ///     .tester_template(BMData::builder().can_message(BMCanMessage::builder().sid(0x7E0).build()).build())
+    ///     .ecu_template(BMData::builder().can_message(BMCanMessage::builder().sid(0x7E8).build()).build())
+    ///     .build()
+    ///     .unwrap();
+    /// let response = channel.read_isotp(4095, Some(1000), &config).unwrap();
     /// ```
-    /// device.get_log_level()
+    pub fn read_isotp(&self, max_len: usize, timeout: Option<i32>, config: &IsotpConfig) -> Result<Vec<u8>> {
+        self.read_isotp_with_progress(max_len, timeout, config, |_| {})
+    }
+
+    /// Same as [OpenChannel::read_isotp], but calls `progress` whenever the device reports transfer progress.
+    pub fn read_isotp_with_progress<F>(&self, max_len: usize, timeout: Option<i32>, config: &IsotpConfig, progress: F) -> Result<Vec<u8>>
+        where F: FnMut(&BMIsoTPStatus)
+    {
+        let channel = &self.channel;
+        let mut data = vec![0u8; max_len];
+
+        let n_bytes = with_progress(config, progress, |config| {
+            channel.read_isotp(&mut data, timeout.unwrap_or_default(), config)
+        }).map_err(self.context("read_isotp"))?;
+
+        data.truncate(n_bytes);
+        Ok(data)
+    }
+
+    /// Wait for event/message notification on the opened channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: This function will block the current thread for at most `timeout` milliseconds if no notification is received.
+    ///
+    /// returns: bool
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use busmust::dmgr::enum_devices;
+    ///
+    /// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+    /// channel.wait_for_notification(Some(1000));
     /// ```
-    pub fn get_log_level(&self) -> BMLogLevel {
-        unsafe {
-            BM_GetLogLevel()
+    pub fn wait_for_notification(&self, timeout: Option<u32>) -> bool {
+        self.channel.wait_for_notification(timeout.unwrap_or_default() as i32)
+    }
+
+    /// Attach the operation and the channel port to an error.
+    fn context(&self, operation: &'static str) -> impl Fn(Error) -> Error {
+        let port = self.device.port();
+        move |e| e.context(operation, Some(port))
+    }
+}
+
+/// Waits for notifications of many opened channels at once, i.e. all ports of a multi-port device,
+/// so a single thread could service all of them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use busmust::dmgr::{enum_devices, Selector};
+///
+/// let channels: Vec<_> = enum_devices().unwrap().map(|device| device.open_ex().unwrap()).collect();
+///
+/// let mut selector = Selector::new();
+/// for channel in &channels {
+///     selector.register(channel);
+/// }
+///
+/// loop {
+///     for token in selector.select(Some(1000)) {
+///         while let Some(message) = selector.channel(token).read_can_message().unwrap() {
+///             println!("port {}: {:X}", channels[token].device().port(), message.id().raw());
+///         }
+///     }
+/// }
+/// ```
+#[derive(Default)]
+pub struct Selector<'a> {
+    /// Registered channels indexed by token, `None` once deregistered
+    channels: Vec<Option<&'a OpenChannel>>
+}
+
+impl<'a> Selector<'a> {
+    pub fn new() -> Selector<'a> {
+        Selector { channels: Vec::new() }
+    }
+
+    /// Register a channel.
+    ///
+    /// returns: Token identifying the channel in results of [Selector::select], tokens are assigned in registration order starting from 0.
+    pub fn register(&mut self, channel: &'a OpenChannel) -> usize {
+        self.channels.push(Some(channel));
+        self.channels.len() - 1
+    }
+
+    /// Deregister the channel with the given token, tokens of the other channels stay valid and are never reused.
+    ///
+    /// returns: The channel, `None` if no channel is registered with the token.
+    pub fn deregister(&mut self, token: usize) -> Option<&'a OpenChannel> {
+        self.channels.get_mut(token).and_then(Option::take)
+    }
+
+    /// Get the channel registered with the given token.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no channel is registered with the token.
+    pub fn channel(&self, token: usize) -> &'a OpenChannel {
+        self.channels[token].expect("no channel registered with the token")
+    }
+
+    /// Number of registered channels.
+    pub fn len(&self) -> usize {
+        self.channels.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait for event/message notification on any of the registered channels.
+    ///
+    /// If all channels are opened by the Busmust library a single [BM_WaitForNotifications] call waits for all of them,
+    /// otherwise (i.e. for a [super::backend::VirtualBus]) the channels are polled every millisecond.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: This function will block the current thread for at most `timeout` milliseconds if no notification is received.
+    ///
+    /// returns: Tokens of all channels which received a notification in ascending order, empty if the timeout expired.
+    pub fn select(&self, timeout: Option<u32>) -> Vec<usize> {
+        let timeout = timeout.unwrap_or_default();
+        let registered: Vec<(usize, &OpenChannel)> = self.channels.iter().enumerate()
+            .filter_map(|(token, channel)| channel.map(|channel| (token, channel)))
+            .collect();
+        let handles: Option<Vec<*const c_void>> = registered.iter()
+            .map(|(_, channel)| channel.channel.notification_handle())
+            .collect();
+
+        match handles {
+            Some(handles) => select_handles(&handles, timeout).into_iter().map(|index| registered[index].0).collect(),
+            None => select_polling(&registered, timeout)
         }
     }
+}
 
-    /// Get platform-independent notification handle for opened channel
-    fn get_notification(&mut self) -> Result<()> {
-        unsafe {
-            let mut handle: *mut c_void = ptr::null_mut();
-            let handle_ptr: *mut *mut c_void = &mut handle;
+/// Poll the channels until any of them is ready, returning the tokens of all ready ones.
+fn select_polling(channels: &[(usize, &OpenChannel)], timeout: u32) -> Vec<usize> {
+    let deadline = Instant::now() + Duration::from_millis(timeout as u64);
 
-            let result = cvt_r(BM_GetNotification(
-                self.1.expect("not opened"), handle_ptr));
-            self.2 = Some(handle.cast_const());
+    loop {
+        let ready: Vec<usize> = channels.iter()
+            .filter(|(_, channel)| channel.wait_for_notification(Some(0)))
+            .map(|&(token, _)| token)
+            .collect();
 
-            result
+        let now = Instant::now();
+        if !ready.is_empty() || now >= deadline {
+            return ready;
         }
+        thread::sleep((deadline - now).min(Duration::from_millis(1)));
     }
 }
 
+/// Wait for any of the notification handles, then collect all other handles which are ready as well.
+fn select_handles(handles: &[*const c_void], timeout: u32) -> Vec<usize> {
+    let mut waiting: Vec<usize> = (0..handles.len()).collect();
+    let mut ready = Vec::new();
+    let mut timeout = timeout as c_int;
+
+    while !waiting.is_empty() {
+        let pending: Vec<*const c_void> = waiting.iter().map(|&token| handles[token]).collect();
+        let index = unsafe { BM_WaitForNotifications(pending.as_ptr(), pending.len() as c_int, timeout) };
+        if index < 0 || index as usize >= waiting.len() {
+            break;
+        }
+
+        ready.push(waiting.remove(index as usize));
+        timeout = 0;
+    }
+
+    ready.sort_unstable();
+    ready
+}
+
+/// Reusable buffer of CAN messages read by [OpenChannel::read_can_messages_into],
+/// together with the source channel and receive timestamp of each message.
+#[derive(Clone)]
+pub struct CanMessageBuffer {
+    messages: Vec<BMCanMessage>,
+    channels: Vec<u32>,
+    timestamps: Vec<u32>,
+    len: usize
+}
+
+impl CanMessageBuffer {
+    /// Create an empty buffer able to hold `capacity` messages.
+    pub fn with_capacity(capacity: usize) -> CanMessageBuffer {
+        CanMessageBuffer {
+            messages: vec![BMCanMessage::builder().build(); capacity],
+            channels: vec![0; capacity],
+            timestamps: vec![0; capacity],
+            len: 0
+        }
+    }
+
+    /// Maximum number of messages read at once.
+    pub fn capacity(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Number of messages read.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Get the messages read.
+    pub fn messages(&self) -> &[BMCanMessage] {
+        &self.messages[..self.len]
+    }
+
+    /// Get the source channel of each message.
+    pub fn channels(&self) -> &[u32] {
+        &self.channels[..self.len]
+    }
+
+    /// Get the 32-bit device local receive timestamp in microseconds of each message.
+    pub fn timestamps(&self) -> &[u32] {
+        &self.timestamps[..self.len]
+    }
+
+    /// Iterate over the messages read, yielding `(message, channel, timestamp)`.
+    pub fn iter(&self) -> impl Iterator<Item = (&BMCanMessage, u32, u32)> {
+        self.messages().iter()
+            .zip(self.channels().iter())
+            .zip(self.timestamps().iter())
+            .map(|((message, &channel), &timestamp)| (message, channel, timestamp))
+    }
+}
+
+impl Drop for OpenChannel {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.channel.close();
+        }
+    }
+}
+
+/// Progress callback state passed to the device through `callback_user_arg`.
+struct Progress<F> {
+    callback: F,
+    panic: Option<Box<dyn Any + Send>>
+}
+
+extern "C" fn progress_trampoline<F: FnMut(&BMIsoTPStatus)>(status: *const BMIsoTPStatus, arg: *const c_void) {
+    if status.is_null() || arg.is_null() {
+        return;
+    }
+
+    unsafe {
+        let progress = &mut *(arg as *mut Progress<F>);
+        if progress.panic.is_some() {
+            return;
+        }
+
+        // Unwinding into the library is undefined behaviour, re-raise the panic once the call returns.
+        let callback = &mut progress.callback;
+        if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| callback(&*status))) {
+            progress.panic = Some(e);
+        }
+    }
+}
+
+/// Call `f` with a raw ISO-TP configuration whose callback forwards to `callback`.
+fn with_progress<F, R, G>(config: &IsotpConfig, callback: F, f: G) -> R
+    where F: FnMut(&BMIsoTPStatus),
+          G: FnOnce(&BMIsotpConfig) -> R
+{
+    let mut progress = Progress { callback, panic: None };
+    let mut raw = config.to_raw();
+    raw.callback = Some(progress_trampoline::<F>);
+    raw.callback_user_arg = &mut progress as *mut Progress<F> as *const c_void;
+
+    let result = f(&raw);
+
+    if let Some(e) = progress.panic.take() {
+        panic::resume_unwind(e);
+    }
+    result
+}
+
 pub struct Devices {
     current: usize,
     count: usize,
-    device_infos: Vec<BMChannelInfo>
+    device_infos: Vec<BMChannelInfo>,
+    backend: Arc<dyn Backend>
 }
 
 impl Devices {
-    fn new(count: usize, infos: Vec<BMChannelInfo>) -> Self {
-        Devices { count, current: 0, device_infos: infos }
+    fn new(count: usize, infos: Vec<BMChannelInfo>, backend: Arc<dyn Backend>) -> Self {
+        Devices { count, current: 0, device_infos: infos, backend }
+    }
+
+    /// Get the remaining channels matching the query, ordered by serial number and port index
+    /// so the selection does not depend on the enumeration order.
+    pub fn select(self, query: &DeviceQuery) -> Vec<Device> {
+        let mut devices: Vec<Device> = self.filter(|device| query.matches(device)).collect();
+        devices.sort_by_key(|device| (device.serial_number(), device.port()));
+        devices
+    }
+
+    /// Get the first remaining channel matching the query, in the order of [Devices::select].
+    pub fn find(self, query: &DeviceQuery) -> Option<Device> {
+        self.select(query).into_iter().next()
+    }
+
+    /// Group the remaining channels by the serial number of their device, ordered by serial number.
+    pub fn physical_devices(self) -> Vec<PhysicalDevice> {
+        let mut devices: Vec<PhysicalDevice> = Vec::new();
+
+        for device in self.select(&DeviceQuery::new()) {
+            let serial_number = device.serial_number();
+            match devices.last_mut() {
+                Some(last) if last.serial_number == serial_number => last.channels.push(device),
+                _ => devices.push(PhysicalDevice { serial_number, channels: vec![device] })
+            }
+        }
+
+        devices
     }
 }
 
@@ -586,8 +1579,7 @@ impl Iterator for Devices {
 
     fn next(&mut self) -> Option<Device> {
         if self.current < self.count {
-            let device = Device(self.device_infos[self.current],
-                                None, None);
+            let device = Device(self.device_infos[self.current], self.backend.clone());
             self.current += 1;
 
             Some(device)
@@ -597,14 +1589,695 @@ impl Iterator for Devices {
     }
 }
 
+/// Enumerate all connected Busmust devices, initializing the library for as long as any of them is alive.
+/// See [BusMust::enum_devices].
 pub fn enum_devices() -> Result<Devices> {
-    let mut infos = Vec::with_capacity(64);
-    let mut count: c_int = infos.capacity() as c_int;
+    BusMust::new()?.enum_devices()
+}
 
-    unsafe {
-        cvt_r(BM_Enumerate(infos.as_mut_ptr(), &mut count)).unwrap();
-        infos.set_len(count as usize);
+/// Enumerate all device channels provided by the given backend, i.e. a [super::backend::VirtualBus].
+pub fn enum_devices_with(backend: Arc<dyn Backend>) -> Result<Devices> {
+    let infos = backend.enumerate().map_err(|e| e.context("enumerate", None))?;
+
+    Ok(Devices::new(infos.len(), infos, backend))
+}
+
+/// Change of the connected devices reported by [DeviceMonitor].
+pub enum DeviceEvent {
+    /// A device channel has been plugged in
+    Added(Device),
+    /// A device channel has been unplugged, channels opened on it are no longer usable
+    Removed(Device),
+    /// A channel registered using [DeviceMonitor::reopen] has been opened after being plugged in
+    Reopened(OpenChannel),
+    /// A channel registered using [DeviceMonitor::reopen] could not be opened after being plugged in
+    ReopenFailed(Device, Error)
+}
+
+/// Identity of a device channel across enumerations.
+fn channel_key(info: &BMChannelInfo) -> ([u8; 16], u16) {
+    (info.sn, info.port)
+}
+
+/// Compare two enumerations of device channels by serial number and port.
+///
+/// The order of the enumerations does not matter, channels listed repeatedly are reported once.
+///
+/// returns: Channels found only in `current` (added) and channels found only in `previous` (removed).
+///
+/// # Examples
+///
+/// ```
+/// use busmust::backend::{Backend, VirtualBus};
+/// use busmust::dmgr::diff_devices;
+///
+/// let previous = VirtualBus::new(2).enumerate().unwrap();
+/// let current = VirtualBus::new(3).enumerate().unwrap();
+///
+/// let (added, removed) = diff_devices(&previous, &current);
+/// assert_eq!(added.len(), 1);
+/// assert_eq!(added[0].port, 2);
+/// assert!(removed.is_empty());
+/// ```
+pub fn diff_devices(previous: &[BMChannelInfo], current: &[BMChannelInfo]) -> (Vec<BMChannelInfo>, Vec<BMChannelInfo>) {
+    let contains = |infos: &[BMChannelInfo], info: &BMChannelInfo| {
+        infos.iter().any(|other| channel_key(other) == channel_key(info))
+    };
+    let missing = |infos: &[BMChannelInfo], others: &[BMChannelInfo]| {
+        let mut missing: Vec<BMChannelInfo> = Vec::new();
+        for info in infos {
+            if !contains(others, info) && !contains(&missing, info) {
+                missing.push(*info);
+            }
+        }
+        missing
+    };
+
+    (missing(current, previous), missing(previous, current))
+}
+
+/// Hot-plug monitor periodically enumerating device channels and reporting added and removed ones.
+///
+/// Channels are identified by serial number and port. The first poll reports all connected channels as added.
+/// Channels registered using [DeviceMonitor::reopen] are opened with their configuration whenever they appear.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use busmust::dmgr::{DeviceEvent, DeviceMonitor, OpenOptions};
+///
+/// let (monitor, events) = DeviceMonitor::new().unwrap()
+///     .interval(Duration::from_millis(500))
+///     .reopen("0123456789", 0, OpenOptions::new())
+///     .spawn_channel();
+///
+/// for event in events {
+///     match event {
+///         DeviceEvent::Added(device) => println!("added {} port {}", device.serial_number(), device.port()),
+///         DeviceEvent::Removed(device) => println!("removed {} port {}", device.serial_number(), device.port()),
+///         DeviceEvent::Reopened(channel) => println!("reopened port {}", channel.device().port()),
+///         DeviceEvent::ReopenFailed(_, e) => println!("{}", e)
+///     }
+/// }
+/// ```
+pub struct DeviceMonitor {
+    backend: Arc<dyn Backend>,
+    interval: Duration,
+    known: Vec<BMChannelInfo>,
+    reopen: Vec<(String, u16, OpenOptions)>
+}
+
+impl DeviceMonitor {
+    /// Monitor connected Busmust devices, initializing the library for as long as the monitor is alive.
+    pub fn new() -> Result<DeviceMonitor> {
+        Ok(DeviceMonitor::with_backend(Arc::new(Native::new(BusMust::new()?))))
+    }
+
+    /// Monitor device channels provided by the given backend, i.e. a [super::backend::VirtualBus].
+    pub fn with_backend(backend: Arc<dyn Backend>) -> DeviceMonitor {
+        DeviceMonitor { backend, interval: Duration::from_secs(1), known: Vec::new(), reopen: Vec::new() }
+    }
+
+    /// Set the enumeration interval of [DeviceMonitor::spawn], 1 s by default.
+    pub fn interval(mut self, interval: Duration) -> DeviceMonitor {
+        self.interval = interval;
+        self
+    }
+
+    /// Open the channel with the given serial number and port using `options` whenever it appears.
+    pub fn reopen(mut self, serial_number: &str, port: u16, options: OpenOptions) -> DeviceMonitor {
+        self.reopen.push((serial_number.to_string(), port, options));
+        self
+    }
+
+    /// Enumerate device channels once, returning the changes since the previous poll.
+    pub fn poll(&mut self) -> Result<Vec<DeviceEvent>> {
+        let current = self.backend.enumerate().map_err(|e| e.context("enumerate", None))?;
+        let (added, removed) = diff_devices(&self.known, &current);
+        self.known = current;
+
+        let mut events: Vec<DeviceEvent> = removed.into_iter()
+            .map(|info| DeviceEvent::Removed(Device(info, self.backend.clone())))
+            .collect();
+
+        for info in added {
+            let device = Device(info, self.backend.clone());
+            let options = self.reopen.iter()
+                .find(|(sn, port, _)| *sn == device.serial_number() && *port == device.port())
+                .map(|(_, _, options)| options.clone());
+
+            events.push(DeviceEvent::Added(device.clone()));
+            if let Some(options) = options {
+                events.push(match device.open_with(&options) {
+                    Ok(channel) => DeviceEvent::Reopened(channel),
+                    Err(e) => DeviceEvent::ReopenFailed(device, e)
+                });
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Poll on a background thread every [DeviceMonitor::interval], passing events to `callback`.
+    /// Enumeration errors are ignored and retried at the next interval.
+    ///
+    /// returns: Handle stopping the thread when dropped.
+    pub fn spawn<F>(self, mut callback: F) -> MonitorHandle
+        where F: FnMut(DeviceEvent) + Send + 'static
+    {
+        self.spawn_with(move |event| {
+            callback(event);
+            true
+        })
+    }
+
+    /// Poll on a background thread every [DeviceMonitor::interval], sending events to the returned receiver.
+    /// The thread stops when the handle or the receiver is dropped.
+    pub fn spawn_channel(self) -> (MonitorHandle, Receiver<DeviceEvent>) {
+        let (tx, rx) = mpsc::channel();
+        (self.spawn_with(move |event| tx.send(event).is_ok()), rx)
+    }
+
+    /// Poll on a background thread until stopped or `callback` returns `false`.
+    fn spawn_with<F>(mut self, mut callback: F) -> MonitorHandle
+        where F: FnMut(DeviceEvent) -> bool + Send + 'static
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        let thread = thread::spawn(move || {
+            while !thread_stop.load(Ordering::SeqCst) {
+                if let Ok(events) = self.poll() {
+                    for event in events {
+                        if !callback(event) {
+                            return;
+                        }
+                    }
+                }
+                thread::park_timeout(self.interval);
+            }
+        });
+
+        MonitorHandle { stop, thread: Some(thread) }
+    }
+}
+
+/// Background thread of a [DeviceMonitor], stopped when dropped.
+pub struct MonitorHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl MonitorHandle {
+    /// Stop the monitor thread and wait for it to exit.
+    pub fn stop(self) {}
+}
+
+impl Drop for MonitorHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread::{self, JoinHandle};
+    use backend::{Backend, VirtualBus};
+    use super::*;
+
+    fn message(id: u16) -> BMCanMessage {
+        BMCanMessage::builder().sid(id).payload(vec![id as u8; 8]).build()
+    }
+
+    /// Open the first two channels of a virtual bus.
+    fn open_pair(bus: &VirtualBus) -> (OpenChannel, OpenChannel) {
+        let mut devices = bus.enum_devices().unwrap();
+        let tx = devices.next().unwrap().open_ex().unwrap();
+        let rx = devices.next().unwrap().open_ex().unwrap();
+        (tx, rx)
+    }
+
+    #[test]
+    fn read_multiple_partial_batch() {
+        let bus = VirtualBus::new(2);
+        let (tx, rx) = open_pair(&bus);
+
+        let timestamps = tx.write_can_messages(&[message(1), message(2), message(3)], Some(100)).unwrap();
+        assert_eq!(timestamps.len(), 3);
+
+        let data = rx.read_multiple(10, Some(0)).unwrap();
+        assert_eq!(data.len(), 3);
+        assert_eq!(data[2].timestamp, timestamps[2]);
+        assert!(rx.read_multiple(10, Some(0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_can_messages_partial_batch() {
+        let bus = VirtualBus::new(2);
+        let (tx, rx) = open_pair(&bus);
+
+        let timestamps = tx.write_can_messages(&[message(1), message(2)], Some(100)).unwrap();
+
+        let mut buffer = CanMessageBuffer::with_capacity(8);
+        assert_eq!(rx.read_can_messages_into(&mut buffer, Some(0)).unwrap(), 2);
+
+        let received: Vec<(u32, u32, u32)> = buffer.iter()
+            .map(|(message, channel, timestamp)| (message.id().raw(), channel, timestamp))
+            .collect();
+        assert_eq!(received, vec![(1, 1, timestamps[0]), (2, 1, timestamps[1])]);
+
+        assert_eq!(rx.read_can_messages_into(&mut buffer, Some(0)).unwrap(), 0);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn write_multiple_without_ack() {
+        let bus = VirtualBus::new(1);
+        let tx = bus.enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+
+        let error = tx.write_can_messages(&[message(1), message(2)], Some(100)).unwrap_err();
+        assert!(error.is(BMStatus::BusTimeout));
+    }
+
+    /// Open all channels of a virtual bus with `channels` channels.
+    fn open_all(channels: u16) -> Vec<OpenChannel> {
+        VirtualBus::new(channels).enum_devices().unwrap().map(|device| device.open_ex().unwrap()).collect()
+    }
+
+    #[test]
+    fn selector_reports_ready_channels() {
+        let channels = open_all(4);
+        let mut selector = Selector::new();
+        let tokens: Vec<usize> = channels[1..].iter().map(|channel| selector.register(channel)).collect();
+        assert_eq!(tokens, vec![0, 1, 2]);
+        assert_eq!(selector.len(), 3);
+
+        // Every channel receives the frame.
+        channels[0].write_can_message(message(1), Some(100)).unwrap();
+        assert_eq!(selector.select(Some(100)), vec![0, 1, 2]);
+
+        channels[1].clear_buffer().unwrap();
+        channels[3].clear_buffer().unwrap();
+        assert_eq!(selector.select(Some(100)), vec![1]);
+        assert_eq!(selector.channel(1).read_can_message().unwrap().unwrap().id().raw(), 1);
+    }
+
+    #[test]
+    fn selector_waits() {
+        let mut channels = open_all(3);
+        let tx = channels.remove(0);
+        let mut selector = Selector::new();
+        for channel in &channels {
+            selector.register(channel);
+        }
+
+        let started = Instant::now();
+        assert!(selector.select(Some(30)).is_empty());
+        assert!(started.elapsed() >= Duration::from_millis(30));
+
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            tx.write_can_message(message(1), Some(100)).unwrap();
+            tx
+        });
+
+        let started = Instant::now();
+        assert_eq!(selector.select(Some(5000)), vec![0, 1]);
+        assert!(started.elapsed() < Duration::from_millis(5000));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn selector_deregister() {
+        let channels = open_all(4);
+        let mut selector = Selector::new();
+        for channel in &channels[1..] {
+            selector.register(channel);
+        }
+
+        assert!(selector.deregister(1).is_some());
+        assert!(selector.deregister(1).is_none());
+        assert!(selector.deregister(5).is_none());
+        assert_eq!(selector.len(), 2);
+
+        // Remaining channels keep their tokens, new ones get fresh tokens.
+        channels[0].write_can_message(message(1), Some(100)).unwrap();
+        assert_eq!(selector.select(Some(100)), vec![0, 2]);
+        assert_eq!(selector.register(&channels[2]), 3);
+        assert_eq!(selector.select(Some(100)), vec![0, 2, 3]);
+
+        for token in [0, 2, 3] {
+            selector.deregister(token);
+        }
+        assert!(selector.is_empty());
+        assert!(selector.select(Some(10)).is_empty());
+    }
+
+    /// Get port 0 of the bus, reporting the given capabilities instead of CAN and CAN FD.
+    fn device_with_caps(bus: &VirtualBus, caps: BMCapability) -> Device {
+        let mut info = bus.enumerate().unwrap()[0];
+        info.cap = caps.bits();
+        Device(info, Arc::new(bus.clone()))
+    }
+
+    #[test]
+    fn open_with_validates_capabilities() {
+        let bus = VirtualBus::new(1);
+        let can = device_with_caps(&bus, BMCapability::CAN);
+        let invalid = |options: OpenOptions| options.open(&can).err().unwrap().is(BMStatus::InvalidParameterValue);
+
+        // CAN FD data bitrate and FD TX tasks are refused, unless the channel is opened in classic mode.
+        assert!(invalid(OpenOptions::new().bitrate(BMBitrate::FD_500K_2M)));
+        assert!(invalid(OpenOptions::new().mode(BMCanMode::ListenOnly).bitrate(BMBitrate::FD_500K_2M)));
+        assert!(OpenOptions::new().mode(BMCanMode::Classic).bitrate(BMBitrate::FD_500K_2M).validate(can.caps()).is_ok());
+
+        let fd_task = TxTask::builder().sid(0x123).fdf(true).payload(vec![0; 12]).build().unwrap();
+        assert!(invalid(OpenOptions::new().tx_tasks(vec![fd_task.clone()])));
+
+        let filter = BMRxFilter::builder().standard_id(0x123, 0x7FF).build();
+        assert!(invalid(OpenOptions::new().rx_filters(vec![filter; 3])));
+
+        // Devices without CAN cannot be opened at all.
+        let lin = device_with_caps(&bus, BMCapability::LIN);
+        assert!(OpenOptions::new().open(&lin).err().unwrap().is(BMStatus::InvalidOperation));
+
+        // Nothing has been attached to the bus by the refused attempts.
+        let channel = OpenOptions::new().bitrate(BMBitrate::CAN_500K).rx_filters(vec![filter; 2]).open(&can).unwrap();
+        channel.close().unwrap();
+
+        let fd = device_with_caps(&bus, BMCapability::CAN | BMCapability::CAN_FD);
+        assert!(OpenOptions::new().tx_tasks(vec![fd_task]).validate(fd.caps()).is_ok());
+        let channel = OpenOptions::new().bitrate(BMBitrate::FD_500K_2M).open(&fd).unwrap();
+        assert_eq!(channel.device().caps(), BMCapability::CAN | BMCapability::CAN_FD);
+    }
+
+    /// Keep transmitting `message` from port 1 of the bus, acknowledged by port 2, until the returned flag is set.
+    fn transmit_until(bus: &VirtualBus, bitrate: BMBitrate, message: BMCanMessage) -> (Arc<AtomicBool>, JoinHandle<()>) {
+        let mut devices = bus.enum_devices().unwrap().skip(1);
+        let options = OpenOptions::new().bitrate(bitrate);
+        let tx = options.open(&devices.next().unwrap()).unwrap();
+        let ack = options.open(&devices.next().unwrap()).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let handle = thread::spawn(move || {
+            while !flag.load(Ordering::SeqCst) {
+                let _ = tx.write_can_message(message, Some(10));
+                ack.clear_buffer().unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        (stop, handle)
+    }
+
+    /// Detect the bitrate of port 0 while transmitting `message` at `bitrate`.
+    fn detect(bus: &VirtualBus, device: &Device, candidates: &[BMBitrate], bitrate: BMBitrate,
+              message: BMCanMessage) -> Option<DetectedBitrate> {
+        let (stop, handle) = transmit_until(bus, bitrate, message);
+        let detected = device.detect_bitrate(candidates, Duration::from_millis(50)).unwrap();
+        stop.store(true, Ordering::SeqCst);
+        handle.join().unwrap();
+        detected
+    }
+
+    #[test]
+    fn detect_bitrate() {
+        let bus = VirtualBus::new(3);
+        let device = bus.enum_devices().unwrap().next().unwrap();
+        let candidates = [BMBitrate::CAN_1M, BMBitrate::CAN_250K, BMBitrate::CAN_500K, BMBitrate::CAN_125K];
+
+        let detected = detect(&bus, &device, &candidates, BMBitrate::CAN_250K, message(1)).unwrap();
+        assert_eq!(detected.bitrate.n_bitrate, 250);
+        assert_eq!(detected.data_bitrate, None);
+
+        assert!(detect(&bus, &device, &candidates[2..], BMBitrate::CAN_250K, message(1)).is_none());
+        assert!(device.detect_bitrate(&candidates, Duration::from_millis(10)).unwrap().is_none());
+    }
+
+    #[test]
+    fn detect_bitrate_reports_observed_data_bitrate() {
+        let bus = VirtualBus::new(3);
+        let device = bus.enum_devices().unwrap().next().unwrap();
+        let candidates = [BMBitrate::FD_1M_5M, BMBitrate::FD_500K_2M];
+        let fd = |brs| BMCanMessage::builder().sid(1).fdf(true).brs(brs).payload(vec![0; 16]).build();
+
+        let detected = detect(&bus, &device, &candidates, BMBitrate::FD_500K_2M, fd(false)).unwrap();
+        assert_eq!(detected.bitrate.n_bitrate, 500);
+        assert_eq!(detected.data_bitrate, None);
+
+        let detected = detect(&bus, &device, &candidates, BMBitrate::FD_500K_2M, fd(true)).unwrap();
+        assert_eq!(detected.data_bitrate, Some(2000));
     }
 
-    Ok(Devices::new(count as usize, infos))
+    #[test]
+    fn detect_bitrate_skips_unsupported_candidates() {
+        let bus = VirtualBus::new(3);
+        let device = device_with_caps(&bus, BMCapability::CAN);
+
+        let candidates = [BMBitrate::FD_500K_2M, BMBitrate::CAN_500K];
+        let detected = detect(&bus, &device, &candidates, BMBitrate::CAN_500K, message(1)).unwrap();
+        assert_eq!(detected.bitrate.n_bitrate, 500);
+
+        // Nothing can be opened at all.
+        let error = device.detect_bitrate(&candidates[..1], Duration::from_millis(10)).unwrap_err();
+        assert!(error.is(BMStatus::InvalidParameterValue));
+
+        let _channel = device.open_ex().unwrap();
+        let error = device.detect_bitrate(&candidates, Duration::from_millis(10)).unwrap_err();
+        assert!(error.is(BMStatus::HardwareInUse));
+    }
+
+    fn info(sn: &[u8], port: u16) -> BMChannelInfo {
+        let mut info: BMChannelInfo = unsafe { mem::zeroed() };
+        info.sn[..sn.len()].copy_from_slice(sn);
+        info.port = port;
+        info
+    }
+
+    /// Channel of a device with the given serial number, unique ID, name, USB IDs, capabilities and firmware version.
+    fn device_info(sn: &[u8], port: u16, uid: &[u8], name: &str, ids: (u16, u16), caps: BMCapability,
+                   version: [u8; 4]) -> BMChannelInfo {
+        let mut info = info(sn, port);
+        info.uid[..uid.len()].copy_from_slice(uid);
+        for (c, b) in info.name.iter_mut().zip(name.bytes()) {
+            *c = b as c_char;
+        }
+        info.vid = ids.0;
+        info.pid = ids.1;
+        info.cap = caps.bits();
+        info.version = version;
+        info
+    }
+
+    /// Enumerate a dual-port CAN FD device (enumerated in reverse port order), a CAN device and a LIN device.
+    fn devices() -> Devices {
+        let fd = BMCapability::CAN | BMCapability::CAN_FD;
+        let infos = vec![
+            device_info(b"B200", 1, b"UID-B", "Busmust BM-CANFD-X2", (0x1234, 0x0002), fd, [2, 1, 0, 7]),
+            device_info(b"C300", 0, b"UID-C", "Other LIN", (0x5678, 0x0001), BMCapability::LIN, [0, 9, 0, 0]),
+            device_info(b"A100", 0, b"UID-A", "Busmust BM-CAN-X1", (0x1234, 0x0001), BMCapability::CAN, [1, 0, 3, 0]),
+            device_info(b"B200", 0, b"UID-B", "Busmust BM-CANFD-X2", (0x1234, 0x0002), fd, [2, 1, 0, 7])
+        ];
+        VirtualBus::with_channels(infos).enum_devices().unwrap()
+    }
+
+    /// Select channels, returning their serial numbers and ports.
+    fn select(query: DeviceQuery) -> Vec<(String, u16)> {
+        devices().select(&query).iter().map(|device| (device.serial_number(), device.port())).collect()
+    }
+
+    fn channels(list: &[(&str, u16)]) -> Vec<(String, u16)> {
+        list.iter().map(|&(sn, port)| (sn.to_string(), port)).collect()
+    }
+
+    #[test]
+    fn device_query_criteria() {
+        assert_eq!(select(DeviceQuery::new()), channels(&[("A100", 0), ("B200", 0), ("B200", 1), ("C300", 0)]));
+        assert_eq!(select(DeviceQuery::new().serial_number("B200")), channels(&[("B200", 0), ("B200", 1)]));
+        assert_eq!(select(DeviceQuery::new().unique_id("UID-A")), channels(&[("A100", 0)]));
+        assert_eq!(select(DeviceQuery::new().name("CANFD")), channels(&[("B200", 0), ("B200", 1)]));
+        assert_eq!(select(DeviceQuery::new().port(1)), channels(&[("B200", 1)]));
+        assert_eq!(select(DeviceQuery::new().vendor_id(0x5678)), channels(&[("C300", 0)]));
+        assert_eq!(select(DeviceQuery::new().product_id(0x0001)), channels(&[("A100", 0), ("C300", 0)]));
+        assert_eq!(select(DeviceQuery::new().capabilities(BMCapability::CAN_FD)), channels(&[("B200", 0), ("B200", 1)]));
+        assert_eq!(select(DeviceQuery::new().capabilities(BMCapability::CAN)),
+                   channels(&[("A100", 0), ("B200", 0), ("B200", 1)]));
+
+        // All criteria must match.
+        let query = DeviceQuery::new().vendor_id(0x1234).product_id(0x0002).port(0);
+        assert_eq!(select(query), channels(&[("B200", 0)]));
+        assert!(select(DeviceQuery::new().serial_number("A100").port(1)).is_empty());
+        assert!(select(DeviceQuery::new().serial_number("B20")).is_empty());
+        assert!(select(DeviceQuery::new().name("busmust")).is_empty());
+
+        let device = devices().find(&DeviceQuery::new().vendor_id(0x1234)).unwrap();
+        assert_eq!(device.serial_number(), "A100");
+        assert!(devices().find(&DeviceQuery::new().vendor_id(0)).is_none());
+    }
+
+    #[test]
+    fn physical_devices() {
+        let physical = devices().physical_devices();
+
+        let serials: Vec<&str> = physical.iter().map(|device| device.serial_number()).collect();
+        assert_eq!(serials, vec!["A100", "B200", "C300"]);
+
+        let dual = &physical[1];
+        assert_eq!(dual.name(), "Busmust BM-CANFD-X2");
+        assert_eq!(dual.firmware_version().to_string(), "2.1.0.7");
+        let ports: Vec<u16> = dual.channels().iter().map(|device| device.port()).collect();
+        assert_eq!(ports, vec![0, 1]);
+        assert_eq!(dual.channel(1).unwrap().port(), 1);
+        assert!(dual.channel(2).is_none());
+        assert_eq!(physical[0].channels().len(), 1);
+
+        // Only the remaining channels are grouped.
+        let mut devices = devices();
+        devices.next();
+        let physical = devices.physical_devices();
+        assert_eq!(physical.iter().map(|device| device.channels().len()).collect::<Vec<_>>(), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn firmware_version() {
+        let version = FirmwareVersion::from([1, 2, 3, 4]);
+        assert_eq!(version, FirmwareVersion { major: 1, minor: 2, revision: 3, build: 4 });
+        assert_eq!(version.to_string(), "1.2.3.4");
+
+        // Versions compare field by field, most significant first.
+        assert!(FirmwareVersion::from([1, 10, 0, 0]) > FirmwareVersion::from([1, 9, 255, 255]));
+        assert!(FirmwareVersion::from([2, 0, 0, 0]) > FirmwareVersion::from([1, 255, 255, 255]));
+        assert!(FirmwareVersion::from([1, 0, 0, 1]) > FirmwareVersion::from([1, 0, 0, 0]));
+
+        let device = devices().find(&DeviceQuery::new().serial_number("A100")).unwrap();
+        assert_eq!(device.firmware_version(), FirmwareVersion::from([1, 0, 3, 0]));
+        assert_eq!(device.version(), vec![1, 0, 3, 0]);
+        assert_eq!(device.unique_id(), "UID-A");
+        assert_eq!((device.vendor_id(), device.product_id()), (0x1234, 0x0001));
+    }
+
+    fn keys(infos: &[BMChannelInfo]) -> Vec<([u8; 16], u16)> {
+        infos.iter().map(channel_key).collect()
+    }
+
+    #[test]
+    fn diff_devices_added_and_removed() {
+        let previous = [info(b"A", 0), info(b"B", 0)];
+        let current = [info(b"A", 0), info(b"C", 0), info(b"C", 1)];
+
+        let (added, removed) = diff_devices(&previous, &current);
+        assert_eq!(keys(&added), keys(&[info(b"C", 0), info(b"C", 1)]));
+        assert_eq!(keys(&removed), keys(&[info(b"B", 0)]));
+
+        let (added, removed) = diff_devices(&[], &current);
+        assert_eq!(keys(&added), keys(&current));
+        assert!(removed.is_empty());
+
+        let (added, removed) = diff_devices(&current, &[]);
+        assert!(added.is_empty());
+        assert_eq!(keys(&removed), keys(&current));
+    }
+
+    #[test]
+    fn diff_devices_reordered() {
+        let previous = [info(b"A", 0), info(b"A", 1), info(b"B", 0)];
+        let current = [info(b"B", 0), info(b"A", 1), info(b"A", 0)];
+
+        let (added, removed) = diff_devices(&previous, &current);
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn diff_devices_duplicate_serials() {
+        // Same serial number on different ports are distinct channels.
+        let previous = [info(b"A", 0)];
+        let current = [info(b"A", 0), info(b"A", 1)];
+        let (added, removed) = diff_devices(&previous, &current);
+        assert_eq!(keys(&added), keys(&[info(b"A", 1)]));
+        assert!(removed.is_empty());
+
+        // Channels listed repeatedly are reported once.
+        let current = [info(b"B", 0), info(b"B", 0)];
+        let (added, removed) = diff_devices(&previous, &current);
+        assert_eq!(keys(&added), keys(&[info(b"B", 0)]));
+        assert_eq!(keys(&removed), keys(&[info(b"A", 0)]));
+
+        let (added, removed) = diff_devices(&current, &current);
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+    }
+
+    fn isotp_config() -> IsotpConfig {
+        let template = |id| BMData::builder().can_message(BMCanMessage::builder().sid(id).dlc(8).build()).build();
+        IsotpConfig::builder().tester_template(template(0x7E0)).ecu_template(template(0x7E8)).build().unwrap()
+    }
+
+    fn isotp_status(transferred_bytes: u32) -> BMIsoTPStatus {
+        BMIsoTPStatus {
+            version: 1,
+            flow_control: 0,
+            st_min: 0,
+            block_size: 0,
+            transferred_bytes,
+            total_bytes: 100,
+            timestamp: 0,
+            reserved: [0; 4]
+        }
+    }
+
+    #[test]
+    fn progress_trampoline_forwards_status() {
+        let mut transferred = Vec::new();
+
+        let result = with_progress(&isotp_config(), |status| transferred.push(status.transferred_bytes), |raw| {
+            let callback = raw.callback.unwrap();
+            callback(&isotp_status(10), raw.callback_user_arg);
+            callback(&isotp_status(60), raw.callback_user_arg);
+
+            // Null pointers are ignored.
+            callback(std::ptr::null(), raw.callback_user_arg);
+            callback(&isotp_status(70), std::ptr::null());
+            42
+        });
+
+        assert_eq!(result, 42);
+        assert_eq!(transferred, vec![10, 60]);
+
+        // The configuration itself is left without callback.
+        assert!(isotp_config().to_raw().callback.is_none());
+    }
+
+    #[test]
+    fn progress_panic_does_not_unwind_into_library() {
+        let mut calls = 0;
+        let mut returned = false;
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            with_progress(&isotp_config(), |_| {
+                calls += 1;
+                panic!("progress failed");
+            }, |raw| {
+                // Unwinding through the extern "C" trampoline would abort the process instead of returning here.
+                let callback = raw.callback.unwrap();
+                callback(&isotp_status(10), raw.callback_user_arg);
+                callback(&isotp_status(20), raw.callback_user_arg);
+                returned = true;
+            })
+        }));
+
+        // The panic is raised again once the library call returned, the closure is not called after panicking.
+        let panic = result.unwrap_err();
+        assert_eq!(panic.downcast_ref::<&str>(), Some(&"progress failed"));
+        assert!(returned);
+        assert_eq!(calls, 1);
+    }
 }
@@ -0,0 +1,365 @@
+use std::fmt;
+use ffi::BMStatus;
+use dmgr::desc_from_status;
+
+/// Status flags in ascending bit order, see [Status::flags].
+const FLAGS: [BMStatus; 20] = [
+    BMStatus::XmtFull,
+    BMStatus::Overrun,
+    BMStatus::BusLight,
+    BMStatus::BusWarning,
+    BMStatus::BusOff,
+    BMStatus::ReceiveBufferEmpty,
+    BMStatus::QueueOverrun,
+    BMStatus::TransmitQueueFull,
+    BMStatus::RegTest,
+    BMStatus::NoDriver,
+    BMStatus::OutOfResources,
+    BMStatus::InvalidParameterType,
+    BMStatus::InvalidParameterValue,
+    BMStatus::Unknown,
+    BMStatus::InvalidData,
+    BMStatus::BusPassive,
+    BMStatus::BusTimeout,
+    BMStatus::Caution,
+    BMStatus::NotInitialized,
+    BMStatus::InvalidOperation
+];
+
+/// Bits holding a single hardware/handle error code rather than independent flags.
+const HANDLE_MASK: u32 = 0x1C00;
+
+/// Hardware/handle error codes stored in [HANDLE_MASK].
+const HANDLE_CODES: [BMStatus; 5] = [
+    BMStatus::HardwareInUse,
+    BMStatus::NetInUse,
+    BMStatus::HardwareError,
+    BMStatus::InvalidBus,
+    BMStatus::InvalidClient
+];
+
+/// Status code returned by the Busmust library.
+///
+/// Unlike [BMStatus] it holds any raw value, i.e. a combination of several flags like
+/// `BusWarning | BusLight` or bits unknown to this crate, and decomposes it into known [BMStatus] values.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Status(u32);
+
+impl Status {
+    pub const OK: Status = Status(0);
+
+    pub fn from_raw(raw: u32) -> Status {
+        Status(raw)
+    }
+
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+
+    pub fn is_ok(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Check whether all flags of `status` are set, the hardware/handle error code (i.e. [BMStatus::HardwareInUse])
+    /// must match exactly.
+    pub fn contains(self, status: BMStatus) -> bool {
+        let bits = status as u32;
+        let flags = bits & !HANDLE_MASK;
+        let code = bits & HANDLE_MASK;
+
+        self.0 & flags == flags && (code == 0 || self.0 & HANDLE_MASK == code)
+    }
+
+    /// Get the status as single [BMStatus] value, `None` if it is a combination or holds unknown bits.
+    pub fn to_bm_status(self) -> Option<BMStatus> {
+        BMStatus::from_raw(self.0)
+    }
+
+    /// Decompose the status into known flags and the hardware/handle error code, in ascending bit order.
+    pub fn flags(self) -> Vec<BMStatus> {
+        let mut flags: Vec<BMStatus> = FLAGS.iter()
+            .filter(|&&flag| self.0 & flag as u32 != 0)
+            .copied()
+            .collect();
+
+        if let Some(&code) = HANDLE_CODES.iter().find(|&&code| self.0 & HANDLE_MASK == code as u32) {
+            let index = flags.iter().position(|&flag| flag as u32 > code as u32).unwrap_or(flags.len());
+            flags.insert(index, code);
+        }
+
+        flags
+    }
+
+    /// Get the bits not covered by [Status::flags].
+    pub fn unknown_bits(self) -> u32 {
+        self.flags().iter().fold(self.0, |bits, &flag| bits & !(flag as u32))
+    }
+
+    /// Get the category of the status, the most severe one if several flags are set.
+    pub fn kind(self) -> ErrorKind {
+        let any = |statuses: &[BMStatus]| statuses.iter().any(|&status| self.contains(status));
+
+        if any(&[BMStatus::NotInitialized]) {
+            ErrorKind::NotInitialized
+        } else if self.0 & HANDLE_MASK != 0 || any(&[BMStatus::NoDriver, BMStatus::OutOfResources]) {
+            ErrorKind::Hardware
+        } else if any(&[BMStatus::InvalidParameterType, BMStatus::InvalidParameterValue, BMStatus::InvalidData]) {
+            ErrorKind::Parameter
+        } else if any(&[BMStatus::InvalidOperation]) {
+            ErrorKind::Operation
+        } else if any(&[BMStatus::BusLight, BMStatus::BusWarning, BMStatus::BusPassive, BMStatus::BusOff,
+                        BMStatus::BusTimeout, BMStatus::Overrun]) {
+            ErrorKind::Bus
+        } else if any(&[BMStatus::XmtFull, BMStatus::ReceiveBufferEmpty, BMStatus::QueueOverrun,
+                        BMStatus::TransmitQueueFull]) {
+            ErrorKind::Buffer
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+impl From<BMStatus> for Status {
+    fn from(status: BMStatus) -> Status {
+        Status(status as u32)
+    }
+}
+
+impl fmt::Debug for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Status({})", self)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "{:?}", BMStatus::Ok);
+        }
+
+        let mut parts: Vec<String> = self.flags().iter().map(|flag| format!("{:?}", flag)).collect();
+        if self.unknown_bits() != 0 {
+            parts.push(format!("{:#X}", self.unknown_bits()));
+        }
+
+        write!(f, "{}", parts.join(" | "))
+    }
+}
+
+/// Category of an [Error].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Bus state error or warning, i.e. bus off, error passive or transmit timeout
+    Bus,
+    /// Transmit buffer full, receive buffer empty or overrun
+    Buffer,
+    /// Invalid parameter or data
+    Parameter,
+    /// Hardware error, missing driver or device in use
+    Hardware,
+    /// The library is not initialized
+    NotInitialized,
+    /// The operation is not supported in the current state
+    Operation,
+    /// Any other status
+    Other
+}
+
+/// Error returned by the Busmust library or this crate, with the failing operation and channel port if known.
+#[derive(Debug, Clone)]
+pub struct Error {
+    status: Status,
+    operation: Option<&'static str>,
+    port: Option<u16>
+}
+
+impl Error {
+    pub fn new<S: Into<Status>>(status: S) -> Error {
+        Error { status: status.into(), operation: None, port: None }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.status.kind()
+    }
+
+    /// Check whether the status is exactly the given one.
+    pub fn is(&self, status: BMStatus) -> bool {
+        self.status == Status::from(status)
+    }
+
+    /// Get the failing operation, i.e. `"set_bitrate"`.
+    pub fn operation(&self) -> Option<&'static str> {
+        self.operation
+    }
+
+    /// Get the port of the channel the operation failed on.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Attach the failing operation and the channel port, keeping those already attached.
+    pub fn context(mut self, operation: &'static str, port: Option<u16>) -> Error {
+        self.operation = self.operation.or(Some(operation));
+        self.port = self.port.or(port);
+        self
+    }
+}
+
+impl From<BMStatus> for Error {
+    fn from(status: BMStatus) -> Error {
+        Error::new(status)
+    }
+}
+
+impl From<Status> for Error {
+    fn from(status: Status) -> Error {
+        Error::new(status)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(operation) = self.operation {
+            write!(f, "{}", operation)?;
+            if let Some(port) = self.port {
+                write!(f, " on port {}", port)?;
+            }
+            write!(f, ": ")?;
+        }
+
+        write!(f, "{}", self.status)?;
+
+        let desc = desc_from_status(self.status);
+        if !desc.is_empty() {
+            write!(f, " ({})", desc)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(raw: u32) -> Status {
+        Status::from_raw(raw)
+    }
+
+    fn raw_flags(status: Status) -> Vec<u32> {
+        status.flags().iter().map(|&flag| flag as u32).collect()
+    }
+
+    #[test]
+    fn combined_flags() {
+        let warning = status(BMStatus::BusWarning as u32 | BMStatus::BusLight as u32);
+        assert_eq!(raw_flags(warning), vec![BMStatus::BusLight as u32, BMStatus::BusWarning as u32]);
+        assert!(warning.contains(BMStatus::BusWarning) && warning.contains(BMStatus::BusLight));
+        assert!(!warning.contains(BMStatus::BusOff));
+        assert!(warning.to_bm_status().is_none());
+        assert_eq!(warning.unknown_bits(), 0);
+        assert_eq!(warning.to_string(), "BusLight | BusWarning");
+        assert_eq!(format!("{:?}", warning), "Status(BusLight | BusWarning)");
+
+        let any = Status::from(BMStatus::AnyBusError);
+        assert_eq!(raw_flags(any), vec![0x4, 0x8, 0x10, 0x40000]);
+        assert_eq!(any.to_bm_status().map(|status| status as u32), Some(BMStatus::AnyBusError as u32));
+        assert!(any.contains(BMStatus::BusPassive));
+        assert!(!any.contains(BMStatus::BusTimeout));
+        assert!(!warning.contains(BMStatus::AnyBusError));
+    }
+
+    #[test]
+    fn handle_codes() {
+        let client = status(0x1C00);
+        assert_eq!(raw_flags(client), vec![BMStatus::InvalidClient as u32]);
+        assert!(client.contains(BMStatus::InvalidClient));
+        assert!(!client.contains(BMStatus::HardwareInUse));
+        assert!(!client.contains(BMStatus::NetInUse));
+        assert_eq!(client.unknown_bits(), 0);
+        assert_eq!(client.to_string(), "InvalidClient");
+
+        assert_eq!(raw_flags(status(0x1400)), vec![BMStatus::HardwareError as u32]);
+        assert_eq!(raw_flags(status(0x0800)), vec![BMStatus::NetInUse as u32]);
+
+        // The code is placed among the flags in ascending bit order.
+        let combined = status(0x1C00 | BMStatus::XmtFull as u32 | BMStatus::OutOfResources as u32);
+        assert_eq!(raw_flags(combined), vec![0x1, 0x1C00, 0x2000]);
+        assert_eq!(combined.to_string(), "XmtFull | InvalidClient | OutOfResources");
+    }
+
+    #[test]
+    fn unknown_bits() {
+        let unknown = status(0x100_0000 | BMStatus::BusOff as u32);
+        assert_eq!(raw_flags(unknown), vec![BMStatus::BusOff as u32]);
+        assert_eq!(unknown.unknown_bits(), 0x100_0000);
+        assert!(unknown.contains(BMStatus::BusOff));
+        assert_eq!(unknown.to_string(), "BusOff | 0x1000000");
+
+        let only_unknown = status(0x8000_0000);
+        assert!(only_unknown.flags().is_empty());
+        assert_eq!(only_unknown.to_string(), "0x80000000");
+
+        assert!(Status::OK.is_ok());
+        assert_eq!(Status::OK.to_string(), "Ok");
+        assert!(Status::OK.flags().is_empty());
+    }
+
+    #[test]
+    fn kinds() {
+        let kind = |statuses: &[BMStatus]| status(statuses.iter().fold(0, |raw, &s| raw | s as u32)).kind();
+
+        assert_eq!(kind(&[BMStatus::BusOff]), ErrorKind::Bus);
+        assert_eq!(kind(&[BMStatus::AnyBusError]), ErrorKind::Bus);
+        assert_eq!(kind(&[BMStatus::BusTimeout]), ErrorKind::Bus);
+        assert_eq!(kind(&[BMStatus::Overrun]), ErrorKind::Bus);
+        assert_eq!(kind(&[BMStatus::XmtFull]), ErrorKind::Buffer);
+        assert_eq!(kind(&[BMStatus::ReceiveBufferEmpty]), ErrorKind::Buffer);
+        assert_eq!(kind(&[BMStatus::TransmitQueueFull]), ErrorKind::Buffer);
+        assert_eq!(kind(&[BMStatus::InvalidParameterValue]), ErrorKind::Parameter);
+        assert_eq!(kind(&[BMStatus::InvalidData]), ErrorKind::Parameter);
+        assert_eq!(kind(&[BMStatus::HardwareInUse]), ErrorKind::Hardware);
+        assert_eq!(kind(&[BMStatus::InvalidClient]), ErrorKind::Hardware);
+        assert_eq!(kind(&[BMStatus::NoDriver]), ErrorKind::Hardware);
+        assert_eq!(kind(&[BMStatus::NotInitialized]), ErrorKind::NotInitialized);
+        assert_eq!(kind(&[BMStatus::InvalidOperation]), ErrorKind::Operation);
+        assert_eq!(kind(&[BMStatus::Caution]), ErrorKind::Other);
+        assert_eq!(kind(&[BMStatus::RegTest]), ErrorKind::Other);
+        assert_eq!(status(0x8000_0000).kind(), ErrorKind::Other);
+
+        // The most severe category wins.
+        assert_eq!(kind(&[BMStatus::BusOff, BMStatus::XmtFull]), ErrorKind::Bus);
+        assert_eq!(kind(&[BMStatus::BusOff, BMStatus::InvalidParameterType]), ErrorKind::Parameter);
+        assert_eq!(kind(&[BMStatus::InvalidOperation, BMStatus::HardwareError]), ErrorKind::Hardware);
+        assert_eq!(kind(&[BMStatus::NoDriver, BMStatus::NotInitialized]), ErrorKind::NotInitialized);
+    }
+
+    #[test]
+    fn error_context() {
+        let error = Error::new(BMStatus::BusOff);
+        assert_eq!((error.operation(), error.port()), (None, None));
+        assert!(error.to_string().starts_with("BusOff"));
+
+        let error = error.context("set_bitrate", Some(2)).context("open", Some(3));
+        assert_eq!((error.operation(), error.port()), (Some("set_bitrate"), Some(2)));
+        assert!(error.to_string().starts_with("set_bitrate on port 2: BusOff"));
+        assert_eq!(error.kind(), ErrorKind::Bus);
+
+        let error = Error::new(status(0x1C01)).context("write", None);
+        assert!(error.to_string().starts_with("write: XmtFull | InvalidClient"));
+
+        let error = Error::from(BMStatus::AnyBusError);
+        assert!(error.is(BMStatus::AnyBusError));
+        assert!(!error.is(BMStatus::BusOff));
+        assert!(error.status().contains(BMStatus::BusOff));
+    }
+}
@@ -0,0 +1,623 @@
+//! Typed CAN and CAN FD frames.
+//!
+//! [CanFrame] and [CanFdFrame] are validated on construction and convert losslessly to and from
+//! [BMCanMessage], the representation used by the Busmust library. [Frame] holds either of them.
+
+use std::convert::TryFrom;
+use std::fmt;
+use ffi::*;
+
+/// Maximum payload length of a classic CAN frame.
+pub const CAN_MAX_LEN: usize = 8;
+
+/// Maximum payload length of a CAN FD frame.
+pub const CANFD_MAX_LEN: usize = 64;
+
+/// Frame validation errors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The identifier does not fit in 11 (standard) or 29 (extended) bits
+    InvalidId(u32),
+    /// The payload length is above 8 for a classic frame, or cannot be encoded as DLC for a CAN FD frame (i.e. 9)
+    InvalidLength(usize),
+    /// The DLC is above 15, or above 8 for a remote frame
+    InvalidDlc(u8),
+    /// The message is a CAN FD frame while a classic frame was expected, or the other way round,
+    /// or it is a CAN FD remote frame which does not exist
+    InvalidFormat
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidId(id) => write!(f, "invalid CAN identifier {:#X}", id),
+            Error::InvalidLength(len) => write!(f, "invalid payload length {}", len),
+            Error::InvalidDlc(dlc) => write!(f, "invalid DLC {}", dlc),
+            Error::InvalidFormat => write!(f, "unexpected frame format")
+        }
+    }
+}
+
+impl ::std::error::Error for Error {}
+
+/// Get the DLC of a CAN FD frame with exactly `len` payload bytes, see [ffi::len_to_dlc].
+fn exact_dlc(len: usize) -> Option<u8> {
+    len_to_dlc(len).filter(|&dlc| dlc_to_len(dlc) == len)
+}
+
+/// 11-bit standard CAN identifier.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StandardId(u16);
+
+impl StandardId {
+    /// Highest standard identifier.
+    pub const MAX: StandardId = StandardId(0x7FF);
+
+    /// Create an identifier, fails if `raw` does not fit in 11 bits.
+    pub fn new(raw: u16) -> Result<StandardId> {
+        if raw <= StandardId::MAX.0 {
+            Ok(StandardId(raw))
+        } else {
+            Err(Error::InvalidId(raw as u32))
+        }
+    }
+
+    /// Get the raw identifier value.
+    pub fn as_raw(&self) -> u16 {
+        self.0
+    }
+}
+
+/// 29-bit extended CAN identifier.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExtendedId(u32);
+
+impl ExtendedId {
+    /// Highest extended identifier.
+    pub const MAX: ExtendedId = ExtendedId(0x1FFF_FFFF);
+
+    /// Create an identifier, fails if `raw` does not fit in 29 bits.
+    pub fn new(raw: u32) -> Result<ExtendedId> {
+        if raw <= ExtendedId::MAX.0 {
+            Ok(ExtendedId(raw))
+        } else {
+            Err(Error::InvalidId(raw))
+        }
+    }
+
+    /// Get the raw identifier value.
+    pub fn as_raw(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Standard or extended CAN identifier.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Id {
+    Standard(StandardId),
+    Extended(ExtendedId)
+}
+
+impl Id {
+    /// Get the raw identifier value.
+    pub fn as_raw(&self) -> u32 {
+        match self {
+            Id::Standard(id) => id.as_raw() as u32,
+            Id::Extended(id) => id.as_raw()
+        }
+    }
+
+    /// Check whether this is an extended identifier.
+    pub fn is_extended(&self) -> bool {
+        matches!(self, Id::Extended(_))
+    }
+
+    fn to_message_id(self) -> BMMessageId {
+        BMCanId::from(self).into()
+    }
+
+    fn from_message(message: &BMCanMessage) -> Id {
+        match message.id() {
+            BMCanId::Standard(id) => Id::Standard(StandardId(id)),
+            BMCanId::Extended(id) => Id::Extended(ExtendedId(id))
+        }
+    }
+}
+
+impl From<Id> for BMCanId {
+    fn from(id: Id) -> BMCanId {
+        match id {
+            Id::Standard(id) => BMCanId::Standard(id.as_raw()),
+            Id::Extended(id) => BMCanId::Extended(id.as_raw())
+        }
+    }
+}
+
+impl TryFrom<BMCanId> for Id {
+    type Error = Error;
+
+    fn try_from(id: BMCanId) -> Result<Id> {
+        match id {
+            BMCanId::Standard(id) => StandardId::new(id).map(Id::Standard),
+            BMCanId::Extended(id) => ExtendedId::new(id).map(Id::Extended)
+        }
+    }
+}
+
+impl From<StandardId> for Id {
+    fn from(id: StandardId) -> Id {
+        Id::Standard(id)
+    }
+}
+
+impl From<ExtendedId> for Id {
+    fn from(id: ExtendedId) -> Id {
+        Id::Extended(id)
+    }
+}
+
+/// Classic CAN data or remote frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CanFrame {
+    id: Id,
+    dlc: u8,
+    remote: bool,
+    data: [u8; CAN_MAX_LEN]
+}
+
+impl CanFrame {
+    /// Create a data frame with at most 8 payload bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use busmust::frame::{CanFrame, StandardId};
+    ///
+    /// let frame = CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+    /// ```
+    pub fn new<I: Into<Id>>(id: I, data: &[u8]) -> Result<CanFrame> {
+        if data.len() > CAN_MAX_LEN {
+            return Err(Error::InvalidLength(data.len()));
+        }
+
+        let mut frame = CanFrame { id: id.into(), dlc: data.len() as u8, remote: false, data: [0; CAN_MAX_LEN] };
+        frame.data[..data.len()].copy_from_slice(data);
+        Ok(frame)
+    }
+
+    /// Create a remote frame requesting `dlc` (0-8) bytes.
+    pub fn new_remote<I: Into<Id>>(id: I, dlc: u8) -> Result<CanFrame> {
+        if dlc as usize > CAN_MAX_LEN {
+            return Err(Error::InvalidDlc(dlc));
+        }
+
+        Ok(CanFrame { id: id.into(), dlc, remote: true, data: [0; CAN_MAX_LEN] })
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Get the DLC, values 9-15 received from the bus are kept and denote 8 payload bytes.
+    pub fn dlc(&self) -> u8 {
+        self.dlc
+    }
+
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    /// Get the payload, empty for remote frames.
+    pub fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..(self.dlc as usize).min(CAN_MAX_LEN)]
+        }
+    }
+}
+
+/// CAN FD frame with up to 64 payload bytes.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CanFdFrame {
+    id: Id,
+    dlc: u8,
+    brs: bool,
+    esi: bool,
+    data: [u8; CANFD_MAX_LEN]
+}
+
+impl CanFdFrame {
+    /// Create a frame, the payload length must be encodable as DLC, i.e. 0-8, 12, 16, 20, 24, 32, 48 or 64.
+    /// Bitrate switching is enabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use busmust::frame::{CanFdFrame, ExtendedId};
+    ///
+    /// let frame = CanFdFrame::new(ExtendedId::new(0x18DAF110).unwrap(), &[0; 12]).unwrap()
+    ///     .with_brs(false);
+    /// ```
+    pub fn new<I: Into<Id>>(id: I, data: &[u8]) -> Result<CanFdFrame> {
+        let dlc = exact_dlc(data.len()).ok_or(Error::InvalidLength(data.len()))?;
+
+        let mut frame = CanFdFrame { id: id.into(), dlc, brs: true, esi: false, data: [0; CANFD_MAX_LEN] };
+        frame.data[..data.len()].copy_from_slice(data);
+        Ok(frame)
+    }
+
+    /// Enable or disable bitrate switching.
+    pub fn with_brs(mut self, value: bool) -> CanFdFrame {
+        self.brs = value;
+        self
+    }
+
+    /// Set the error state indicator, reserved for gateways.
+    pub fn with_esi(mut self, value: bool) -> CanFdFrame {
+        self.esi = value;
+        self
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    pub fn dlc(&self) -> u8 {
+        self.dlc
+    }
+
+    pub fn brs(&self) -> bool {
+        self.brs
+    }
+
+    pub fn esi(&self) -> bool {
+        self.esi
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..dlc_to_len(self.dlc)]
+    }
+}
+
+impl fmt::Debug for CanFdFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CanFdFrame")
+            .field("id", &self.id)
+            .field("dlc", &self.dlc)
+            .field("brs", &self.brs)
+            .field("esi", &self.esi)
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+/// Classic CAN or CAN FD frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Frame {
+    Can(CanFrame),
+    Fd(CanFdFrame)
+}
+
+impl Frame {
+    pub fn id(&self) -> Id {
+        match self {
+            Frame::Can(frame) => frame.id(),
+            Frame::Fd(frame) => frame.id()
+        }
+    }
+
+    pub fn dlc(&self) -> u8 {
+        match self {
+            Frame::Can(frame) => frame.dlc(),
+            Frame::Fd(frame) => frame.dlc()
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Frame::Can(frame) => frame.data(),
+            Frame::Fd(frame) => frame.data()
+        }
+    }
+}
+
+impl From<CanFrame> for Frame {
+    fn from(frame: CanFrame) -> Frame {
+        Frame::Can(frame)
+    }
+}
+
+impl From<CanFdFrame> for Frame {
+    fn from(frame: CanFdFrame) -> Frame {
+        Frame::Fd(frame)
+    }
+}
+
+impl From<CanFrame> for BMCanMessage {
+    fn from(frame: CanFrame) -> BMCanMessage {
+        let mut payload = [0; CANFD_MAX_LEN];
+        payload[..CAN_MAX_LEN].copy_from_slice(&frame.data);
+
+        BMCanMessage {
+            mid: frame.id.to_message_id(),
+            ctrl: BMMessageCtrl {
+                tx: BMTxMessageCtrl::new()
+                    .with_dlc(frame.dlc)
+                    .with_ide(frame.id.is_extended())
+                    .with_rtr(frame.remote)
+            },
+            payload
+        }
+    }
+}
+
+impl From<CanFdFrame> for BMCanMessage {
+    fn from(frame: CanFdFrame) -> BMCanMessage {
+        BMCanMessage {
+            mid: frame.id.to_message_id(),
+            ctrl: BMMessageCtrl {
+                tx: BMTxMessageCtrl::new()
+                    .with_dlc(frame.dlc)
+                    .with_ide(frame.id.is_extended())
+                    .with_brs(frame.brs)
+                    .with_fdf(true)
+                    .with_esi(frame.esi)
+            },
+            payload: frame.data
+        }
+    }
+}
+
+impl From<Frame> for BMCanMessage {
+    fn from(frame: Frame) -> BMCanMessage {
+        match frame {
+            Frame::Can(frame) => frame.into(),
+            Frame::Fd(frame) => frame.into()
+        }
+    }
+}
+
+impl TryFrom<BMCanMessage> for CanFrame {
+    type Error = Error;
+
+    fn try_from(message: BMCanMessage) -> Result<CanFrame> {
+        let ctrl = unsafe { message.ctrl.rx };
+        if ctrl.fdf() {
+            return Err(Error::InvalidFormat);
+        }
+
+        let mut data = [0; CAN_MAX_LEN];
+        if !ctrl.rtr() {
+            let len = (ctrl.dlc() as usize).min(CAN_MAX_LEN);
+            data[..len].copy_from_slice(&message.payload[..len]);
+        } else if ctrl.dlc() as usize > CAN_MAX_LEN {
+            return Err(Error::InvalidDlc(ctrl.dlc()));
+        }
+
+        Ok(CanFrame {
+            id: Id::from_message(&message),
+            dlc: ctrl.dlc(),
+            remote: ctrl.rtr(),
+            data
+        })
+    }
+}
+
+impl TryFrom<BMCanMessage> for CanFdFrame {
+    type Error = Error;
+
+    fn try_from(message: BMCanMessage) -> Result<CanFdFrame> {
+        let ctrl = unsafe { message.ctrl.rx };
+        if !ctrl.fdf() || ctrl.rtr() {
+            return Err(Error::InvalidFormat);
+        }
+
+        let len = dlc_to_len(ctrl.dlc());
+        let mut data = [0; CANFD_MAX_LEN];
+        data[..len].copy_from_slice(&message.payload[..len]);
+
+        Ok(CanFdFrame {
+            id: Id::from_message(&message),
+            dlc: ctrl.dlc(),
+            brs: ctrl.brs(),
+            esi: ctrl.esi(),
+            data
+        })
+    }
+}
+
+impl TryFrom<BMCanMessage> for Frame {
+    type Error = Error;
+
+    fn try_from(message: BMCanMessage) -> Result<Frame> {
+        if unsafe { message.ctrl.rx.fdf() } {
+            CanFdFrame::try_from(message).map(Frame::Fd)
+        } else {
+            CanFrame::try_from(message).map(Frame::Can)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids() -> Vec<Id> {
+        vec![
+            StandardId::new(0).unwrap().into(),
+            StandardId::new(0x123).unwrap().into(),
+            StandardId::MAX.into(),
+            ExtendedId::new(0).unwrap().into(),
+            ExtendedId::new(0x18DAF110).unwrap().into(),
+            ExtendedId::MAX.into()
+        ]
+    }
+
+    /// Convert a frame into a message and back, checking the raw fields on the way.
+    fn round_trip(frame: Frame) -> Frame {
+        let message = BMCanMessage::from(frame);
+        let ctrl = unsafe { message.ctrl.rx };
+
+        assert_eq!(message.id(), BMCanId::from(frame.id()));
+        assert_eq!(ctrl.ide(), frame.id().is_extended());
+        assert_eq!(ctrl.dlc(), frame.dlc());
+        assert_eq!(&message.payload[..frame.data().len()], frame.data());
+
+        Frame::try_from(message).unwrap()
+    }
+
+    #[test]
+    fn data_frames() {
+        for id in ids() {
+            for len in 0..=CAN_MAX_LEN {
+                let data: Vec<u8> = (1..=len as u8).collect();
+                let frame = Frame::Can(CanFrame::new(id, &data).unwrap());
+                assert_eq!(round_trip(frame), frame);
+            }
+        }
+
+        let id = StandardId::new(0x123).unwrap();
+        assert_eq!(CanFrame::new(id, &[0; 9]), Err(Error::InvalidLength(9)));
+    }
+
+    #[test]
+    fn remote_frames() {
+        for id in ids() {
+            for dlc in 0..=CAN_MAX_LEN as u8 {
+                let frame = CanFrame::new_remote(id, dlc).unwrap();
+                assert!(frame.data().is_empty());
+
+                let message = BMCanMessage::from(frame);
+                assert!(unsafe { message.ctrl.rx.rtr() });
+                assert_eq!(round_trip(Frame::Can(frame)), Frame::Can(frame));
+            }
+        }
+
+        let id = StandardId::new(0x123).unwrap();
+        assert_eq!(CanFrame::new_remote(id, 9), Err(Error::InvalidDlc(9)));
+
+        let message = BMCanMessage::builder().sid(0x123).rtr(true).dlc(9).build();
+        assert_eq!(CanFrame::try_from(message), Err(Error::InvalidDlc(9)));
+    }
+
+    #[test]
+    fn classic_dlc_above_eight() {
+        for dlc in 9..=15 {
+            let message = BMCanMessage::builder().sid(0x123).payload(vec![0xAA; 8]).dlc(dlc).build();
+
+            let frame = CanFrame::try_from(message).unwrap();
+            assert_eq!(frame.dlc(), dlc);
+            assert_eq!(frame.data(), &[0xAA; 8]);
+
+            let message = BMCanMessage::from(frame);
+            assert_eq!(unsafe { message.ctrl.rx.dlc() }, dlc);
+            assert_eq!(CanFrame::try_from(message), Ok(frame));
+        }
+    }
+
+    #[test]
+    fn fd_frames() {
+        for id in ids() {
+            for dlc in 0..=15 {
+                let data: Vec<u8> = (0..dlc_to_len(dlc)).map(|b| b as u8).collect();
+                let frame = CanFdFrame::new(id, &data).unwrap();
+                assert_eq!(frame.dlc(), dlc);
+                assert_eq!(frame.data(), &data[..]);
+
+                for &(brs, esi) in [(false, false), (true, false), (false, true), (true, true)].iter() {
+                    let frame = frame.with_brs(brs).with_esi(esi);
+
+                    let ctrl = unsafe { BMCanMessage::from(frame).ctrl.rx };
+                    assert!(ctrl.fdf());
+                    assert_eq!((ctrl.brs(), ctrl.esi()), (brs, esi));
+
+                    assert_eq!(round_trip(Frame::Fd(frame)), Frame::Fd(frame));
+                }
+            }
+        }
+
+        let id = StandardId::new(0x123).unwrap();
+        assert_eq!(CanFdFrame::new(id, &[0; 9]).err(), Some(Error::InvalidLength(9)));
+        assert_eq!(CanFdFrame::new(id, &[0; 65]).err(), Some(Error::InvalidLength(65)));
+    }
+
+    #[test]
+    fn fd_frames_from_messages() {
+        for dlc in 9..=15 {
+            let payload: Vec<u8> = (0..dlc_to_len(dlc)).map(|b| b as u8).collect();
+            let message = BMCanMessage::builder().extended_id(0x18DAF110).fdf(true).brs(true).payload(payload.clone())
+                .build();
+            assert_eq!(unsafe { message.ctrl.rx.dlc() }, dlc);
+
+            let frame = CanFdFrame::try_from(message).unwrap();
+            assert_eq!(frame.id(), Id::Extended(ExtendedId::new(0x18DAF110).unwrap()));
+            assert_eq!(frame.dlc(), dlc);
+            assert!(frame.brs());
+            assert!(!frame.esi());
+            assert_eq!(frame.data(), &payload[..]);
+        }
+    }
+
+    #[test]
+    fn invalid_formats() {
+        let classic = BMCanMessage::builder().sid(0x123).payload(vec![1, 2]).build();
+        let fd = BMCanMessage::builder().sid(0x123).fdf(true).payload(vec![1, 2]).build();
+        let fd_remote = BMCanMessage::builder().sid(0x123).fdf(true).rtr(true).build();
+
+        assert_eq!(CanFdFrame::try_from(classic).err(), Some(Error::InvalidFormat));
+        assert_eq!(CanFrame::try_from(fd), Err(Error::InvalidFormat));
+        assert_eq!(CanFdFrame::try_from(fd_remote).err(), Some(Error::InvalidFormat));
+        assert_eq!(Frame::try_from(fd_remote), Err(Error::InvalidFormat));
+
+        assert!(matches!(Frame::try_from(classic), Ok(Frame::Can(_))));
+        assert!(matches!(Frame::try_from(fd), Ok(Frame::Fd(_))));
+    }
+
+    #[test]
+    fn id_validation() {
+        assert_eq!(StandardId::new(0x7FF), Ok(StandardId::MAX));
+        assert_eq!(StandardId::new(0x800), Err(Error::InvalidId(0x800)));
+        assert_eq!(ExtendedId::new(0x1FFF_FFFF), Ok(ExtendedId::MAX));
+        assert_eq!(ExtendedId::new(0x2000_0000), Err(Error::InvalidId(0x2000_0000)));
+
+        for id in ids() {
+            assert_eq!(Id::try_from(BMCanId::from(id)), Ok(id));
+        }
+        assert_eq!(Id::try_from(BMCanId::Standard(0xFFFF)), Err(Error::InvalidId(0xFFFF)));
+        assert_eq!(Id::try_from(BMCanId::Extended(u32::MAX)), Err(Error::InvalidId(u32::MAX)));
+
+        assert_eq!(Id::from(StandardId::MAX).as_raw(), 0x7FF);
+        assert!(!Id::from(StandardId::MAX).is_extended());
+        assert_eq!(Id::from(ExtendedId::MAX).as_raw(), 0x1FFF_FFFF);
+        assert!(Id::from(ExtendedId::MAX).is_extended());
+    }
+
+    #[test]
+    fn fd_lengths() {
+        let id = StandardId::new(0x123).unwrap();
+        let valid = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+        for len in 0..=CANFD_MAX_LEN + 1 {
+            let frame = CanFdFrame::new(id, &vec![0x55; len]);
+            match valid.iter().position(|&l| l == len) {
+                Some(dlc) => {
+                    let frame = frame.unwrap();
+                    assert_eq!(frame.dlc(), dlc as u8);
+                    assert_eq!(frame.data().len(), len);
+                }
+                None => assert_eq!(frame.err(), Some(Error::InvalidLength(len)))
+            }
+        }
+    }
+
+    #[test]
+    fn error_display() {
+        assert_eq!(Error::InvalidId(0x800).to_string(), "invalid CAN identifier 0x800");
+        assert_eq!(Error::InvalidLength(9).to_string(), "invalid payload length 9");
+        assert_eq!(Error::InvalidDlc(16).to_string(), "invalid DLC 16");
+        assert_eq!(Error::InvalidFormat.to_string(), "unexpected frame format");
+    }
+}
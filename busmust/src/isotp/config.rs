@@ -0,0 +1,276 @@
+use std::ptr;
+use std::time::Duration;
+use {Error, Result};
+use ffi::*;
+use super::encode_st_min;
+
+/// ISO-TP protocol timeouts in milliseconds, see ISO 15765-2 for details.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IsotpTimeouts {
+    /// `N_As` if writing as tester or reading as ECU, otherwise `N_Ar`
+    pub a: u16,
+    /// `N_Bs` if writing as tester or reading as ECU, otherwise `N_Br`
+    pub b: u16,
+    /// `N_Cs` if writing as tester or reading as ECU, otherwise `N_Cr`
+    pub c: u16
+}
+
+impl Default for IsotpTimeouts {
+    fn default() -> IsotpTimeouts {
+        IsotpTimeouts { a: 1000, b: 1000, c: 1000 }
+    }
+}
+
+impl IsotpTimeouts {
+    fn to_raw(self) -> BMIsotpTimeoutConfig {
+        BMIsotpTimeoutConfig { a: self.a, b: self.b, c: self.c }
+    }
+}
+
+/// Validated configuration of the device ISO-TP implementation.
+#[derive(Copy, Clone)]
+pub struct IsotpConfig(BMIsotpConfig);
+
+impl IsotpConfig {
+    pub fn builder() -> IsotpConfigBuilder {
+        IsotpConfigBuilder::default()
+    }
+
+    /// Get the raw configuration, without progress callback.
+    pub fn to_raw(&self) -> BMIsotpConfig {
+        self.0
+    }
+}
+
+/// Builder for [IsotpConfig].
+pub struct IsotpConfigBuilder {
+    mode: BMIsotpMode,
+    tester_timeout: IsotpTimeouts,
+    ecu_timeout: IsotpTimeouts,
+    st_min: Duration,
+    block_size: u8,
+    fc_frame_length: u8,
+    padding: Option<u8>,
+    long_pdu: bool,
+    tester_template: Option<BMData>,
+    ecu_template: Option<BMData>
+}
+
+impl Default for IsotpConfigBuilder {
+    fn default() -> IsotpConfigBuilder {
+        IsotpConfigBuilder {
+            mode: BMIsotpMode::NormalTester,
+            tester_timeout: IsotpTimeouts::default(),
+            ecu_timeout: IsotpTimeouts::default(),
+            st_min: Duration::from_millis(0),
+            block_size: 0,
+            fc_frame_length: 8,
+            padding: None,
+            long_pdu: false,
+            tester_template: None,
+            ecu_template: None
+        }
+    }
+}
+
+impl IsotpConfigBuilder {
+    pub fn new() -> IsotpConfigBuilder {
+        IsotpConfigBuilder::default()
+    }
+
+    /// ISO-TP operation mode, only normal addressing modes are currently supported by the device.
+    pub fn mode(mut self, mode: BMIsotpMode) -> IsotpConfigBuilder {
+        self.mode = mode;
+        self
+    }
+
+    /// Tester (UDS client) timeouts.
+    pub fn tester_timeout(mut self, timeouts: IsotpTimeouts) -> IsotpConfigBuilder {
+        self.tester_timeout = timeouts;
+        self
+    }
+
+    /// ECU (UDS server) timeouts.
+    pub fn ecu_timeout(mut self, timeouts: IsotpTimeouts) -> IsotpConfigBuilder {
+        self.ecu_timeout = timeouts;
+        self
+    }
+
+    /// Minimum separation time reported in flow control frames when the device acts as receiver.
+    /// Values below 1 ms are rounded up to multiples of 100 us, values above 127 ms are invalid.
+    pub fn st_min(mut self, value: Duration) -> IsotpConfigBuilder {
+        self.st_min = value;
+        self
+    }
+
+    /// Block size reported in flow control frames, 0 means no further flow control is needed.
+    pub fn block_size(mut self, value: u8) -> IsotpConfigBuilder {
+        self.block_size = value;
+        self
+    }
+
+    /// Flow control frame length in bytes.
+    pub fn fc_frame_length(mut self, value: u8) -> IsotpConfigBuilder {
+        self.fc_frame_length = value;
+        self
+    }
+
+    /// Pad unused payload bytes with the given value, or disable padding with `None`.
+    pub fn padding(mut self, value: Option<u8>) -> IsotpConfigBuilder {
+        self.padding = value;
+        self
+    }
+
+    /// Enable long PDUs (>4095 bytes), always enabled by the device if templates use DLC>8.
+    pub fn long_pdu(mut self, value: bool) -> IsotpConfigBuilder {
+        self.long_pdu = value;
+        self
+    }
+
+    /// All tester messages are formatted/checked using this template, configure CAN message ID and IDE/FDF flags here.
+    pub fn tester_template(mut self, value: BMData) -> IsotpConfigBuilder {
+        self.tester_template = Some(value);
+        self
+    }
+
+    /// All ECU messages are formatted/checked using this template, configure CAN message ID and IDE/FDF flags here.
+    pub fn ecu_template(mut self, value: BMData) -> IsotpConfigBuilder {
+        self.ecu_template = Some(value);
+        self
+    }
+
+    /// Validate the configuration.
+    ///
+    /// returns: [`Result<IsotpConfig>`], or [BMStatus::InvalidParameterValue] if templates are missing,
+    /// addressing mode is not supported or any parameter is out of range.
+    pub fn build(self) -> Result<IsotpConfig> {
+        let st_min = encode_st_min(self.st_min);
+        let valid_timeout = |t: &IsotpTimeouts| t.a > 0 && t.b > 0 && t.c > 0;
+
+        let (tester, ecu) = match (self.tester_template, self.ecu_template) {
+            (Some(tester), Some(ecu)) => (tester, ecu),
+            _ => return Err(Error::new(BMStatus::InvalidParameterValue))
+        };
+
+        if !matches!(self.mode, BMIsotpMode::NormalTester | BMIsotpMode::NormalEcu)
+            || st_min.is_none()
+            || !valid_timeout(&self.tester_timeout)
+            || !valid_timeout(&self.ecu_timeout)
+            || self.fc_frame_length < 3
+            || self.fc_frame_length > 64 {
+            return Err(Error::new(BMStatus::InvalidParameterValue));
+        }
+
+        Ok(IsotpConfig(BMIsotpConfig {
+            version: 0x01,
+            mode: self.mode as u8,
+            tester_timeout: self.tester_timeout.to_raw(),
+            ecu_timeout: self.ecu_timeout.to_raw(),
+            flow_control: BMIsotpFlowControlConfig {
+                st_min: st_min.unwrap_or_default(),
+                block_size: self.block_size,
+                fc_frame_length: self.fc_frame_length,
+                reserved: 0
+            },
+            extended_address: 0,
+            padding_enabled: self.padding.is_some() as u8,
+            padding_value: self.padding.unwrap_or_default(),
+            long_pdu_enabled: self.long_pdu as u8,
+            padding: [0; 2],
+            callback: None,
+            callback_user_arg: ptr::null(),
+            tester_data_template: tester,
+            ecu_data_template: ecu
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(id: u16) -> BMData {
+        BMData::builder().can_message(BMCanMessage::builder().sid(id).dlc(8).build()).build()
+    }
+
+    fn builder() -> IsotpConfigBuilder {
+        IsotpConfig::builder().tester_template(template(0x7E0)).ecu_template(template(0x7E8))
+    }
+
+    fn invalid(builder: IsotpConfigBuilder) -> bool {
+        builder.build().err().is_some_and(|e| e.is(BMStatus::InvalidParameterValue))
+    }
+
+    #[test]
+    fn defaults() {
+        let raw = builder().build().unwrap().to_raw();
+
+        assert_eq!((raw.version, raw.mode), (0x01, BMIsotpMode::NormalTester as u8));
+        let timeouts = |t: BMIsotpTimeoutConfig| (t.a, t.b, t.c);
+        assert_eq!(timeouts(raw.tester_timeout), (1000, 1000, 1000));
+        assert_eq!(timeouts(raw.ecu_timeout), (1000, 1000, 1000));
+
+        let fc = raw.flow_control;
+        assert_eq!((fc.st_min, fc.block_size, fc.fc_frame_length), (0, 0, 8));
+        assert_eq!((raw.padding_enabled, raw.padding_value, raw.long_pdu_enabled), (0, 0, 0));
+        assert!(raw.callback.is_none());
+        assert!(raw.callback_user_arg.is_null());
+    }
+
+    #[test]
+    fn parameters() {
+        let raw = builder()
+            .mode(BMIsotpMode::NormalEcu)
+            .tester_timeout(IsotpTimeouts { a: 10, b: 20, c: 30 })
+            .ecu_timeout(IsotpTimeouts { a: 40, b: 50, c: 60 })
+            .st_min(Duration::from_micros(250))
+            .block_size(8)
+            .fc_frame_length(3)
+            .padding(Some(0xAA))
+            .long_pdu(true)
+            .build()
+            .unwrap()
+            .to_raw();
+
+        assert_eq!(raw.mode, BMIsotpMode::NormalEcu as u8);
+        assert_eq!((raw.tester_timeout.a, raw.tester_timeout.b, raw.tester_timeout.c), (10, 20, 30));
+        assert_eq!((raw.ecu_timeout.a, raw.ecu_timeout.b, raw.ecu_timeout.c), (40, 50, 60));
+
+        // 250 us are rounded up to 300 us.
+        let fc = raw.flow_control;
+        assert_eq!((fc.st_min, fc.block_size, fc.fc_frame_length), (0xF3, 8, 3));
+        assert_eq!((raw.padding_enabled, raw.padding_value, raw.long_pdu_enabled), (1, 0xAA, 1));
+
+        let raw = builder().st_min(Duration::from_millis(127)).build().unwrap().to_raw();
+        assert_eq!(raw.flow_control.st_min, 0x7F);
+    }
+
+    #[test]
+    fn templates_are_copied() {
+        let tester = template(0x7E0);
+        let ecu = BMData::builder().can_message(BMCanMessage::builder().extended_id(0x18DAF110).fdf(true).build()).build();
+        let raw = IsotpConfig::builder().tester_template(tester).ecu_template(ecu).build().unwrap().to_raw();
+
+        let bytes = |data: &BMData| (u16::from(data.header), data.length, data.payload.to_vec());
+        assert_eq!(bytes(&raw.tester_data_template), bytes(&tester));
+        assert_eq!(bytes(&raw.ecu_data_template), bytes(&ecu));
+    }
+
+    #[test]
+    fn validation() {
+        // Both templates are required.
+        assert!(invalid(IsotpConfig::builder()));
+        assert!(invalid(IsotpConfig::builder().tester_template(template(0x7E0))));
+        assert!(invalid(IsotpConfig::builder().ecu_template(template(0x7E8))));
+
+        assert!(invalid(builder().mode(BMIsotpMode::ExtendedTester)));
+        assert!(invalid(builder().mode(BMIsotpMode::ExtendedEcu)));
+        assert!(invalid(builder().st_min(Duration::from_millis(128))));
+        assert!(invalid(builder().tester_timeout(IsotpTimeouts { a: 0, ..IsotpTimeouts::default() })));
+        assert!(invalid(builder().ecu_timeout(IsotpTimeouts { c: 0, ..IsotpTimeouts::default() })));
+        assert!(invalid(builder().fc_frame_length(2)));
+        assert!(invalid(builder().fc_frame_length(65)));
+
+        assert!(builder().fc_frame_length(64).build().is_ok());
+    }
+}
@@ -0,0 +1,265 @@
+//! ISO-TP (ISO 15765-2) support.
+//!
+//! [IsotpConfig] configures the ISO-TP implementation running on the device itself,
+//! see [super::dmgr::OpenChannel::write_isotp] and [super::dmgr::OpenChannel::read_isotp].
+//!
+//! [IsoTpSocket] is a pure software implementation working on top of any [FrameIo], i.e. an opened
+//! [super::dmgr::OpenChannel] or an in-memory [loopback], and additionally supports extended and mixed addressing.
+
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use ffi::*;
+use dmgr::OpenChannel;
+
+mod config;
+mod socket;
+
+pub use self::config::{IsotpConfig, IsotpConfigBuilder, IsotpTimeouts};
+pub use self::socket::{Addressing, IsoTpSocket, IsoTpSocketBuilder, Timeouts};
+
+/// ISO-TP timer which expired, see ISO 15765-2 for details.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Timer {
+    /// Transmission of a frame by the sender
+    As,
+    /// Transmission of a flow control frame by the receiver
+    Ar,
+    /// Reception of the next flow control frame by the sender
+    Bs,
+    /// Reception of the next consecutive frame by the receiver
+    Cr
+}
+
+/// Software ISO-TP errors.
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying device returned an error
+    Device(::Error),
+    /// No message was received within the requested time
+    Timeout,
+    /// A protocol timer expired during a segmented transfer
+    TimerExpired(Timer),
+    /// A consecutive frame with unexpected sequence number was received
+    WrongSequenceNumber { expected: u8, received: u8 },
+    /// The receiver reported a buffer overflow
+    Overflow,
+    /// The sender kept the receiver waiting for more than the allowed number of wait frames
+    WaitLimitExceeded,
+    /// Received frame could not be parsed as ISO-TP frame
+    InvalidFrame,
+    /// The message is too long to be sent or received
+    MessageTooLong(usize)
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl From<::Error> for Error {
+    fn from(err: ::Error) -> Error {
+        Error::Device(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Device(e) => write!(f, "device error: {}", e),
+            Error::Timeout => write!(f, "no message received"),
+            Error::TimerExpired(timer) => write!(f, "N_{:?} timeout", timer),
+            Error::WrongSequenceNumber { expected, received } => {
+                write!(f, "wrong sequence number: expected {}, received {}", expected, received)
+            }
+            Error::Overflow => write!(f, "receiver buffer overflow"),
+            Error::WaitLimitExceeded => write!(f, "too many flow control wait frames"),
+            Error::InvalidFrame => write!(f, "invalid ISO-TP frame"),
+            Error::MessageTooLong(len) => write!(f, "message of {} bytes is too long", len)
+        }
+    }
+}
+
+impl ::std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Device(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+/// Source and sink of CAN frames used by [IsoTpSocket].
+pub trait FrameIo {
+    /// Send a single frame, waiting at most `timeout` until it is transmitted.
+    fn send_frame(&mut self, message: &BMCanMessage, timeout: Duration) -> ::Result<()>;
+
+    /// Receive a single frame, waiting at most `timeout`, returns `None` if no frame has been received.
+    fn recv_frame(&mut self, timeout: Duration) -> ::Result<Option<BMCanMessage>>;
+}
+
+impl<T: FrameIo + ?Sized> FrameIo for &mut T {
+    fn send_frame(&mut self, message: &BMCanMessage, timeout: Duration) -> ::Result<()> {
+        (**self).send_frame(message, timeout)
+    }
+
+    fn recv_frame(&mut self, timeout: Duration) -> ::Result<Option<BMCanMessage>> {
+        (**self).recv_frame(timeout)
+    }
+}
+
+impl FrameIo for &OpenChannel {
+    fn send_frame(&mut self, message: &BMCanMessage, timeout: Duration) -> ::Result<()> {
+        self.write_can_message(*message, Some(millis(timeout))).map(|_| ())
+    }
+
+    fn recv_frame(&mut self, timeout: Duration) -> ::Result<Option<BMCanMessage>> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(message) = self.read_can_message()? {
+                return Ok(Some(message));
+            }
+
+            let now = Instant::now();
+            if now >= deadline || !self.wait_for_notification(Some(millis(deadline - now) as u32)) {
+                return self.read_can_message();
+            }
+        }
+    }
+}
+
+impl FrameIo for OpenChannel {
+    fn send_frame(&mut self, message: &BMCanMessage, timeout: Duration) -> ::Result<()> {
+        (&*self).send_frame(message, timeout)
+    }
+
+    fn recv_frame(&mut self, timeout: Duration) -> ::Result<Option<BMCanMessage>> {
+        (&*self).recv_frame(timeout)
+    }
+}
+
+/// Bus level timeout of a single segment sent by [DeviceTransport], protocol timeouts are set by [IsotpConfig].
+const SEGMENT_TIMEOUT_MS: i32 = 1000;
+
+/// Message level ISO-TP transport, used i.e. by the [super::uds] client and server.
+pub trait Transport {
+    /// Send a complete message.
+    fn send(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Receive a complete message, waiting at most `timeout` for it to start.
+    fn recv(&mut self, timeout: Duration) -> Result<Vec<u8>>;
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        (**self).send(data)
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        (**self).recv(timeout)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        (**self).send(data)
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        (**self).recv(timeout)
+    }
+}
+
+impl<F: FrameIo> Transport for IsoTpSocket<F> {
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        IsoTpSocket::send(self, data)
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        IsoTpSocket::recv(self, timeout)
+    }
+}
+
+/// [Transport] using the ISO-TP implementation running on the device, see [OpenChannel::write_isotp].
+pub struct DeviceTransport<'a> {
+    channel: &'a OpenChannel,
+    config: IsotpConfig,
+    max_length: usize
+}
+
+impl<'a> DeviceTransport<'a> {
+    /// Create a transport receiving messages of at most 4095 bytes.
+    pub fn new(channel: &'a OpenChannel, config: IsotpConfig) -> DeviceTransport<'a> {
+        DeviceTransport { channel, config, max_length: 4095 }
+    }
+
+    /// Set the maximum length of received messages.
+    pub fn max_length(mut self, value: usize) -> DeviceTransport<'a> {
+        self.max_length = value;
+        self
+    }
+}
+
+impl<'a> Transport for DeviceTransport<'a> {
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        Ok(self.channel.write_isotp(data, Some(SEGMENT_TIMEOUT_MS), &self.config)?)
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        match self.channel.read_isotp(self.max_length, Some(millis(timeout)), &self.config) {
+            Err(ref e) if e.is(BMStatus::ReceiveBufferEmpty) || e.is(BMStatus::BusTimeout) => Err(Error::Timeout),
+            result => Ok(result?)
+        }
+    }
+}
+
+/// One end of an in-memory [loopback], every frame sent is received by the other end.
+pub struct Loopback {
+    tx: Sender<BMCanMessage>,
+    rx: Receiver<BMCanMessage>
+}
+
+/// Create a pair of connected in-memory frame endpoints, i.e. for testing ISO-TP without a device.
+pub fn loopback() -> (Loopback, Loopback) {
+    let (tx_a, rx_a) = mpsc::channel();
+    let (tx_b, rx_b) = mpsc::channel();
+
+    (Loopback { tx: tx_a, rx: rx_b }, Loopback { tx: tx_b, rx: rx_a })
+}
+
+impl FrameIo for Loopback {
+    fn send_frame(&mut self, message: &BMCanMessage, _timeout: Duration) -> ::Result<()> {
+        self.tx.send(*message).map_err(|_| ::Error::new(BMStatus::BusOff))
+    }
+
+    fn recv_frame(&mut self, timeout: Duration) -> ::Result<Option<BMCanMessage>> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(message) => Ok(Some(message)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(::Error::new(BMStatus::BusOff))
+        }
+    }
+}
+
+fn millis(value: Duration) -> i32 {
+    value.as_millis().min(i32::MAX as u128) as i32
+}
+
+/// Encode STmin into its raw value: 0x00-0x7F milliseconds or 0xF1-0xF9 hundreds of microseconds.
+fn encode_st_min(value: Duration) -> Option<u8> {
+    let micros = value.as_micros();
+
+    match micros {
+        0 => Some(0),
+        1..=900 => Some(0xF0 + micros.div_ceil(100) as u8),
+        _ if micros <= 127_000 => Some(micros.div_ceil(1000) as u8),
+        _ => None
+    }
+}
+
+/// Decode raw STmin value, reserved values are interpreted as the longest separation time.
+fn decode_st_min(value: u8) -> Duration {
+    match value {
+        0x00..=0x7F => Duration::from_millis(value as u64),
+        0xF1..=0xF9 => Duration::from_micros((value - 0xF0) as u64 * 100),
+        _ => Duration::from_millis(0x7F)
+    }
+}
@@ -0,0 +1,937 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use ffi::*;
+use super::{decode_st_min, encode_st_min, Error, FrameIo, Result, Timer};
+
+const PCI_SINGLE: u8 = 0x00;
+const PCI_FIRST: u8 = 0x10;
+const PCI_CONSECUTIVE: u8 = 0x20;
+const PCI_FLOW_CONTROL: u8 = 0x30;
+
+const FC_CONTINUE: u8 = 0x00;
+const FC_WAIT: u8 = 0x01;
+const FC_OVERFLOW: u8 = 0x02;
+
+/// Longest message which could be announced by a first frame without escape sequence.
+const MAX_SHORT_LENGTH: usize = 0xFFF;
+
+/// Addressing format, see ISO 15765-2 for details.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Addressing {
+    /// The whole payload is used by ISO-TP
+    Normal,
+    /// The first payload byte holds the target address, `tx` is sent and `rx` is expected in received frames
+    Extended { tx: u8, rx: u8 },
+    /// The first payload byte holds the address extension
+    Mixed { ae: u8 }
+}
+
+impl Addressing {
+    fn tx_byte(&self) -> Option<u8> {
+        match *self {
+            Addressing::Normal => None,
+            Addressing::Extended { tx, .. } => Some(tx),
+            Addressing::Mixed { ae } => Some(ae)
+        }
+    }
+
+    fn rx_byte(&self) -> Option<u8> {
+        match *self {
+            Addressing::Normal => None,
+            Addressing::Extended { rx, .. } => Some(rx),
+            Addressing::Mixed { ae } => Some(ae)
+        }
+    }
+
+    fn offset(&self) -> usize {
+        self.tx_byte().map_or(0, |_| 1)
+    }
+}
+
+/// ISO-TP network layer timeouts, see ISO 15765-2 for details.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Time for transmission of any frame (`N_As`/`N_Ar`)
+    pub n_as: Duration,
+    /// Time until reception of the next flow control frame (`N_Bs`)
+    pub n_bs: Duration,
+    /// Time until reception of the next consecutive frame (`N_Cr`)
+    pub n_cr: Duration
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            n_as: Duration::from_millis(1000),
+            n_bs: Duration::from_millis(1000),
+            n_cr: Duration::from_millis(1000)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Id {
+    id: u32,
+    extended: bool
+}
+
+impl Id {
+    fn matches(&self, message: &BMCanMessage) -> bool {
+        if unsafe { message.ctrl.rx.ide() } != self.extended {
+            return false;
+        }
+
+        if self.extended {
+            message.mid.extended() == self.id
+        } else {
+            message.sid() as u32 == self.id
+        }
+    }
+}
+
+/// Software ISO-TP transport, sending and receiving messages over any [FrameIo].
+///
+/// # Examples
+///
+/// ```
+/// use busmust::isotp::{self, IsoTpSocket};
+///
+/// let (tester, ecu) = isotp::loopback();
+/// let mut tester = IsoTpSocket::builder().tx_sid(0x7E0).rx_sid(0x7E8).build(tester);
+/// let mut ecu = IsoTpSocket::builder().tx_sid(0x7E8).rx_sid(0x7E0).build(ecu);
+///
+/// let request = vec![0x2E; 100];
+/// let sent = request.clone();
+/// let sender = std::thread::spawn(move || tester.send(&sent));
+///
+/// assert_eq!(ecu.recv(std::time::Duration::from_secs(1)).unwrap(), request);
+/// sender.join().unwrap().unwrap();
+/// ```
+pub struct IsoTpSocket<F> {
+    io: F,
+    tx_id: Id,
+    rx_id: Id,
+    addressing: Addressing,
+    fd: bool,
+    brs: bool,
+    tx_dl: usize,
+    padding: Option<u8>,
+    st_min: u8,
+    block_size: u8,
+    max_wait_frames: usize,
+    max_length: usize,
+    timeouts: Timeouts
+}
+
+impl IsoTpSocket<()> {
+    pub fn builder() -> IsoTpSocketBuilder {
+        IsoTpSocketBuilder::default()
+    }
+}
+
+impl<F: FrameIo> IsoTpSocket<F> {
+    /// Get a reference to the underlying frame source/sink.
+    pub fn get_ref(&self) -> &F {
+        &self.io
+    }
+
+    /// Consume the socket, returning the underlying frame source/sink.
+    pub fn into_inner(self) -> F {
+        self.io
+    }
+
+    /// Send a message, segmenting it if it does not fit into a single frame.
+    pub fn send(&mut self, data: &[u8]) -> Result<()> {
+        let offset = self.addressing.offset();
+        if data.len() > u32::MAX as usize {
+            return Err(Error::MessageTooLong(data.len()));
+        }
+
+        // Single frame, using the escape sequence only if the length does not fit into the PCI nibble.
+        if data.len() <= 7 - offset {
+            let mut frame = vec![PCI_SINGLE | data.len() as u8];
+            frame.extend_from_slice(data);
+            return self.send_frame(frame);
+        }
+        if self.tx_dl > 8 && data.len() <= self.tx_dl - 2 - offset {
+            let mut frame = vec![PCI_SINGLE, data.len() as u8];
+            frame.extend_from_slice(data);
+            return self.send_frame(frame);
+        }
+
+        let mut frame = if data.len() <= MAX_SHORT_LENGTH {
+            vec![PCI_FIRST | (data.len() >> 8) as u8, data.len() as u8]
+        } else {
+            let mut frame = vec![PCI_FIRST, 0];
+            frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            frame
+        };
+        let first = self.tx_dl - offset - frame.len();
+        frame.extend_from_slice(&data[..first]);
+        self.send_frame(frame)?;
+
+        let mut remaining = &data[first..];
+        let mut sn = 1u8;
+
+        while !remaining.is_empty() {
+            let (block_size, st_min) = self.wait_flow_control()?;
+            let mut sent = 0usize;
+
+            while !remaining.is_empty() && (block_size == 0 || sent < block_size as usize) {
+                if sent > 0 {
+                    thread::sleep(st_min);
+                }
+
+                let n = remaining.len().min(self.tx_dl - 1 - offset);
+                let mut frame = vec![PCI_CONSECUTIVE | (sn & 0x0F)];
+                frame.extend_from_slice(&remaining[..n]);
+                self.send_frame(frame)?;
+
+                remaining = &remaining[n..];
+                sn = sn.wrapping_add(1);
+                sent += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Receive a message, waiting at most `timeout` for its first frame.
+    pub fn recv(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+
+        let mut pending = None;
+        loop {
+            let pci = match pending.take() {
+                Some(pci) => pci,
+                None => match self.recv_pdu(deadline)? {
+                    Some(pci) => pci,
+                    None => return Err(Error::Timeout)
+                }
+            };
+
+            match pci[0] & 0xF0 {
+                PCI_SINGLE => return parse_single(&pci, self.addressing.offset()),
+                PCI_FIRST => {
+                    // A new single or first frame aborts the reception in progress and is processed instead.
+                    match self.recv_segmented(&pci)? {
+                        Segmented::Complete(data) => return Ok(data),
+                        Segmented::Restarted(pci) => pending = Some(pci)
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn recv_segmented(&mut self, first: &[u8]) -> Result<Segmented> {
+        let (length, header) = parse_first(first)?;
+
+        if length > self.max_length {
+            self.send_flow_control(FC_OVERFLOW)?;
+            return Err(Error::MessageTooLong(length));
+        }
+
+        let mut data = Vec::with_capacity(length);
+        data.extend_from_slice(&first[header..first.len().min(header + length)]);
+
+        let mut sn = 1u8;
+        while data.len() < length {
+            self.send_flow_control(FC_CONTINUE)?;
+
+            let mut received = 0usize;
+            while data.len() < length && (self.block_size == 0 || received < self.block_size as usize) {
+                let deadline = Instant::now() + self.timeouts.n_cr;
+                let pci = match self.recv_pdu(deadline)? {
+                    Some(pci) => pci,
+                    None => return Err(Error::TimerExpired(Timer::Cr))
+                };
+
+                match pci[0] & 0xF0 {
+                    PCI_CONSECUTIVE => {
+                        let received_sn = pci[0] & 0x0F;
+                        if received_sn != sn & 0x0F {
+                            return Err(Error::WrongSequenceNumber { expected: sn & 0x0F, received: received_sn });
+                        }
+
+                        let n = (length - data.len()).min(pci.len() - 1);
+                        data.extend_from_slice(&pci[1..1 + n]);
+                        sn = sn.wrapping_add(1);
+                        received += 1;
+                    }
+                    PCI_SINGLE | PCI_FIRST => return Ok(Segmented::Restarted(pci)),
+                    _ => {}
+                }
+            }
+        }
+        Ok(Segmented::Complete(data))
+    }
+
+    fn wait_flow_control(&mut self) -> Result<(u8, Duration)> {
+        let mut waits = 0;
+
+        loop {
+            let deadline = Instant::now() + self.timeouts.n_bs;
+            let pci = loop {
+                match self.recv_pdu(deadline)? {
+                    Some(ref pci) if pci[0] & 0xF0 == PCI_FLOW_CONTROL => break pci.clone(),
+                    Some(_) => continue,
+                    None => return Err(Error::TimerExpired(Timer::Bs))
+                }
+            };
+
+            if pci.len() < 3 {
+                return Err(Error::InvalidFrame);
+            }
+
+            match pci[0] & 0x0F {
+                FC_CONTINUE => return Ok((pci[1], decode_st_min(pci[2]))),
+                FC_WAIT => {
+                    waits += 1;
+                    if waits > self.max_wait_frames {
+                        return Err(Error::WaitLimitExceeded);
+                    }
+                }
+                FC_OVERFLOW => return Err(Error::Overflow),
+                _ => return Err(Error::InvalidFrame)
+            }
+        }
+    }
+
+    fn send_flow_control(&mut self, status: u8) -> Result<()> {
+        let frame = vec![PCI_FLOW_CONTROL | status, self.block_size, self.st_min];
+        self.send_frame(frame).map_err(|e| match e {
+            Error::TimerExpired(Timer::As) => Error::TimerExpired(Timer::Ar),
+            e => e
+        })
+    }
+
+    /// Receive the next frame addressed to this socket and return its ISO-TP PDU (addressing byte stripped).
+    fn recv_pdu(&mut self, deadline: Instant) -> Result<Option<Vec<u8>>> {
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            let message = match self.io.recv_frame(deadline - now)? {
+                Some(message) => message,
+                None => return Ok(None)
+            };
+            // Remote frames carry no payload and are never part of an ISO-TP transfer.
+            if !self.rx_id.matches(&message) || unsafe { message.ctrl.rx.rtr() } {
+                continue;
+            }
+
+            let payload = message.payload();
+
+            let payload = match self.addressing.rx_byte() {
+                Some(address) if payload.first() == Some(&address) => &payload[1..],
+                Some(_) => continue,
+                None => payload
+            };
+
+            if !payload.is_empty() {
+                return Ok(Some(payload.to_vec()));
+            }
+        }
+    }
+
+    /// Send an ISO-TP PDU, prepending the addressing byte and padding the frame as needed.
+    fn send_frame(&mut self, pdu: Vec<u8>) -> Result<()> {
+        let mut payload = Vec::with_capacity(self.tx_dl);
+        if let Some(address) = self.addressing.tx_byte() {
+            payload.push(address);
+        }
+        payload.extend(pdu);
+
+        // CAN-FD frames longer than 8 bytes must always be padded up to the next valid length. Frames fitting
+        // into 8 bytes are padded to 8 only, as single frames longer than that require the escape sequence.
+        let length = match self.padding {
+            Some(_) if payload.len() <= 8 => 8,
+            Some(_) => self.tx_dl.max(payload.len()),
+            None => payload.len()
+        };
+        let dlc = len_to_dlc(length).expect("frame too long");
+        payload.resize(dlc_to_len(dlc), self.padding.unwrap_or(0xCC));
+
+        let message = BMCanMessage::builder()
+            .sid(if self.tx_id.extended { 0 } else { self.tx_id.id as u16 })
+            .ide(self.tx_id.extended)
+            .fdf(self.fd)
+            .brs(self.brs)
+            .payload(payload)
+            .dlc(dlc)
+            .build();
+        let message = if self.tx_id.extended {
+            BMCanMessage { mid: BMMessageId::from_extended(self.tx_id.id), ..message }
+        } else {
+            message
+        };
+
+        let started = Instant::now();
+        match self.io.send_frame(&message, self.timeouts.n_as) {
+            Ok(()) => Ok(()),
+            Err(_) if started.elapsed() >= self.timeouts.n_as => Err(Error::TimerExpired(Timer::As)),
+            Err(e) => Err(Error::Device(e))
+        }
+    }
+}
+
+enum Segmented {
+    Complete(Vec<u8>),
+    Restarted(Vec<u8>)
+}
+
+/// Parse a single frame PDU, received in a frame with `offset` addressing bytes before it.
+///
+/// Frames longer than 8 bytes must use the escape sequence, shorter ones the PCI nibble.
+fn parse_single(pci: &[u8], offset: usize) -> Result<Vec<u8>> {
+    let escaped = pci.len() + offset > 8;
+    let (length, header) = match pci[0] & 0x0F {
+        0 if escaped && pci.len() > 1 => (pci[1] as usize, 2),
+        length if !escaped && length != 0 => (length as usize, 1),
+        _ => return Err(Error::InvalidFrame)
+    };
+
+    if header + length > pci.len() {
+        return Err(Error::InvalidFrame);
+    }
+    Ok(pci[header..header + length].to_vec())
+}
+
+fn parse_first(pci: &[u8]) -> Result<(usize, usize)> {
+    if pci.len() < 2 {
+        return Err(Error::InvalidFrame);
+    }
+
+    let length = (((pci[0] & 0x0F) as usize) << 8) | pci[1] as usize;
+    if length != 0 {
+        return Ok((length, 2));
+    }
+
+    if pci.len() < 6 {
+        return Err(Error::InvalidFrame);
+    }
+    let length = u32::from_be_bytes([pci[2], pci[3], pci[4], pci[5]]) as usize;
+    Ok((length, 6))
+}
+
+/// Builder for [IsoTpSocket].
+pub struct IsoTpSocketBuilder {
+    tx_id: Id,
+    rx_id: Id,
+    addressing: Addressing,
+    fd: bool,
+    brs: bool,
+    tx_dl: usize,
+    padding: Option<u8>,
+    st_min: Duration,
+    block_size: u8,
+    max_wait_frames: usize,
+    max_length: usize,
+    timeouts: Timeouts
+}
+
+impl Default for IsoTpSocketBuilder {
+    fn default() -> IsoTpSocketBuilder {
+        IsoTpSocketBuilder {
+            tx_id: Id { id: 0, extended: false },
+            rx_id: Id { id: 0, extended: false },
+            addressing: Addressing::Normal,
+            fd: false,
+            brs: false,
+            tx_dl: 8,
+            padding: None,
+            st_min: Duration::from_millis(0),
+            block_size: 0,
+            max_wait_frames: 10,
+            max_length: MAX_SHORT_LENGTH,
+            timeouts: Timeouts::default()
+        }
+    }
+}
+
+impl IsoTpSocketBuilder {
+    pub fn new() -> IsoTpSocketBuilder {
+        IsoTpSocketBuilder::default()
+    }
+
+    /// Send frames using the given standard ID.
+    pub fn tx_sid(mut self, id: u16) -> IsoTpSocketBuilder {
+        self.tx_id = Id { id: (id & 0x7FF) as u32, extended: false };
+        self
+    }
+
+    /// Send frames using the given extended ID.
+    pub fn tx_eid(mut self, id: u32) -> IsoTpSocketBuilder {
+        self.tx_id = Id { id: id & 0x1FFF_FFFF, extended: true };
+        self
+    }
+
+    /// Receive frames with the given standard ID.
+    pub fn rx_sid(mut self, id: u16) -> IsoTpSocketBuilder {
+        self.rx_id = Id { id: (id & 0x7FF) as u32, extended: false };
+        self
+    }
+
+    /// Receive frames with the given extended ID.
+    pub fn rx_eid(mut self, id: u32) -> IsoTpSocketBuilder {
+        self.rx_id = Id { id: id & 0x1FFF_FFFF, extended: true };
+        self
+    }
+
+    pub fn addressing(mut self, value: Addressing) -> IsoTpSocketBuilder {
+        self.addressing = value;
+        self
+    }
+
+    /// Send CAN-FD frames of at most `tx_dl` bytes (one of 8, 12, 16, 20, 24, 32, 48 or 64),
+    /// optionally using bitrate switching.
+    pub fn fd(mut self, tx_dl: usize, brs: bool) -> IsoTpSocketBuilder {
+        self.fd = true;
+        self.brs = brs;
+        self.tx_dl = len_to_dlc(tx_dl.max(8)).map_or(64, dlc_to_len);
+        self
+    }
+
+    /// Pad frames to their full length with the given value, or send frames of minimal length with `None`.
+    pub fn padding(mut self, value: Option<u8>) -> IsoTpSocketBuilder {
+        self.padding = value;
+        self
+    }
+
+    /// Minimum separation time requested from the sender, values above 127 ms are clamped.
+    pub fn st_min(mut self, value: Duration) -> IsoTpSocketBuilder {
+        self.st_min = value;
+        self
+    }
+
+    /// Number of consecutive frames received between flow control frames, 0 means unlimited.
+    pub fn block_size(mut self, value: u8) -> IsoTpSocketBuilder {
+        self.block_size = value;
+        self
+    }
+
+    /// Maximum number of consecutive flow control wait frames accepted while sending.
+    pub fn max_wait_frames(mut self, value: usize) -> IsoTpSocketBuilder {
+        self.max_wait_frames = value;
+        self
+    }
+
+    /// Maximum length of received messages, longer messages are rejected with a flow control overflow.
+    pub fn max_length(mut self, value: usize) -> IsoTpSocketBuilder {
+        self.max_length = value;
+        self
+    }
+
+    pub fn timeouts(mut self, value: Timeouts) -> IsoTpSocketBuilder {
+        self.timeouts = value;
+        self
+    }
+
+    pub fn build<F: FrameIo>(self, io: F) -> IsoTpSocket<F> {
+        IsoTpSocket {
+            io,
+            tx_id: self.tx_id,
+            rx_id: self.rx_id,
+            addressing: self.addressing,
+            fd: self.fd,
+            brs: self.brs,
+            tx_dl: self.tx_dl,
+            padding: self.padding,
+            st_min: encode_st_min(self.st_min).unwrap_or(0x7F),
+            block_size: self.block_size,
+            max_wait_frames: self.max_wait_frames,
+            max_length: self.max_length,
+            timeouts: self.timeouts
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use backend::VirtualBus;
+    use dmgr::{OpenChannel, OpenOptions};
+    use isotp::{loopback, Loopback};
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Frame seen on the bus: identifier, payload and timestamp.
+    type Seen = (u32, Vec<u8>, u32);
+
+    /// Open a tester, an ECU and a listen-only sniffer on a virtual bus.
+    fn open_bus() -> (OpenChannel, OpenChannel, OpenChannel) {
+        let bus = VirtualBus::new(3);
+        let mut devices = bus.enum_devices().unwrap();
+        let tester = devices.next().unwrap().open_ex().unwrap();
+        let ecu = devices.next().unwrap().open_ex().unwrap();
+        let sniffer = OpenOptions::new().mode(BMCanMode::ListenOnly).open(&devices.next().unwrap()).unwrap();
+        (tester, ecu, sniffer)
+    }
+
+    fn tester() -> IsoTpSocketBuilder {
+        IsoTpSocket::builder().tx_sid(0x7E0).rx_sid(0x7E8)
+    }
+
+    fn ecu() -> IsoTpSocketBuilder {
+        IsoTpSocket::builder().tx_sid(0x7E8).rx_sid(0x7E0)
+    }
+
+    fn sniff(sniffer: &OpenChannel) -> Vec<Seen> {
+        sniffer.read_can_messages(256, Some(0)).unwrap().iter()
+            .map(|(message, _, timestamp)| (message.id().raw(), message.payload().to_vec(), timestamp))
+            .collect()
+    }
+
+    /// Send `data` from the tester to the ECU, returning the received message and the frames seen on the bus.
+    fn transfer(tester: IsoTpSocketBuilder, ecu: IsoTpSocketBuilder, data: &[u8]) -> (Vec<u8>, Vec<Seen>) {
+        let (tester_channel, ecu_channel, sniffer) = open_bus();
+
+        let sent = data.to_vec();
+        let sender = thread::spawn(move || tester.build(tester_channel).send(&sent));
+
+        let received = ecu.build(ecu_channel).recv(TIMEOUT).unwrap();
+        sender.join().unwrap().unwrap();
+        (received, sniff(&sniffer))
+    }
+
+    fn pci(frames: &[Seen]) -> Vec<u8> {
+        frames.iter().map(|frame| frame.1[0]).collect()
+    }
+
+    #[test]
+    fn single_frame() {
+        let (received, frames) = transfer(tester(), ecu(), &[0x22, 0xF1, 0x90]);
+
+        assert_eq!(received, vec![0x22, 0xF1, 0x90]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0, 0x7E0);
+        assert_eq!(frames[0].1, vec![0x03, 0x22, 0xF1, 0x90]);
+    }
+
+    #[test]
+    fn single_frame_padded() {
+        let (received, frames) = transfer(tester().padding(Some(0xAA)), ecu(), &[0x3E, 0x00]);
+
+        assert_eq!(received, vec![0x3E, 0x00]);
+        assert_eq!(frames[0].1, vec![0x02, 0x3E, 0x00, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA]);
+    }
+
+    #[test]
+    fn first_and_consecutive_frames() {
+        let data: Vec<u8> = (0..20).collect();
+        let (received, frames) = transfer(tester(), ecu(), &data);
+
+        assert_eq!(received, data);
+        assert_eq!(pci(&frames), vec![0x10, 0x30, 0x21, 0x22]);
+        assert_eq!(frames[0].1, vec![0x10, 20, 0, 1, 2, 3, 4, 5]);
+        assert_eq!(frames[1], (0x7E8, vec![0x30, 0x00, 0x00], frames[1].2));
+        assert_eq!(frames[2].1, vec![0x21, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(frames[3].1, vec![0x22, 13, 14, 15, 16, 17, 18, 19]);
+    }
+
+    #[test]
+    fn block_size() {
+        let data: Vec<u8> = (0..40).collect();
+        let (received, frames) = transfer(tester(), ecu().block_size(2), &data);
+
+        assert_eq!(received, data);
+        assert_eq!(pci(&frames), vec![0x10, 0x30, 0x21, 0x22, 0x30, 0x23, 0x24, 0x30, 0x25]);
+        assert_eq!(frames[1].1, vec![0x30, 0x02, 0x00]);
+    }
+
+    #[test]
+    fn st_min() {
+        let data: Vec<u8> = (0..34).collect();
+        let (received, frames) = transfer(tester(), ecu().st_min(Duration::from_millis(5)), &data);
+
+        assert_eq!(received, data);
+        assert_eq!(frames[1].1, vec![0x30, 0x00, 0x05]);
+
+        let consecutive: Vec<u32> = frames.iter().filter(|frame| frame.1[0] & 0xF0 == PCI_CONSECUTIVE)
+            .map(|frame| frame.2)
+            .collect();
+        assert_eq!(consecutive.len(), 4);
+        assert!(consecutive.windows(2).all(|pair| pair[1] - pair[0] >= 5000));
+    }
+
+    #[test]
+    fn sequence_number_wraps() {
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let (received, frames) = transfer(tester(), ecu(), &data);
+
+        assert_eq!(received, data);
+
+        let sequence: Vec<u8> = frames[2..].iter().map(|frame| frame.1[0]).collect();
+        let expected: Vec<u8> = (1..=28).map(|sn| PCI_CONSECUTIVE | (sn & 0x0F) as u8).collect();
+        assert_eq!(sequence, expected);
+        assert_eq!(&sequence[14..17], &[0x2F, 0x20, 0x21]);
+    }
+
+    #[test]
+    fn overflow() {
+        let (tester_channel, ecu_channel, _sniffer) = open_bus();
+
+        let sender = thread::spawn(move || tester().build(tester_channel).send(&[0; 100]));
+        let received = ecu().max_length(50).build(ecu_channel).recv(TIMEOUT);
+
+        assert!(matches!(received, Err(Error::MessageTooLong(100))));
+        assert!(matches!(sender.join().unwrap(), Err(Error::Overflow)));
+    }
+
+    #[test]
+    fn classic_frame_uses_eight_bytes() {
+        let (tester_channel, ecu_channel, _sniffer) = open_bus();
+
+        // Single frame escape sequence announcing 10 bytes, which only fit a CAN FD frame.
+        let mut payload = vec![0x00, 0x0A];
+        payload.extend(1..=10);
+        let message = BMCanMessage::builder().sid(0x7E0).payload(payload).build();
+        tester_channel.write_can_message(message, Some(100)).unwrap();
+
+        let received = ecu().build(&ecu_channel).recv(TIMEOUT);
+        assert!(matches!(received, Err(Error::InvalidFrame)));
+    }
+
+    #[test]
+    fn remote_frames_ignored() {
+        let (tester_channel, ecu_channel, _sniffer) = open_bus();
+
+        let remote = BMCanMessage::builder().sid(0x7E0).rtr(true).dlc(8).build();
+        tester_channel.write_can_message(remote, Some(100)).unwrap();
+        tester().build(&tester_channel).send(&[0x10, 0x03]).unwrap();
+
+        assert_eq!(ecu().build(&ecu_channel).recv(TIMEOUT).unwrap(), vec![0x10, 0x03]);
+    }
+
+    /// Loopback end recording every frame sent through it into a log shared by both ends.
+    struct Tap {
+        io: Loopback,
+        log: Arc<Mutex<Vec<BMCanMessage>>>
+    }
+
+    impl FrameIo for Tap {
+        fn send_frame(&mut self, message: &BMCanMessage, timeout: Duration) -> ::Result<()> {
+            self.log.lock().unwrap().push(*message);
+            self.io.send_frame(message, timeout)
+        }
+
+        fn recv_frame(&mut self, timeout: Duration) -> ::Result<Option<BMCanMessage>> {
+            self.io.recv_frame(timeout)
+        }
+    }
+
+    /// Send `data` from the tester to the ECU over an in-memory loopback, returning the received message
+    /// and all frames sent by both ends.
+    fn transfer_loopback(tester: IsoTpSocketBuilder, ecu: IsoTpSocketBuilder, data: &[u8]) -> (Vec<u8>, Vec<BMCanMessage>) {
+        let (tester_io, ecu_io) = loopback();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let tester_io = Tap { io: tester_io, log: log.clone() };
+        let ecu_io = Tap { io: ecu_io, log: log.clone() };
+
+        let sent = data.to_vec();
+        let sender = thread::spawn(move || tester.build(tester_io).send(&sent));
+
+        let received = ecu.build(ecu_io).recv(TIMEOUT).unwrap();
+        sender.join().unwrap().unwrap();
+
+        let frames = log.lock().unwrap().clone();
+        (received, frames)
+    }
+
+    fn raw_frame(id: u16, payload: Vec<u8>, fd: bool) -> BMCanMessage {
+        BMCanMessage::builder().sid(id).fdf(fd).payload(payload).build()
+    }
+
+    fn is_fd(message: &BMCanMessage) -> bool {
+        unsafe { message.ctrl.rx.fdf() }
+    }
+
+    #[test]
+    fn fd_single_frame_padded_to_eight_bytes() {
+        let (received, frames) = transfer_loopback(tester().fd(64, true).padding(Some(0xAA)), ecu().fd(64, true), &[1, 2, 3]);
+
+        assert_eq!(received, vec![1, 2, 3]);
+        assert_eq!(frames.len(), 1);
+        assert!(is_fd(&frames[0]));
+        assert_eq!(frames[0].payload(), &[0x03, 1, 2, 3, 0xAA, 0xAA, 0xAA, 0xAA]);
+    }
+
+    #[test]
+    fn fd_single_frame_escape_sequence() {
+        let data: Vec<u8> = (0..20).collect();
+        let (received, frames) = transfer_loopback(tester().fd(64, false).padding(Some(0xAA)), ecu().fd(64, false), &data);
+
+        assert_eq!(received, data);
+        assert_eq!(frames.len(), 1);
+
+        let payload = frames[0].payload();
+        assert_eq!(payload.len(), 64);
+        assert_eq!(&payload[..2], &[0x00, 20]);
+        assert_eq!(&payload[2..22], &data[..]);
+        assert!(payload[22..].iter().all(|&b| b == 0xAA));
+
+        // Unpadded frames are only extended to the next valid CAN FD length.
+        let (_, frames) = transfer_loopback(tester().fd(64, false), ecu().fd(64, false), &data);
+        assert_eq!(frames[0].payload().len(), 24);
+    }
+
+    #[test]
+    fn fd_segmentation() {
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let (received, frames) = transfer_loopback(tester().fd(64, true), ecu().fd(64, true), &data);
+
+        assert_eq!(received, data);
+        assert!(frames.iter().all(is_fd));
+
+        let sent: Vec<&BMCanMessage> = frames.iter().filter(|frame| frame.sid() == 0x7E0).collect();
+        assert_eq!(sent.len(), 4);
+        assert_eq!(&sent[0].payload()[..2], &[0x10, 200]);
+        assert_eq!(&sent[0].payload()[2..], &data[..62]);
+
+        // Consecutive frames hold `tx_dl - 1` bytes, the last one is extended to the next valid length.
+        assert_eq!(sent[1].payload()[0], 0x21);
+        assert_eq!(&sent[1].payload()[1..], &data[62..125]);
+        assert_eq!(&sent[2].payload()[1..], &data[125..188]);
+        assert_eq!(sent[3].payload().len(), 16);
+        assert_eq!(&sent[3].payload()[1..13], &data[188..]);
+    }
+
+    #[test]
+    fn fd_first_frame_escape_sequence() {
+        let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let (received, frames) = transfer_loopback(tester().fd(64, true), ecu().fd(64, true).max_length(5000), &data);
+
+        assert_eq!(received, data);
+
+        let first = frames[0].payload();
+        assert_eq!(&first[..6], &[0x10, 0x00, 0x00, 0x00, 0x13, 0x88]);
+        assert_eq!(&first[6..], &data[..58]);
+
+        let consecutive = frames.iter().filter(|frame| frame.payload()[0] & 0xF0 == PCI_CONSECUTIVE).count();
+        assert_eq!(consecutive, 79);
+    }
+
+    #[test]
+    fn single_frame_length_must_match_frame_length() {
+        let (mut tester, ecu_io) = loopback();
+        let mut ecu = ecu().fd(64, false).build(ecu_io);
+
+        // PCI nibble in a frame longer than 8 bytes.
+        let mut payload = vec![0x03, 1, 2, 3];
+        payload.resize(64, 0xAA);
+        tester.send_frame(&raw_frame(0x7E0, payload, true), TIMEOUT).unwrap();
+        assert!(matches!(ecu.recv(TIMEOUT), Err(Error::InvalidFrame)));
+
+        // Escape sequence in a frame of 8 bytes.
+        tester.send_frame(&raw_frame(0x7E0, vec![0x00, 0x03, 1, 2, 3, 0, 0, 0], true), TIMEOUT).unwrap();
+        assert!(matches!(ecu.recv(TIMEOUT), Err(Error::InvalidFrame)));
+
+        tester.send_frame(&raw_frame(0x7E0, vec![0x03, 1, 2, 3, 0, 0, 0, 0], true), TIMEOUT).unwrap();
+        assert_eq!(ecu.recv(TIMEOUT).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn extended_addressing() {
+        let data: Vec<u8> = (0..20).collect();
+        let tester = tester().addressing(Addressing::Extended { tx: 0x10, rx: 0xF1 });
+        let ecu = ecu().addressing(Addressing::Extended { tx: 0xF1, rx: 0x10 });
+        let (received, frames) = transfer_loopback(tester, ecu, &data);
+
+        assert_eq!(received, data);
+
+        let payloads: Vec<&[u8]> = frames.iter().map(|frame| frame.payload()).collect();
+        assert_eq!(payloads, vec![
+            &[0x10, 0x10, 20, 0, 1, 2, 3, 4][..],
+            &[0xF1, 0x30, 0x00, 0x00][..],
+            &[0x10, 0x21, 5, 6, 7, 8, 9, 10][..],
+            &[0x10, 0x22, 11, 12, 13, 14, 15, 16][..],
+            &[0x10, 0x23, 17, 18, 19][..]
+        ]);
+    }
+
+    #[test]
+    fn extended_addressing_ignores_other_targets() {
+        let (mut tester, ecu_io) = loopback();
+        let mut ecu = ecu().addressing(Addressing::Extended { tx: 0xF1, rx: 0x10 }).build(ecu_io);
+
+        tester.send_frame(&raw_frame(0x7E0, vec![0x22, 0x02, 0x3E, 0x00], false), TIMEOUT).unwrap();
+        tester.send_frame(&raw_frame(0x7E0, vec![0x10, 0x02, 0x10, 0x03], false), TIMEOUT).unwrap();
+
+        assert_eq!(ecu.recv(TIMEOUT).unwrap(), vec![0x10, 0x03]);
+    }
+
+    #[test]
+    fn mixed_addressing() {
+        let data: Vec<u8> = (0..20).collect();
+        let addressing = Addressing::Mixed { ae: 0x42 };
+        let (received, frames) = transfer_loopback(tester().addressing(addressing), ecu().addressing(addressing), &data);
+
+        assert_eq!(received, data);
+        assert_eq!(frames.len(), 5);
+        assert!(frames.iter().all(|frame| frame.payload()[0] == 0x42));
+        assert_eq!(frames[1].payload(), &[0x42, 0x30, 0x00, 0x00]);
+
+        // Frames with another address extension are dropped.
+        let (mut tester, ecu_io) = loopback();
+        let mut ecu = ecu().addressing(addressing).build(ecu_io);
+        tester.send_frame(&raw_frame(0x7E0, vec![0x43, 0x01, 0x3E], false), TIMEOUT).unwrap();
+        assert!(matches!(ecu.recv(Duration::from_millis(50)), Err(Error::Timeout)));
+    }
+
+    fn short_timeouts() -> Timeouts {
+        Timeouts { n_bs: Duration::from_millis(50), n_cr: Duration::from_millis(50), ..Timeouts::default() }
+    }
+
+    #[test]
+    fn bs_timer() {
+        let (tester_io, _ecu) = loopback();
+        let mut tester = tester().timeouts(short_timeouts()).build(tester_io);
+
+        let started = Instant::now();
+        assert!(matches!(tester.send(&[0; 20]), Err(Error::TimerExpired(Timer::Bs))));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn cr_timer() {
+        let (mut tester, ecu_io) = loopback();
+        let mut ecu = ecu().timeouts(short_timeouts()).build(ecu_io);
+
+        tester.send_frame(&raw_frame(0x7E0, vec![0x10, 20, 0, 1, 2, 3, 4, 5], false), TIMEOUT).unwrap();
+        assert!(matches!(ecu.recv(TIMEOUT), Err(Error::TimerExpired(Timer::Cr))));
+
+        let flow_control = tester.recv_frame(TIMEOUT).unwrap().unwrap();
+        assert_eq!(flow_control.payload(), &[0x30, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn flow_control_wait() {
+        let (tester_io, mut ecu) = loopback();
+        let mut tester = tester().build(tester_io);
+
+        // Flow control frames are queued up front, the sender consumes them after its first frame.
+        for pci in [0x31, 0x31, 0x30].iter() {
+            ecu.send_frame(&raw_frame(0x7E8, vec![*pci, 0x00, 0x00], false), TIMEOUT).unwrap();
+        }
+        let data: Vec<u8> = (0..20).collect();
+        tester.send(&data).unwrap();
+
+        let frames: Vec<u8> = (0..3).map(|_| ecu.recv_frame(TIMEOUT).unwrap().unwrap().payload()[0]).collect();
+        assert_eq!(frames, vec![0x10, 0x21, 0x22]);
+    }
+
+    #[test]
+    fn flow_control_wait_limit() {
+        let (tester_io, mut ecu) = loopback();
+        let mut tester = tester().max_wait_frames(1).build(tester_io);
+
+        for _ in 0..2 {
+            ecu.send_frame(&raw_frame(0x7E8, vec![0x31, 0x00, 0x00], false), TIMEOUT).unwrap();
+        }
+        assert!(matches!(tester.send(&[0; 20]), Err(Error::WaitLimitExceeded)));
+    }
+}
@@ -1,25 +1,30 @@
 extern crate busmust_sys as ffi;
-
-use std::fmt;
-use dmgr::desc_from_error;
+#[cfg(feature = "tokio")]
+extern crate futures_core;
+#[cfg(feature = "tokio")]
+extern crate futures_sink;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(all(test, feature = "tokio"))]
+extern crate futures;
 
 mod call;
+mod error;
 mod util;
+#[cfg(feature = "tokio")]
+pub mod aio;
+pub mod backend;
 pub mod dmgr;
+pub mod frame;
+pub mod timestamp;
+pub mod txtask;
+pub mod isotp;
+pub mod log;
+pub mod uds;
 
-#[derive(Debug, Clone)]
-pub struct Error(ffi::BMStatus);
-
-pub type Result<T> = std::result::Result<T, Error>;
+#[cfg(feature = "dynamic")]
+pub use ffi::loader;
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "error: {:?} ({})", self, desc_from_error(self))
-    }
-}
+pub use error::{Error, ErrorKind, Status};
 
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
-    }
-}
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,480 @@
+//! Reader and writer of the log format produced by `candump -L` of can-utils.
+//!
+//! Every line holds a timestamp, the interface name and a frame:
+//!
+//! ```text
+//! (1436509052.249713) can0 123#DEADBEEF
+//! (1436509052.250122) can0 18DAF110#R
+//! (1436509052.250385) can0 123##1112233
+//! ```
+//!
+//! Standard identifiers are written with 3 and extended ones with 8 hex digits. Remote frames are marked with `R`
+//! followed by their DLC if it is not zero. CAN FD frames use `##` followed by a hex digit of flags
+//! (`1` bitrate switch, `2` error state indicator) and their payload.
+
+use std::convert::TryFrom;
+use std::fmt::{self, Write as FmtWrite};
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::time::Duration;
+use ffi::*;
+use frame::{self, CanFdFrame, CanFrame, ExtendedId, Frame, Id, StandardId};
+
+/// CAN FD flag marking bitrate switching.
+const FLAG_BRS: u8 = 0x01;
+/// CAN FD flag marking the error state indicator.
+const FLAG_ESI: u8 = 0x02;
+
+/// Error reading or writing a candump log.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the underlying stream failed
+    Io(io::Error),
+    /// The line does not follow the candump log format
+    InvalidSyntax,
+    /// The frame is invalid, or it cannot be represented as [Frame]
+    InvalidFrame(frame::Error)
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::InvalidSyntax => write!(f, "invalid candump log line"),
+            Error::InvalidFrame(e) => write!(f, "invalid frame: {}", e)
+        }
+    }
+}
+
+impl ::std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::InvalidSyntax => None,
+            Error::InvalidFrame(e) => Some(e)
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<frame::Error> for Error {
+    fn from(e: frame::Error) -> Error {
+        Error::InvalidFrame(e)
+    }
+}
+
+/// Single line of a candump log.
+///
+/// # Examples
+///
+/// ```
+/// use busmust::log::candump::LogEntry;
+///
+/// let entry: LogEntry = "(1436509052.249713) can0 123#DEADBEEF".parse().unwrap();
+/// assert_eq!(entry.interface, "can0");
+/// assert_eq!(entry.frame.data(), &[0xDE, 0xAD, 0xBE, 0xEF]);
+/// assert_eq!(entry.to_string(), "(1436509052.249713) can0 123#DEADBEEF");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// Receive time, usually since [std::time::UNIX_EPOCH], written with microsecond precision
+    pub timestamp: Duration,
+    /// Name of the interface the frame was received on, without whitespace
+    pub interface: String,
+    pub frame: Frame
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({:010}.{:06}) {} ", self.timestamp.as_secs(), self.timestamp.subsec_micros(), self.interface)?;
+
+        match self.frame.id() {
+            Id::Standard(id) => write!(f, "{:03X}", id.as_raw())?,
+            Id::Extended(id) => write!(f, "{:08X}", id.as_raw())?
+        }
+
+        match &self.frame {
+            Frame::Can(frame) if frame.is_remote() => {
+                f.write_str("#R")?;
+                if frame.dlc() != 0 {
+                    write!(f, "{:X}", frame.dlc())?;
+                }
+                Ok(())
+            }
+            Frame::Can(frame) => {
+                f.write_char('#')?;
+                write_hex(f, frame.data())
+            }
+            Frame::Fd(frame) => {
+                let flags = if frame.brs() { FLAG_BRS } else { 0 } | if frame.esi() { FLAG_ESI } else { 0 };
+                write!(f, "##{:X}", flags)?;
+                write_hex(f, frame.data())
+            }
+        }
+    }
+}
+
+impl FromStr for LogEntry {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<LogEntry> {
+        let mut fields = line.split_whitespace();
+        let (timestamp, interface, frame) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(timestamp), Some(interface), Some(frame), None) => (timestamp, interface, frame),
+            _ => return Err(Error::InvalidSyntax)
+        };
+
+        Ok(LogEntry {
+            timestamp: parse_timestamp(timestamp).ok_or(Error::InvalidSyntax)?,
+            interface: interface.to_string(),
+            frame: parse_frame(frame)?
+        })
+    }
+}
+
+/// Parse `(sec.usec)`.
+fn parse_timestamp(field: &str) -> Option<Duration> {
+    let field = field.strip_prefix('(')?.strip_suffix(')')?;
+    let (secs, fraction) = field.split_once('.')?;
+    if secs.is_empty() || fraction.is_empty() || fraction.len() > 9 || !is_digits(secs) || !is_digits(fraction) {
+        return None;
+    }
+
+    let nanos = format!("{:0<9}", fraction).parse::<u32>().ok()?;
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+/// Parse `ID#DATA`, `ID#R[DLC]` or `ID##FLAGS[DATA]`.
+fn parse_frame(field: &str) -> Result<Frame> {
+    let (id, rest) = field.split_once('#').ok_or(Error::InvalidSyntax)?;
+    if !is_hex(id) {
+        return Err(Error::InvalidSyntax);
+    }
+
+    let raw = u32::from_str_radix(id, 16).map_err(|_| Error::InvalidSyntax)?;
+    let id = match id.len() {
+        3 => Id::from(StandardId::new(raw as u16).map_err(|_| frame::Error::InvalidId(raw))?),
+        8 => Id::from(ExtendedId::new(raw)?),
+        _ => return Err(Error::InvalidSyntax)
+    };
+
+    if let Some(rest) = rest.strip_prefix('#') {
+        let mut chars = rest.chars();
+        let flags = chars.next().and_then(|c| c.to_digit(16)).ok_or(Error::InvalidSyntax)? as u8;
+        let data = parse_hex(chars.as_str())?;
+
+        let frame = CanFdFrame::new(id, &data)?
+            .with_brs(flags & FLAG_BRS != 0)
+            .with_esi(flags & FLAG_ESI != 0);
+        Ok(Frame::Fd(frame))
+    } else if let Some(dlc) = rest.strip_prefix('R') {
+        let dlc = match dlc {
+            "" => 0,
+            dlc if dlc.len() == 1 => u8::from_str_radix(dlc, 16).map_err(|_| Error::InvalidSyntax)?,
+            _ => return Err(Error::InvalidSyntax)
+        };
+        Ok(Frame::Can(CanFrame::new_remote(id, dlc)?))
+    } else {
+        Ok(Frame::Can(CanFrame::new(id, &parse_hex(rest)?)?))
+    }
+}
+
+/// Parse pairs of hex digits, optionally separated by dots.
+fn parse_hex(digits: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = digits.bytes().filter(|&b| b != b'.').collect();
+    if digits.len() & 1 != 0 {
+        return Err(Error::InvalidSyntax);
+    }
+
+    digits.chunks(2)
+        .map(|pair| {
+            let pair = ::std::str::from_utf8(pair).map_err(|_| Error::InvalidSyntax)?;
+            if !is_hex(pair) {
+                return Err(Error::InvalidSyntax);
+            }
+            u8::from_str_radix(pair, 16).map_err(|_| Error::InvalidSyntax)
+        })
+        .collect()
+}
+
+fn write_hex(f: &mut fmt::Formatter, data: &[u8]) -> fmt::Result {
+    data.iter().try_for_each(|b| write!(f, "{:02X}", b))
+}
+
+fn is_digits(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Reads entries of a candump log, blank lines are skipped.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use busmust::log::candump::Reader;
+///
+/// let log = "(1436509052.249713) can0 123#DEADBEEF\n\n(1436509052.250122) can0 18DAF110#R\ninvalid\n";
+///
+/// let mut reader = Reader::new(Cursor::new(log));
+/// let mut frames = 0;
+/// while let Some(entry) = reader.next() {
+///     match entry {
+///         Ok(entry) => {
+///             println!("{:?} {:?}", entry.timestamp, entry.frame);
+///             frames += 1;
+///         }
+///         Err(e) => println!("line {}: {}", reader.line(), e)
+///     }
+/// }
+/// assert_eq!(frames, 2);
+/// ```
+pub struct Reader<R> {
+    inner: R,
+    line: usize,
+    buffer: String
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(inner: R) -> Reader<R> {
+        Reader { inner, line: 0, buffer: String::new() }
+    }
+
+    /// Get the number of the line read last, starting at 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Result<LogEntry>> {
+        loop {
+            self.buffer.clear();
+            match self.inner.read_line(&mut self.buffer) {
+                Ok(0) => return None,
+                Ok(_) => self.line += 1,
+                Err(e) => return Some(Err(Error::Io(e)))
+            }
+
+            if !self.buffer.trim().is_empty() {
+                return Some(self.buffer.parse());
+            }
+        }
+    }
+}
+
+/// Writes entries of a candump log for a single interface.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use busmust::frame::{CanFrame, StandardId};
+/// use busmust::log::candump::Writer;
+///
+/// let frame = CanFrame::new(StandardId::new(0x123).unwrap(), &[0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+///
+/// let mut writer = Writer::new(Vec::new(), "can0");
+/// writer.write_frame(Duration::new(1436509052, 249_713_000), frame.into()).unwrap();
+/// assert_eq!(writer.into_inner(), b"(1436509052.249713) can0 123#DEADBEEF\n");
+/// ```
+pub struct Writer<W> {
+    inner: W,
+    interface: String
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W, interface: &str) -> Writer<W> {
+        Writer { inner, interface: interface.to_string() }
+    }
+
+    /// Write a frame received at the given time.
+    pub fn write_frame(&mut self, timestamp: Duration, frame: Frame) -> Result<()> {
+        let entry = LogEntry { timestamp, interface: self.interface.clone(), frame };
+        self.write_entry(&entry)
+    }
+
+    /// Write a message received at the given time, i.e. estimated by [super::super::timestamp::ChannelClock].
+    pub fn write_message(&mut self, timestamp: Duration, message: &BMCanMessage) -> Result<()> {
+        self.write_frame(timestamp, Frame::try_from(*message)?)
+    }
+
+    /// Write an entry keeping its interface name.
+    pub fn write_entry(&mut self, entry: &LogEntry) -> Result<()> {
+        writeln!(self.inner, "{}", entry)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    fn entry(frame: Frame) -> LogEntry {
+        LogEntry { timestamp: Duration::new(1436509052, 249_713_000), interface: "can0".to_string(), frame }
+    }
+
+    fn standard(raw: u16) -> StandardId {
+        StandardId::new(raw).unwrap()
+    }
+
+    fn extended(raw: u32) -> ExtendedId {
+        ExtendedId::new(raw).unwrap()
+    }
+
+    /// Check that `line` parses into `frame` and is written back unchanged.
+    fn assert_round_trip(line: &str, frame: Frame) {
+        let entry = entry(frame);
+        assert_eq!(line.parse::<LogEntry>().unwrap(), entry, "{}", line);
+        assert_eq!(entry.to_string(), line);
+    }
+
+    #[test]
+    fn data_frames() {
+        let frame = |id: Id, data: &[u8]| Frame::Can(CanFrame::new(id, data).unwrap());
+
+        assert_round_trip("(1436509052.249713) can0 123#DEADBEEF",
+                          frame(standard(0x123).into(), &[0xDE, 0xAD, 0xBE, 0xEF]));
+        assert_round_trip("(1436509052.249713) can0 7FF#", frame(standard(0x7FF).into(), &[]));
+        assert_round_trip("(1436509052.249713) can0 000#0001020304050607",
+                          frame(standard(0).into(), &[0, 1, 2, 3, 4, 5, 6, 7]));
+        assert_round_trip("(1436509052.249713) can0 18DAF110#0211", frame(extended(0x18DAF110).into(), &[0x02, 0x11]));
+        assert_round_trip("(1436509052.249713) can0 00000123#", frame(extended(0x123).into(), &[]));
+        assert_round_trip("(1436509052.249713) can0 1FFFFFFF#FF", frame(extended(0x1FFFFFFF).into(), &[0xFF]));
+    }
+
+    #[test]
+    fn remote_frames() {
+        let remote = |id: Id, dlc| Frame::Can(CanFrame::new_remote(id, dlc).unwrap());
+
+        assert_round_trip("(1436509052.249713) can0 123#R", remote(standard(0x123).into(), 0));
+        assert_round_trip("(1436509052.249713) can0 123#R8", remote(standard(0x123).into(), 8));
+        assert_round_trip("(1436509052.249713) can0 18DAF110#R", remote(extended(0x18DAF110).into(), 0));
+        assert_round_trip("(1436509052.249713) can0 18DAF110#R3", remote(extended(0x18DAF110).into(), 3));
+
+        // An explicit zero DLC is accepted, but not written.
+        let entry: LogEntry = "(1436509052.249713) can0 123#R0".parse().unwrap();
+        assert_eq!(entry.frame, remote(standard(0x123).into(), 0));
+    }
+
+    #[test]
+    fn fd_frames() {
+        let fd = |id: Id, data: &[u8], brs, esi| {
+            Frame::Fd(CanFdFrame::new(id, data).unwrap().with_brs(brs).with_esi(esi))
+        };
+
+        assert_round_trip("(1436509052.249713) can0 123##0", fd(standard(0x123).into(), &[], false, false));
+        assert_round_trip("(1436509052.249713) can0 123##111", fd(standard(0x123).into(), &[0x11], true, false));
+        assert_round_trip("(1436509052.249713) can0 123##211", fd(standard(0x123).into(), &[0x11], false, true));
+        assert_round_trip("(1436509052.249713) can0 123##311", fd(standard(0x123).into(), &[0x11], true, true));
+
+        let data: Vec<u8> = (0..64).collect();
+        let hex: String = data.iter().map(|b| format!("{:02X}", b)).collect();
+        let line = format!("(1436509052.249713) can0 18DAF110##1{}", hex);
+        assert_round_trip(&line, fd(extended(0x18DAF110).into(), &data, true, false));
+
+        // Payload bytes may be separated by dots.
+        let entry: LogEntry = "(1436509052.249713) can0 123##1.11.22".parse().unwrap();
+        assert_eq!(entry.frame, fd(standard(0x123).into(), &[0x11, 0x22], true, false));
+    }
+
+    #[test]
+    fn timestamps() {
+        let entry: LogEntry = "(0000000001.5) can0 123#".parse().unwrap();
+        assert_eq!(entry.timestamp, Duration::from_millis(1500));
+        assert_eq!(entry.to_string(), "(0000000001.500000) can0 123#");
+
+        let entry: LogEntry = "(12.000000001) vcan0 123#".parse().unwrap();
+        assert_eq!(entry.timestamp, Duration::new(12, 1));
+        assert_eq!(entry.interface, "vcan0");
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let syntax = [
+            "",
+            "(1436509052.249713) can0",
+            "(1436509052.249713) can0 123#00 extra",
+            "1436509052.249713 can0 123#00",
+            "(1436509052) can0 123#00",
+            "(1436509052.) can0 123#00",
+            "(.249713) can0 123#00",
+            "(1436509052.2497130000) can0 123#00",
+            "(1436509052.24971x) can0 123#00",
+            "(1436509052.249713) can0 12300",
+            "(1436509052.249713) can0 12#00",
+            "(1436509052.249713) can0 1234#00",
+            "(1436509052.249713) can0 12G#00",
+            "(1436509052.249713) can0 123#0",
+            "(1436509052.249713) can0 123#GG",
+            "(1436509052.249713) can0 123#R10",
+            "(1436509052.249713) can0 123#RX",
+            "(1436509052.249713) can0 123##",
+            "(1436509052.249713) can0 123##G00",
+            "(1436509052.249713) can0 123##1001"
+        ];
+        for line in syntax.iter() {
+            assert!(matches!(line.parse::<LogEntry>(), Err(Error::InvalidSyntax)), "{}", line);
+        }
+
+        let invalid = [
+            "(1436509052.249713) can0 800#00",
+            "(1436509052.249713) can0 20000000#00",
+            "(1436509052.249713) can0 123#001122334455667788",
+            "(1436509052.249713) can0 123#R9",
+            "(1436509052.249713) can0 123##1001122334455667788"
+        ];
+        for line in invalid.iter() {
+            assert!(matches!(line.parse::<LogEntry>(), Err(Error::InvalidFrame(_))), "{}", line);
+        }
+    }
+
+    #[test]
+    fn read_and_write() {
+        let log = "(1436509052.249713) can0 123#DEADBEEF\n\n  \n(1436509052.249713) can0 123#R\ninvalid\n";
+        let mut reader = Reader::new(Cursor::new(log));
+
+        let mut writer = Writer::new(Vec::new(), "can0");
+        writer.write_entry(&reader.next().unwrap().unwrap()).unwrap();
+        assert_eq!(reader.line(), 1);
+        writer.write_entry(&reader.next().unwrap().unwrap()).unwrap();
+        assert_eq!(reader.line(), 4);
+        assert!(matches!(reader.next(), Some(Err(Error::InvalidSyntax))));
+        assert_eq!(reader.line(), 5);
+        assert!(reader.next().is_none());
+
+        let frame = CanFrame::new(standard(0x123), &[0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+        let message = BMCanMessage::from(frame);
+        writer.write_message(Duration::new(1436509052, 249_713_000), &message).unwrap();
+
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(written, "(1436509052.249713) can0 123#DEADBEEF\n(1436509052.249713) can0 123#R\n\
+            (1436509052.249713) can0 123#DEADBEEF\n");
+    }
+}
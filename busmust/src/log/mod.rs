@@ -0,0 +1,3 @@
+//! Capture file formats, i.e. [candump] logs of can-utils.
+
+pub mod candump;
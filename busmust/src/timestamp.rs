@@ -0,0 +1,518 @@
+//! 64-bit device time and wall-clock labelling of device timestamps.
+//!
+//! Device timestamps (i.e. [BMData::timestamp], [OpenChannel::get_timestamp]) are 32-bit microsecond counters
+//! wrapping about every 71 minutes. A [TimestampTracker] unwraps them into monotonic 64-bit device time,
+//! a [ClockCorrelator] estimates the offset and drift of the device clock against the host clock.
+//! [ChannelClock] combines both for a single channel.
+//!
+//! Frames received on several channels are aligned by labelling each of them with the host time
+//! estimated by the [ChannelClock] of its own channel, since channels of different devices do not share a clock,
+//! see [merge].
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+use ffi::*;
+use dmgr::OpenChannel;
+use ::Result;
+
+/// Number of samples used by [ClockCorrelator] to estimate offset and drift.
+const MAX_SAMPLES: usize = 32;
+
+/// Unwraps 32-bit device timestamps of a channel into monotonic 64-bit device time in microseconds.
+///
+/// Timestamps must be passed roughly in order: each one is assumed to be less than half a wrap period
+/// (about 35 minutes) away from the previous one, earlier timestamps (i.e. of frames read late) are allowed.
+///
+/// # Examples
+///
+/// ```
+/// use busmust::timestamp::TimestampTracker;
+///
+/// let mut tracker = TimestampTracker::new();
+/// assert_eq!(tracker.extend(0xFFFF_FFF0), 0xFFFF_FFF0);
+/// assert_eq!(tracker.extend(0x10), 0x1_0000_0010);
+/// ```
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TimestampTracker {
+    last: Option<(u32, u64)>
+}
+
+impl TimestampTracker {
+    pub fn new() -> TimestampTracker {
+        TimestampTracker::default()
+    }
+
+    /// Get the 64-bit device time of the given timestamp, the first timestamp is taken as is.
+    pub fn extend(&mut self, timestamp: u32) -> u64 {
+        let time = match self.last {
+            None => timestamp as u64,
+            Some((last, time)) => {
+                let delta = timestamp.wrapping_sub(last) as i32 as i64;
+                (time as i64 + delta).max(0) as u64
+            }
+        };
+
+        // Keep the latest time as reference so late timestamps do not move it backwards.
+        match self.last {
+            Some((_, last_time)) if last_time > time => {}
+            _ => self.last = Some((timestamp, time))
+        }
+
+        time
+    }
+
+    /// Get the latest 64-bit device time.
+    pub fn last(&self) -> Option<u64> {
+        self.last.map(|(_, time)| time)
+    }
+
+    /// Forget all timestamps, i.e. after the device has been reset.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Estimates the offset and drift of a device clock against the host clock from pairs of simultaneous
+/// device and host times, using a linear fit of the latest samples.
+///
+/// The fit uses the monotonic [Instant] so steps of the wall clock (i.e. by NTP) do not bend it,
+/// estimates are mapped to [SystemTime] through a single anchor pair taken when the correlator is created.
+#[derive(Debug, Clone)]
+pub struct ClockCorrelator {
+    samples: VecDeque<(u64, Instant)>,
+    anchor: (Instant, SystemTime)
+}
+
+impl Default for ClockCorrelator {
+    fn default() -> ClockCorrelator {
+        ClockCorrelator::new()
+    }
+}
+
+impl ClockCorrelator {
+    pub fn new() -> ClockCorrelator {
+        ClockCorrelator::with_anchor(Instant::now(), SystemTime::now())
+    }
+
+    /// Create a correlator mapping `instant` to `system_time`.
+    pub fn with_anchor(instant: Instant, system_time: SystemTime) -> ClockCorrelator {
+        ClockCorrelator { samples: VecDeque::with_capacity(MAX_SAMPLES), anchor: (instant, system_time) }
+    }
+
+    /// Add a sample, the oldest one is dropped once enough samples are collected.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_time`: 64-bit device time in microseconds, see [TimestampTracker].
+    /// * `host_time`: Host time at the same instant.
+    pub fn add_sample(&mut self, device_time: u64, host_time: Instant) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((device_time, host_time));
+    }
+
+    /// Number of samples used by the estimate.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Forget all samples, i.e. after the device has been reset. The anchor is kept.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Get the drift of the device clock against the host clock in ppm, positive if the device clock is slow.
+    /// `None` until at least two samples with distinct device times are collected.
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.fit().and_then(|(_, _, rate)| rate).map(|rate| (rate - 1.0) * 1e6)
+    }
+
+    /// Estimate the host instant at the given 64-bit device time, `None` until a sample is collected.
+    pub fn to_instant(&self, device_time: u64) -> Option<Instant> {
+        let (device_origin, host_origin, rate) = self.fit()?;
+        let offset = (device_time as f64 - device_origin) * rate.unwrap_or(1.0) + host_origin;
+
+        let base = self.samples[0].1;
+        if offset >= 0.0 {
+            base.checked_add(Duration::from_secs_f64(offset / 1e6))
+        } else {
+            base.checked_sub(Duration::from_secs_f64(-offset / 1e6))
+        }
+    }
+
+    /// Estimate the host time at the given 64-bit device time, `None` until a sample is collected.
+    pub fn to_system_time(&self, device_time: u64) -> Option<SystemTime> {
+        self.to_instant(device_time).and_then(|instant| self.system_time(instant))
+    }
+
+    /// Map a host instant to the wall clock using the anchor.
+    fn system_time(&self, instant: Instant) -> Option<SystemTime> {
+        let (anchor_instant, anchor_time) = self.anchor;
+        if instant >= anchor_instant {
+            anchor_time.checked_add(instant - anchor_instant)
+        } else {
+            anchor_time.checked_sub(anchor_instant - instant)
+        }
+    }
+
+    /// Fit `host = (device - device_origin) * rate + host_origin`, in microseconds since the first sample.
+    ///
+    /// returns: Mean device and host time of the samples and the rate if it could be estimated.
+    fn fit(&self) -> Option<(f64, f64, Option<f64>)> {
+        let &(device_base, host_base) = self.samples.front()?;
+
+        // Fit relative to the first sample to keep the precision of large values.
+        let host_micros = |host: Instant| match host.checked_duration_since(host_base) {
+            Some(duration) => duration.as_secs_f64() * 1e6,
+            None => -host_base.duration_since(host).as_secs_f64() * 1e6
+        };
+        let points: Vec<(f64, f64)> = self.samples.iter()
+            .map(|&(device, host)| ((device as i64 - device_base as i64) as f64, host_micros(host)))
+            .collect();
+
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let sxx: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.0 - mean_x)).sum();
+        let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+
+        let rate = if sxx > 0.0 { Some(sxy / sxx) } else { None };
+        Some((device_base as f64 + mean_x, mean_y, rate))
+    }
+}
+
+/// Timestamp tracker and clock correlator of a single channel.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use busmust::dmgr::enum_devices;
+/// use busmust::timestamp::ChannelClock;
+///
+/// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+/// let mut clock = ChannelClock::new(Duration::from_secs(1));
+/// loop {
+///     clock.poll(&channel).unwrap();
+///     if let Ok(data) = channel.read() {
+///         println!("{:?} {:?}", clock.system_time(data.timestamp), data.decode());
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChannelClock {
+    tracker: TimestampTracker,
+    correlator: ClockCorrelator,
+    interval: Duration,
+    last_sample: Option<Instant>
+}
+
+impl ChannelClock {
+    /// Create a clock sampling the device time at most once per `interval`, see [ChannelClock::poll].
+    pub fn new(interval: Duration) -> ChannelClock {
+        ChannelClock {
+            tracker: TimestampTracker::new(),
+            correlator: ClockCorrelator::new(),
+            interval,
+            last_sample: None
+        }
+    }
+
+    /// Sample the device time of the channel against the host clock unless sampled within the interval.
+    /// Call it regularly, at least once in half a wrap period (about 35 minutes).
+    ///
+    /// returns: Whether a sample has been taken.
+    pub fn poll(&mut self, channel: &OpenChannel) -> Result<bool> {
+        match self.last_sample {
+            Some(last) if last.elapsed() < self.interval => Ok(false),
+            _ => self.sample(channel).map(|_| true)
+        }
+    }
+
+    /// Sample the device time of the channel against the host clock, taken halfway through [OpenChannel::get_timestamp].
+    pub fn sample(&mut self, channel: &OpenChannel) -> Result<()> {
+        let before = Instant::now();
+        let timestamp = channel.get_timestamp()?;
+        let after = Instant::now();
+
+        let host_time = before + (after - before) / 2;
+        let device_time = self.tracker.extend(timestamp);
+
+        self.correlator.add_sample(device_time, host_time);
+        self.last_sample = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Get the 64-bit device time of a timestamp of the channel.
+    pub fn device_time(&mut self, timestamp: u32) -> u64 {
+        self.tracker.extend(timestamp)
+    }
+
+    /// Estimate the host instant of a timestamp of the channel, `None` until the first sample.
+    pub fn instant(&mut self, timestamp: u32) -> Option<Instant> {
+        let device_time = self.tracker.extend(timestamp);
+        self.correlator.to_instant(device_time)
+    }
+
+    /// Estimate the host time of a timestamp of the channel, `None` until the first sample.
+    pub fn system_time(&mut self, timestamp: u32) -> Option<SystemTime> {
+        let device_time = self.tracker.extend(timestamp);
+        self.correlator.to_system_time(device_time)
+    }
+
+    /// Estimate the host time of a received message/event, see [ChannelClock::system_time].
+    pub fn label(&mut self, data: &BMData) -> Option<SystemTime> {
+        self.system_time(data.timestamp)
+    }
+
+    pub fn tracker(&self) -> &TimestampTracker {
+        &self.tracker
+    }
+
+    pub fn correlator(&self) -> &ClockCorrelator {
+        &self.correlator
+    }
+
+    /// Forget all timestamps and samples, i.e. after the device has been reset.
+    pub fn reset(&mut self) {
+        self.tracker.reset();
+        self.correlator.clear();
+        self.last_sample = None;
+    }
+}
+
+/// Merge frames received on several channels into a single sequence ordered by their estimated host time.
+///
+/// # Arguments
+///
+/// * `clocks`: Clock of each channel.
+/// * `frames`: Index of the channel in `clocks`, device timestamp and the frame itself, i.e. [BMData] read
+///   from the channel.
+///
+/// returns: Channel index, estimated host time and frame, in the order of the estimated host instants.
+/// Frames of channels without any clock sample are dropped.
+///
+/// # Panics
+///
+/// Panics if a channel index is out of range of `clocks`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use busmust::dmgr::enum_devices;
+/// use busmust::timestamp::{self, ChannelClock};
+///
+/// let channels: Vec<_> = enum_devices().unwrap().map(|device| device.open_ex().unwrap()).collect();
+/// let mut clocks = vec![ChannelClock::new(Duration::from_secs(1)); channels.len()];
+///
+/// let mut frames = Vec::new();
+/// for (index, (channel, clock)) in channels.iter().zip(clocks.iter_mut()).enumerate() {
+///     clock.sample(channel).unwrap();
+///     while let Ok(data) = channel.read() {
+///         frames.push((index, data.timestamp, data));
+///     }
+/// }
+///
+/// for (channel, time, data) in timestamp::merge(&mut clocks, frames) {
+///     println!("{} {:?} {:?}", channel, time, data.decode());
+/// }
+/// ```
+pub fn merge<T, I>(clocks: &mut [ChannelClock], frames: I) -> Vec<(usize, SystemTime, T)>
+    where I: IntoIterator<Item = (usize, u32, T)>
+{
+    let mut labelled: Vec<(Instant, usize, SystemTime, T)> = frames.into_iter()
+        .filter_map(|(channel, timestamp, frame)| {
+            let clock = &mut clocks[channel];
+            let instant = clock.instant(timestamp)?;
+            let system_time = clock.correlator.system_time(instant)?;
+            Some((instant, channel, system_time, frame))
+        })
+        .collect();
+
+    labelled.sort_by_key(|&(instant, channel, _, _)| (instant, channel));
+    labelled.into_iter().map(|(_, channel, system_time, frame)| (channel, system_time, frame)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+    use backend::VirtualBus;
+    use super::*;
+
+    /// Anchor of the synthetic clocks, the host instant is mapped to 1000 s after [UNIX_EPOCH].
+    fn anchor() -> (Instant, SystemTime) {
+        (Instant::now(), UNIX_EPOCH + Duration::from_secs(1000))
+    }
+
+    /// Add `count` samples 100 ms of device time apart, the first one taking `device_start` at `host_start`,
+    /// of a device clock drifting by `drift_ppm`.
+    fn add_samples(correlator: &mut ClockCorrelator, count: u64, device_start: u64, host_start: Instant, drift_ppm: f64) {
+        for i in 0..count {
+            let host = host_start + Duration::from_secs_f64(i as f64 * 0.1 * (1.0 + drift_ppm / 1e6));
+            correlator.add_sample(device_start + i * 100_000, host);
+        }
+    }
+
+    fn assert_close(actual: SystemTime, expected: SystemTime) {
+        let error = actual.duration_since(expected).unwrap_or_else(|e| e.duration());
+        assert!(error <= Duration::from_micros(1), "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn extend_across_wraps() {
+        let mut tracker = TimestampTracker::new();
+        assert_eq!(tracker.last(), None);
+
+        let mut timestamp = 0x1000u32;
+        for i in 0..12u64 {
+            assert_eq!(tracker.extend(timestamp), 0x1000 + i * 0x4000_0000);
+            timestamp = timestamp.wrapping_add(0x4000_0000);
+        }
+        assert_eq!(tracker.last(), Some(0x1000 + 11 * 0x4000_0000));
+    }
+
+    #[test]
+    fn extend_late_timestamps() {
+        let mut tracker = TimestampTracker::new();
+        tracker.extend(0xFFFF_FF00);
+        assert_eq!(tracker.extend(0x10), 0x1_0000_0010);
+
+        // Late timestamps from before the wrap neither wrap again nor move the reference back.
+        assert_eq!(tracker.extend(0xFFFF_FFF0), 0xFFFF_FFF0);
+        assert_eq!(tracker.last(), Some(0x1_0000_0010));
+        assert_eq!(tracker.extend(0x20), 0x1_0000_0020);
+        assert_eq!(tracker.extend(0x18), 0x1_0000_0018);
+        assert_eq!(tracker.last(), Some(0x1_0000_0020));
+
+        // Timestamps before the first one are clamped to zero.
+        let mut tracker = TimestampTracker::new();
+        tracker.extend(0x10);
+        assert_eq!(tracker.extend(0xFFFF_FFF0), 0);
+    }
+
+    #[test]
+    fn extend_after_reset() {
+        let mut tracker = TimestampTracker::new();
+        tracker.extend(0xFFFF_FF00);
+        tracker.extend(0x10);
+
+        tracker.reset();
+        assert_eq!(tracker.last(), None);
+        assert_eq!(tracker.extend(0x20), 0x20);
+        assert_eq!(tracker.extend(0x30), 0x30);
+    }
+
+    #[test]
+    fn correlator_needs_samples() {
+        let (instant, system_time) = anchor();
+        let mut correlator = ClockCorrelator::with_anchor(instant, system_time);
+        assert!(correlator.is_empty());
+        assert_eq!(correlator.to_system_time(0), None);
+
+        // A single sample gives the offset but no drift.
+        correlator.add_sample(5_000_000, instant);
+        assert_eq!(correlator.drift_ppm(), None);
+        assert_close(correlator.to_system_time(5_000_000).unwrap(), system_time);
+        assert_close(correlator.to_system_time(6_000_000).unwrap(), system_time + Duration::from_secs(1));
+        assert_close(correlator.to_system_time(4_000_000).unwrap(), system_time - Duration::from_secs(1));
+    }
+
+    #[test]
+    fn correlator_offset_and_drift() {
+        let (instant, system_time) = anchor();
+        let mut correlator = ClockCorrelator::with_anchor(instant, system_time);
+
+        // Device clock 50 ppm slow, its time 7 s was taken 2 s after the anchor.
+        let host_start = instant + Duration::from_secs(2);
+        add_samples(&mut correlator, 20, 7_000_000, host_start, 50.0);
+
+        assert!((correlator.drift_ppm().unwrap() - 50.0).abs() < 0.01);
+        assert_close(correlator.to_system_time(7_000_000).unwrap(), system_time + Duration::from_secs(2));
+
+        // Extrapolated 100 s past the first sample, the drift adds up to 5 ms.
+        let expected = system_time + Duration::from_secs(102) + Duration::from_millis(5);
+        assert_close(correlator.to_system_time(107_000_000).unwrap(), expected);
+    }
+
+    #[test]
+    fn correlator_window() {
+        let (instant, system_time) = anchor();
+        let mut correlator = ClockCorrelator::with_anchor(instant, system_time);
+
+        // An outlier one second off is evicted once the window is full of consistent samples.
+        correlator.add_sample(0, instant + Duration::from_secs(1));
+        add_samples(&mut correlator, MAX_SAMPLES as u64, 100_000, instant + Duration::from_millis(100), 0.0);
+        assert_eq!(correlator.len(), MAX_SAMPLES);
+        assert!(correlator.drift_ppm().unwrap().abs() < 0.01);
+        assert_close(correlator.to_system_time(0).unwrap(), system_time);
+
+        // Only the latest samples determine the drift.
+        let host_start = instant + Duration::from_secs(10);
+        add_samples(&mut correlator, MAX_SAMPLES as u64, 10_000_000, host_start, -20.0);
+        assert_eq!(correlator.len(), MAX_SAMPLES);
+        assert!((correlator.drift_ppm().unwrap() + 20.0).abs() < 0.01);
+
+        correlator.clear();
+        assert!(correlator.is_empty());
+        assert_eq!(correlator.to_system_time(0), None);
+    }
+
+    #[test]
+    fn merge_channels() {
+        let (instant, system_time) = anchor();
+
+        // The device time of channel 0 is 10 s ahead of channel 1, channel 1 is 100 ppm slow.
+        let mut clocks = vec![ChannelClock::new(Duration::from_secs(1)); 3];
+        clocks[0].correlator = ClockCorrelator::with_anchor(instant, system_time);
+        clocks[1].correlator = ClockCorrelator::with_anchor(instant, system_time);
+        add_samples(&mut clocks[0].correlator, 10, 10_000_000, instant, 0.0);
+        add_samples(&mut clocks[1].correlator, 10, 0, instant, 100.0);
+
+        // Frames received 1 ms apart, alternating between the channels.
+        let frames = vec![
+            (0, 10_000_000 + 1000, "a1"),
+            (1, 1000, "b1"),
+            (0, 10_000_000 + 3000, "a3"),
+            (1, 1_999_800, "b2000"),
+            (1, 2000, "b2"),
+            (2, 2500, "unsampled"),
+            (0, 12_000_000, "a2000")
+        ];
+        let merged = merge(&mut clocks, frames);
+
+        let order: Vec<&str> = merged.iter().map(|entry| entry.2).collect();
+        assert_eq!(order, vec!["a1", "b1", "b2", "a3", "b2000", "a2000"]);
+        assert_eq!(merged[1].0, 1);
+        assert_close(merged[0].1, system_time + Duration::from_millis(1));
+        assert_close(merged[1].1, system_time + Duration::from_micros(1000));
+        assert_close(merged[4].1, system_time + Duration::from_micros(1_999_800 + 200));
+    }
+
+    #[test]
+    fn channel_clock() {
+        let bus = VirtualBus::new(1);
+        let channel = bus.enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+
+        let mut clock = ChannelClock::new(Duration::from_secs(60));
+        assert_eq!(clock.system_time(0), None);
+        assert!(clock.poll(&channel).unwrap());
+        assert!(!clock.poll(&channel).unwrap());
+        assert_eq!(clock.correlator().len(), 1);
+
+        // The virtual bus shares the host clock.
+        let now = SystemTime::now();
+        let labelled = clock.system_time(channel.get_timestamp().unwrap()).unwrap();
+        let error = labelled.duration_since(now).unwrap_or_else(|e| e.duration());
+        assert!(error < Duration::from_millis(50));
+
+        clock.reset();
+        assert!(clock.correlator().is_empty());
+        assert_eq!(clock.tracker().last(), None);
+    }
+}
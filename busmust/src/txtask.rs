@@ -0,0 +1,507 @@
+//! Hardware triggered TX tasks, see [super::dmgr::OpenChannel::set_tx_tasks].
+//!
+//! A [TxTask] describes a message which is sent periodically by the device itself, optionally
+//! altering its ID or a part of its payload between transmissions according to a [TxTaskKind].
+
+use super::{Error, Result};
+use ffi::*;
+
+const MAX_STANDARD_ID: u32 = 0x7FF;
+const MAX_EXTENDED_ID: u32 = 0x1FFF_FFFF;
+
+/// Byte order of the volatile payload part of a data pattern.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Little endian
+    Intel,
+    /// Big endian
+    Motorola
+}
+
+impl ByteOrder {
+    fn to_raw(self) -> u8 {
+        match self {
+            ByteOrder::Intel => 0x80,
+            ByteOrder::Motorola => 0x00
+        }
+    }
+
+    fn from_raw(value: u8) -> Result<ByteOrder> {
+        match value {
+            0x80 => Ok(ByteOrder::Intel),
+            0x00 => Ok(ByteOrder::Motorola),
+            _ => Err(Error::new(BMStatus::InvalidParameterValue))
+        }
+    }
+}
+
+/// Kind of a TX task, including its pattern parameters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TxTaskKind {
+    /// Send fixed ID and fixed payload.
+    Fixed,
+    /// Increment a payload field by `step` from `min` to `max` on every message.
+    IncData {
+        /// Start bit of the field, must be 8-bit aligned
+        start_bit: u16,
+        /// Number of bits of the field, currently only 32 is accepted by the device
+        nbits: u8,
+        /// Byte order of the field
+        order: ByteOrder,
+        min: u32,
+        max: u32,
+        step: u32
+    },
+    /// Increment the message ID by `step` from `min` to `max` on every message.
+    IncId {
+        min: u32,
+        max: u32,
+        step: u32
+    },
+    /// Fill a payload field with random values in range `min..=max`.
+    RandomData {
+        /// Start bit of the field, must be 8-bit aligned
+        start_bit: u16,
+        /// Number of bits of the field, currently only 32 is accepted by the device
+        nbits: u8,
+        /// Byte order of the field
+        order: ByteOrder,
+        min: u32,
+        max: u32,
+        seed: u32
+    },
+    /// Use random message IDs in range `min..=max`.
+    RandomId {
+        min: u32,
+        max: u32,
+        seed: u32
+    }
+}
+
+/// Validated TX task, could be converted into a [BMTxTask] with [TxTask::to_raw].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxTask {
+    kind: TxTaskKind,
+    id: u32,
+    extended: bool,
+    fd: bool,
+    brs: bool,
+    cycle: u16,
+    n_rounds: u16,
+    n_messages: u16,
+    payload: Vec<u8>
+}
+
+impl TxTask {
+    pub fn builder() -> TxTaskBuilder {
+        TxTaskBuilder::default()
+    }
+
+    /// Get the kind of the task.
+    pub fn kind(&self) -> TxTaskKind {
+        self.kind
+    }
+
+    /// Get the message ID, either standard or extended depending on [TxTask::is_extended].
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.extended
+    }
+
+    pub fn is_fd(&self) -> bool {
+        self.fd
+    }
+
+    pub fn is_brs(&self) -> bool {
+        self.brs
+    }
+
+    /// Get the delay between rounds, in `ms`.
+    pub fn cycle(&self) -> u16 {
+        self.cycle
+    }
+
+    /// Get the number of rounds.
+    pub fn rounds(&self) -> u16 {
+        self.n_rounds
+    }
+
+    /// Get the number of messages sent per round.
+    pub fn messages(&self) -> u16 {
+        self.n_messages
+    }
+
+    /// Get the template payload.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..]
+    }
+
+    /// Convert the task into the in-memory layout expected by the device.
+    pub fn to_raw(&self) -> BMTxTask {
+        let mut flags = 0;
+        if self.extended {
+            flags |= BMMessageFlags::Extended as u8;
+        }
+        if self.fd {
+            flags |= BMMessageFlags::CanFD as u8;
+        }
+        if self.brs {
+            flags |= BMMessageFlags::BitRateSwitching as u8;
+        }
+
+        let (kind, pattern) = match self.kind {
+            TxTaskKind::Fixed => (BMTxTaskType::Fixed, BMTxTaskPattern { unused: [0; 32] }),
+            TxTaskKind::IncData { start_bit, nbits, order, min, max, step } => {
+                (BMTxTaskType::IncData, with_pattern(|p| p.inc_data = BMTxTaskIncDataPattern {
+                    start_bit, nbits, format: order.to_raw(), min, max, step
+                }))
+            }
+            TxTaskKind::IncId { min, max, step } => {
+                (BMTxTaskType::IncId, with_pattern(|p| p.inc_id = BMTxTaskIncIdPattern {
+                    min, max, step
+                }))
+            }
+            TxTaskKind::RandomData { start_bit, nbits, order, min, max, seed } => {
+                (BMTxTaskType::RandomData, with_pattern(|p| p.random_data = BMTxTaskRndDataPattern {
+                    start_bit, nbits, format: order.to_raw(), min, max, seed
+                }))
+            }
+            TxTaskKind::RandomId { min, max, seed } => {
+                (BMTxTaskType::RandomId, with_pattern(|p| p.random_id = BMTxTaskRndIdPattern {
+                    min, max, seed
+                }))
+            }
+        };
+
+        let mut payload = [0u8; 64];
+        payload[..self.payload.len()].copy_from_slice(&self.payload[..]);
+
+        BMTxTask {
+            kind: kind as u8,
+            unused: 0,
+            flags,
+            length: self.payload.len() as u8,
+            e2e: 0,
+            reserved: 0,
+            cycle: self.cycle,
+            n_rounds: self.n_rounds,
+            n_messages: self.n_messages,
+            id: if self.extended {
+                BMMessageId::from_extended(self.id).into()
+            } else {
+                BMMessageId::from_standard(self.id as u16).into()
+            },
+            pattern,
+            payload
+        }
+    }
+
+    /// Parse and validate a raw TX task, i.e. one previously produced by [TxTask::to_raw].
+    pub fn from_raw(raw: &BMTxTask) -> Result<TxTask> {
+        let kind = unsafe {
+            match raw.kind {
+                k if k == BMTxTaskType::Fixed as u8 => TxTaskKind::Fixed,
+                k if k == BMTxTaskType::IncData as u8 => {
+                    let p = raw.pattern.inc_data;
+                    TxTaskKind::IncData {
+                        start_bit: p.start_bit,
+                        nbits: p.nbits,
+                        order: ByteOrder::from_raw(p.format)?,
+                        min: p.min,
+                        max: p.max,
+                        step: p.step
+                    }
+                }
+                k if k == BMTxTaskType::IncId as u8 => {
+                    let p = raw.pattern.inc_id;
+                    TxTaskKind::IncId { min: p.min, max: p.max, step: p.step }
+                }
+                k if k == BMTxTaskType::RandomData as u8 => {
+                    let p = raw.pattern.random_data;
+                    TxTaskKind::RandomData {
+                        start_bit: p.start_bit,
+                        nbits: p.nbits,
+                        order: ByteOrder::from_raw(p.format)?,
+                        min: p.min,
+                        max: p.max,
+                        seed: p.seed
+                    }
+                }
+                k if k == BMTxTaskType::RandomId as u8 => {
+                    let p = raw.pattern.random_id;
+                    TxTaskKind::RandomId { min: p.min, max: p.max, seed: p.seed }
+                }
+                _ => return Err(Error::new(BMStatus::InvalidParameterValue))
+            }
+        };
+
+        let length = raw.length as usize;
+        if length > raw.payload.len() {
+            return Err(Error::new(BMStatus::InvalidParameterValue));
+        }
+
+        let extended = raw.flags & BMMessageFlags::Extended as u8 != 0;
+        let mid = BMMessageId::from(raw.id);
+
+        TxTaskBuilder {
+            kind,
+            id: if extended { mid.extended() } else { mid.sid() as u32 },
+            extended,
+            fd: raw.flags & BMMessageFlags::CanFD as u8 != 0,
+            brs: raw.flags & BMMessageFlags::BitRateSwitching as u8 != 0,
+            cycle: raw.cycle,
+            n_rounds: raw.n_rounds,
+            n_messages: raw.n_messages,
+            payload: raw.payload[..length].to_vec()
+        }.build()
+    }
+
+    /// Get the in-memory representation of the task, see [BMTxTask::to_bytes].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_raw().to_bytes()
+    }
+}
+
+fn with_pattern<F: FnOnce(&mut BMTxTaskPattern)>(f: F) -> BMTxTaskPattern {
+    let mut pattern = BMTxTaskPattern { unused: [0; 32] };
+    f(&mut pattern);
+    pattern
+}
+
+/// Builder for [TxTask].
+pub struct TxTaskBuilder {
+    kind: TxTaskKind,
+    id: u32,
+    extended: bool,
+    fd: bool,
+    brs: bool,
+    cycle: u16,
+    n_rounds: u16,
+    n_messages: u16,
+    payload: Vec<u8>
+}
+
+impl Default for TxTaskBuilder {
+    fn default() -> TxTaskBuilder {
+        TxTaskBuilder {
+            kind: TxTaskKind::Fixed,
+            id: 0,
+            extended: false,
+            fd: false,
+            brs: false,
+            cycle: 100,
+            n_rounds: 1,
+            n_messages: 1,
+            payload: Vec::new()
+        }
+    }
+}
+
+impl TxTaskBuilder {
+    pub fn new() -> TxTaskBuilder {
+        TxTaskBuilder::default()
+    }
+
+    pub fn kind(mut self, kind: TxTaskKind) -> TxTaskBuilder {
+        self.kind = kind;
+        self
+    }
+
+    /// Use an 11-bit standard message ID.
+    pub fn sid(mut self, id: u16) -> TxTaskBuilder {
+        self.id = id as u32;
+        self.extended = false;
+        self
+    }
+
+    /// Use a 29-bit extended message ID.
+    pub fn eid(mut self, id: u32) -> TxTaskBuilder {
+        self.id = id;
+        self.extended = true;
+        self
+    }
+
+    pub fn fdf(mut self, value: bool) -> TxTaskBuilder {
+        self.fd = value;
+        self
+    }
+
+    pub fn brs(mut self, value: bool) -> TxTaskBuilder {
+        self.brs = value;
+        self
+    }
+
+    /// Delay between rounds in `ms`, must not be zero.
+    pub fn cycle(mut self, value: u16) -> TxTaskBuilder {
+        self.cycle = value;
+        self
+    }
+
+    /// Number of rounds, must not be zero.
+    pub fn rounds(mut self, value: u16) -> TxTaskBuilder {
+        self.n_rounds = value;
+        self
+    }
+
+    /// Number of messages sent per round, must not be zero.
+    pub fn messages(mut self, value: u16) -> TxTaskBuilder {
+        self.n_messages = value;
+        self
+    }
+
+    /// Template payload, also the unchanged part of the payload for data patterns.
+    pub fn payload(mut self, value: Vec<u8>) -> TxTaskBuilder {
+        self.payload = value;
+        self
+    }
+
+    /// Validate the task parameters.
+    ///
+    /// returns: [`Result<TxTask>`], or [BMStatus::InvalidParameterValue] if any parameter is out of range.
+    pub fn build(self) -> Result<TxTask> {
+        let max_id = if self.extended { MAX_EXTENDED_ID } else { MAX_STANDARD_ID };
+        let length = self.payload.len();
+
+        let valid_length = if self.fd {
+            matches!(length, 0..=8 | 12 | 16 | 20 | 24 | 32 | 48 | 64)
+        } else {
+            length <= 8
+        };
+
+        let valid_pattern = match self.kind {
+            TxTaskKind::Fixed => true,
+            TxTaskKind::IncData { start_bit, nbits, min, max, step, .. } => {
+                valid_data_field(start_bit, nbits, length) && min <= max && step > 0
+            }
+            TxTaskKind::RandomData { start_bit, nbits, min, max, .. } => {
+                valid_data_field(start_bit, nbits, length) && min <= max
+            }
+            TxTaskKind::IncId { min, max, step } => min <= max && max <= max_id && step > 0,
+            TxTaskKind::RandomId { min, max, .. } => min <= max && max <= max_id
+        };
+
+        if self.id > max_id
+            || (self.brs && !self.fd)
+            || !valid_length
+            || !valid_pattern
+            || self.cycle == 0
+            || self.n_rounds == 0
+            || self.n_messages == 0 {
+            return Err(Error::new(BMStatus::InvalidParameterValue));
+        }
+
+        Ok(TxTask {
+            kind: self.kind,
+            id: self.id,
+            extended: self.extended,
+            fd: self.fd,
+            brs: self.brs,
+            cycle: self.cycle,
+            n_rounds: self.n_rounds,
+            n_messages: self.n_messages,
+            payload: self.payload
+        })
+    }
+}
+
+fn valid_data_field(start_bit: u16, nbits: u8, length: usize) -> bool {
+    start_bit & 7 == 0 && nbits == 32 && start_bit as usize + nbits as usize <= length * 8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::ptr;
+    use super::*;
+
+    /// Offset of [BMTxTask::pattern] within the in-memory layout.
+    const PATTERN_OFFSET: usize = 16;
+    /// Offset of [BMTxTask::payload] within the in-memory layout.
+    const PAYLOAD_OFFSET: usize = PATTERN_OFFSET + 32;
+
+    fn from_bytes(bytes: &[u8]) -> BMTxTask {
+        assert_eq!(bytes.len(), mem::size_of::<BMTxTask>());
+        unsafe { ptr::read_unaligned(bytes.as_ptr() as *const BMTxTask) }
+    }
+
+    fn tasks() -> Vec<TxTask> {
+        let payload: Vec<u8> = (0..8).collect();
+        let kinds = [
+            TxTaskKind::Fixed,
+            TxTaskKind::IncData { start_bit: 16, nbits: 32, order: ByteOrder::Intel, min: 1, max: 0x12345678, step: 3 },
+            TxTaskKind::IncId { min: 0x100, max: 0x1FF, step: 1 },
+            TxTaskKind::RandomData { start_bit: 0, nbits: 32, order: ByteOrder::Motorola, min: 0, max: 9, seed: 42 },
+            TxTaskKind::RandomId { min: 0x700, max: 0x7FF, seed: 7 }
+        ];
+
+        let mut tasks: Vec<TxTask> = kinds.iter()
+            .map(|&kind| TxTask::builder().kind(kind).sid(0x7FF).payload(payload.clone()).build().unwrap())
+            .collect();
+        tasks.push(TxTask::builder().eid(0x18DAF110).fdf(true).brs(true).cycle(10).rounds(5).messages(2)
+            .payload(vec![0xAA; 64]).build().unwrap());
+        tasks
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(mem::size_of::<BMTxTaskPattern>(), 32);
+        assert_eq!(mem::size_of::<BMTxTask>(), PAYLOAD_OFFSET + 64);
+
+        let kind = TxTaskKind::IncData { start_bit: 0x0108, nbits: 32, order: ByteOrder::Intel, min: 0x03040506,
+            max: 0x0708090A, step: 0x0B0C0D0E };
+        let task = TxTask::builder().kind(kind).eid(0x18DAF110).fdf(true).cycle(0x1234).rounds(0x5678)
+            .messages(0x9ABC).payload(vec![0xFF; 0x30]).build().unwrap();
+
+        let bytes = task.to_bytes();
+        assert_eq!(bytes[0], BMTxTaskType::IncData as u8);
+        assert_eq!(bytes[2], BMMessageFlags::Extended as u8 | BMMessageFlags::CanFD as u8);
+        assert_eq!(bytes[3], 0x30);
+        assert_eq!(&bytes[6..12], &[0x34, 0x12, 0x78, 0x56, 0xBC, 0x9A]);
+        assert_eq!(u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+                   u32::from(BMMessageId::from_extended(0x18DAF110)));
+
+        let pattern = &bytes[PATTERN_OFFSET..PAYLOAD_OFFSET];
+        assert_eq!(&pattern[..4], &[0x08, 0x01, 32, 0x80]);
+        assert_eq!(&pattern[4..16], &[0x06, 0x05, 0x04, 0x03, 0x0A, 0x09, 0x08, 0x07, 0x0E, 0x0D, 0x0C, 0x0B]);
+        assert!(pattern[16..].iter().all(|&b| b == 0));
+
+        assert_eq!(&bytes[PAYLOAD_OFFSET..PAYLOAD_OFFSET + 0x30], &[0xFF; 0x30][..]);
+        assert!(bytes[PAYLOAD_OFFSET + 0x30..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn round_trip() {
+        for task in tasks() {
+            let bytes = task.to_bytes();
+            let parsed = TxTask::from_raw(&from_bytes(&bytes)).unwrap();
+            assert_eq!(parsed, task);
+            assert_eq!(parsed.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn invalid_raw_tasks() {
+        let task = TxTask::builder().kind(TxTaskKind::IncData {
+            start_bit: 0, nbits: 32, order: ByteOrder::Intel, min: 0, max: 1, step: 1
+        }).payload(vec![0; 8]).build().unwrap();
+
+        let mut raw = task.to_raw();
+        raw.kind = 0xFF;
+        assert!(TxTask::from_raw(&raw).unwrap_err().is(BMStatus::InvalidParameterValue));
+
+        let mut raw = task.to_raw();
+        raw.length = 65;
+        assert!(TxTask::from_raw(&raw).unwrap_err().is(BMStatus::InvalidParameterValue));
+
+        let mut raw = task.to_raw();
+        raw.pattern.inc_data.format = 0x01;
+        assert!(TxTask::from_raw(&raw).unwrap_err().is(BMStatus::InvalidParameterValue));
+
+        let mut raw = task.to_raw();
+        raw.cycle = 0;
+        assert!(TxTask::from_raw(&raw).unwrap_err().is(BMStatus::InvalidParameterValue));
+    }
+}
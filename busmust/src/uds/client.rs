@@ -0,0 +1,495 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use isotp;
+use super::{sid, Error, NegativeResponseCode, Result, Transport};
+use super::{POSITIVE_RESPONSE, SUPPRESS_POSITIVE_RESPONSE};
+
+/// Server timing parameters reported in a DiagnosticSessionControl response.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SessionTiming {
+    /// Maximum time until the server starts its response
+    pub p2: Duration,
+    /// Maximum time until the server starts its response after a response pending notification
+    pub p2_star: Duration
+}
+
+/// RoutineControl sub-functions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RoutineControlType {
+    Start = 0x01,
+    Stop = 0x02,
+    RequestResults = 0x03
+}
+
+/// UDS client (tester), sending service requests over an ISO-TP [Transport].
+///
+/// # Examples
+///
+/// ```no_run
+/// use busmust::dmgr::enum_devices;
+/// use busmust::isotp::IsoTpSocket;
+/// use busmust::uds::Client;
+///
+/// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+/// let socket = IsoTpSocket::builder().tx_sid(0x7E0).rx_sid(0x7E8).build(&channel);
+/// let mut client = Client::new(socket);
+///
+/// client.diagnostic_session_control(0x03).unwrap();
+/// client.security_access(0x01, |seed| seed.iter().map(|b| b ^ 0xFF).collect()).unwrap();
+/// let vin = client.read_data_by_identifier(0xF190).unwrap();
+/// ```
+pub struct Client<T> {
+    transport: T,
+    p2: Duration,
+    p2_star: Duration
+}
+
+impl<T: Transport> Client<T> {
+    /// Create a client using P2 = 1 s and P2* = 5 s.
+    pub fn new(transport: T) -> Client<T> {
+        Client {
+            transport,
+            p2: Duration::from_millis(1000),
+            p2_star: Duration::from_millis(5000)
+        }
+    }
+
+    /// Set the time to wait for a response, and for the final response after a response pending notification.
+    pub fn set_timing(&mut self, timing: SessionTiming) {
+        self.p2 = timing.p2;
+        self.p2_star = timing.p2_star;
+    }
+
+    /// Get the current response timing.
+    pub fn timing(&self) -> SessionTiming {
+        SessionTiming { p2: self.p2, p2_star: self.p2_star }
+    }
+
+    /// Get a reference to the underlying transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Consume the client, returning the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Send a raw request and wait for its positive response.
+    ///
+    /// Negative responses are returned as [Error::NegativeResponse], except for
+    /// [NegativeResponseCode::ResponsePending] which extends the wait by P2*.
+    /// Unrelated messages received in the meantime are discarded.
+    ///
+    /// returns: The complete positive response, including its service identifier,
+    /// or [Error::InvalidRequest] if the request is empty.
+    pub fn request(&mut self, request: &[u8]) -> Result<Vec<u8>> {
+        let service = *request.first().ok_or(Error::InvalidRequest)?;
+
+        self.transport.send(request)?;
+
+        let mut deadline = Instant::now() + self.p2;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Timeout);
+            }
+
+            let response = match self.transport.recv(deadline - now) {
+                Ok(response) => response,
+                Err(isotp::Error::Timeout) => return Err(Error::Timeout),
+                Err(e) => return Err(e.into())
+            };
+
+            match response.first() {
+                Some(&s) if s == service | POSITIVE_RESPONSE => return Ok(response),
+                Some(&sid::NEGATIVE_RESPONSE) if response.len() >= 3 && response[1] == service => {
+                    match NegativeResponseCode::from(response[2]) {
+                        NegativeResponseCode::ResponsePending => deadline = Instant::now() + self.p2_star,
+                        code => return Err(Error::NegativeResponse { service, code })
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Switch to the given diagnostic session (i.e. `0x03` for extended session).
+    ///
+    /// returns: Server timing reported in the response, the client timing is not changed, see [Client::set_timing].
+    pub fn diagnostic_session_control(&mut self, session: u8) -> Result<SessionTiming> {
+        let response = self.request(&[sid::DIAGNOSTIC_SESSION_CONTROL, session])?;
+        expect_echo(&response, &[session])?;
+
+        if response.len() < 6 {
+            return Ok(self.timing());
+        }
+
+        Ok(SessionTiming {
+            p2: Duration::from_millis(u16::from_be_bytes([response[2], response[3]]) as u64),
+            p2_star: Duration::from_millis(u16::from_be_bytes([response[4], response[5]]) as u64 * 10)
+        })
+    }
+
+    /// Reset the server (i.e. `0x01` for hard reset).
+    pub fn ecu_reset(&mut self, reset_type: u8) -> Result<()> {
+        let response = self.request(&[sid::ECU_RESET, reset_type])?;
+        expect_echo(&response, &[reset_type])
+    }
+
+    /// Unlock the given security level.
+    ///
+    /// # Arguments
+    ///
+    /// * `level`: Odd `requestSeed` sub-function below `0x7F`, the key is sent using `level + 1`.
+    /// * `key`: Calculates the key from the seed sent by the server, not called if the level is already unlocked.
+    ///
+    /// returns: [Result<()>], [Error::InvalidRequest] if `level` is not a valid `requestSeed` sub-function.
+    pub fn security_access<F>(&mut self, level: u8, key: F) -> Result<()>
+        where F: FnOnce(&[u8]) -> Vec<u8>
+    {
+        if level & 1 == 0 || level >= 0x7F {
+            return Err(Error::InvalidRequest);
+        }
+
+        let response = self.request(&[sid::SECURITY_ACCESS, level])?;
+        expect_echo(&response, &[level])?;
+
+        let seed = &response[2..];
+        if seed.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+
+        let mut request = vec![sid::SECURITY_ACCESS, level + 1];
+        request.extend(key(seed));
+
+        let response = self.request(&request)?;
+        expect_echo(&response, &[level + 1])
+    }
+
+    /// Keep the current session active, optionally asking the server to not respond.
+    pub fn tester_present(&mut self, suppress_response: bool) -> Result<()> {
+        if suppress_response {
+            self.transport.send(&[sid::TESTER_PRESENT, SUPPRESS_POSITIVE_RESPONSE])?;
+            return Ok(());
+        }
+
+        let response = self.request(&[sid::TESTER_PRESENT, 0x00])?;
+        expect_echo(&response, &[0x00])
+    }
+
+    /// Read the data record of the given data identifier.
+    pub fn read_data_by_identifier(&mut self, did: u16) -> Result<Vec<u8>> {
+        let did = did.to_be_bytes();
+        let response = self.request(&[sid::READ_DATA_BY_IDENTIFIER, did[0], did[1]])?;
+        expect_echo(&response, &did)?;
+
+        Ok(response[3..].to_vec())
+    }
+
+    /// Write the data record of the given data identifier.
+    pub fn write_data_by_identifier(&mut self, did: u16, data: &[u8]) -> Result<()> {
+        let did = did.to_be_bytes();
+        let mut request = vec![sid::WRITE_DATA_BY_IDENTIFIER, did[0], did[1]];
+        request.extend_from_slice(data);
+
+        let response = self.request(&request)?;
+        expect_echo(&response, &did)
+    }
+
+    /// Start, stop or request results of the given routine.
+    ///
+    /// returns: The routine status record sent by the server.
+    pub fn routine_control(&mut self, control: RoutineControlType, routine: u16, options: &[u8]) -> Result<Vec<u8>> {
+        let routine = routine.to_be_bytes();
+        let mut request = vec![sid::ROUTINE_CONTROL, control as u8, routine[0], routine[1]];
+        request.extend_from_slice(options);
+
+        let response = self.request(&request)?;
+        expect_echo(&response, &[control as u8, routine[0], routine[1]])?;
+
+        Ok(response[4..].to_vec())
+    }
+
+    /// Request a download of `size` bytes to the given memory address, using 4-byte address and size fields.
+    ///
+    /// # Arguments
+    ///
+    /// * `data_format`: Compression (high nibble) and encryption (low nibble) method, `0x00` for none.
+    /// * `address`: Memory address to download to.
+    /// * `size`: Number of bytes to download.
+    ///
+    /// returns: Maximum length of TransferData requests accepted by the server, including the service identifier.
+    pub fn request_download(&mut self, data_format: u8, address: u32, size: u32) -> Result<usize> {
+        let mut request = vec![sid::REQUEST_DOWNLOAD, data_format, 0x44];
+        request.extend_from_slice(&address.to_be_bytes());
+        request.extend_from_slice(&size.to_be_bytes());
+
+        let response = self.request(&request)?;
+        let n = response.get(1).map_or(0, |lfi| (lfi >> 4) as usize);
+        if n == 0 || n > 8 || response.len() < 2 + n {
+            return Err(Error::UnexpectedResponse(response));
+        }
+
+        Ok(response[2..2 + n].iter().fold(0, |len, &b| (len << 8) | b as usize))
+    }
+
+    /// Transfer a block of data after [Client::request_download].
+    ///
+    /// returns: The transfer response parameters sent by the server.
+    pub fn transfer_data(&mut self, sequence: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let mut request = vec![sid::TRANSFER_DATA, sequence];
+        request.extend_from_slice(data);
+
+        let response = self.request(&request)?;
+        expect_echo(&response, &[sequence])?;
+
+        Ok(response[2..].to_vec())
+    }
+
+    /// Finish a data transfer.
+    ///
+    /// returns: The transfer response parameters sent by the server.
+    pub fn request_transfer_exit(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut request = vec![sid::REQUEST_TRANSFER_EXIT];
+        request.extend_from_slice(data);
+
+        let response = self.request(&request)?;
+        Ok(response[1..].to_vec())
+    }
+
+    /// Download `data` to the given memory address using RequestDownload, TransferData and RequestTransferExit.
+    pub fn download(&mut self, data_format: u8, address: u32, data: &[u8]) -> Result<()> {
+        let max_length = self.request_download(data_format, address, data.len() as u32)?;
+        if max_length <= 2 {
+            return Err(Error::UnexpectedResponse(Vec::new()));
+        }
+
+        let mut sequence = 1u8;
+        for block in data.chunks(max_length - 2) {
+            self.transfer_data(sequence, block)?;
+            sequence = sequence.wrapping_add(1);
+        }
+
+        self.request_transfer_exit(&[])?;
+        Ok(())
+    }
+}
+
+/// Check that the response repeats the given request parameters after its service identifier.
+fn expect_echo(response: &[u8], echo: &[u8]) -> Result<()> {
+    if response.len() > echo.len() && &response[1..=echo.len()] == echo {
+        Ok(())
+    } else {
+        Err(Error::UnexpectedResponse(response.to_vec()))
+    }
+}
+
+/// Background thread periodically sending TesterPresent to keep a non-default session active.
+/// The thread is stopped when the value is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+/// use busmust::uds::{Client, KeepAlive, ScriptedEcu};
+///
+/// let ecu = ScriptedEcu::new().expect(&[0x22, 0xF1, 0x90], &[&[0x62, 0xF1, 0x90, 0x42]]);
+/// let client = Arc::new(Mutex::new(Client::new(ecu)));
+/// let _keep_alive = KeepAlive::start(client.clone(), Duration::from_secs(2));
+///
+/// client.lock().unwrap().read_data_by_identifier(0xF190).unwrap();
+/// ```
+pub struct KeepAlive {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl KeepAlive {
+    /// Send TesterPresent with suppressed response every `interval` using the shared client.
+    pub fn start<T>(client: Arc<Mutex<Client<T>>>, interval: Duration) -> KeepAlive
+        where T: Transport + Send + 'static
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let thread = thread::spawn(move || {
+            let mut next = Instant::now() + interval;
+
+            while !stopped.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                if now < next {
+                    thread::park_timeout(next - now);
+                    continue;
+                }
+
+                if let Ok(mut client) = client.lock() {
+                    let _ = client.tester_present(true);
+                }
+                next += interval;
+            }
+        });
+
+        KeepAlive { stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use uds::ScriptedEcu;
+    use super::*;
+
+    const IMMEDIATELY: Duration = Duration::from_millis(0);
+
+    fn client(ecu: ScriptedEcu) -> Client<ScriptedEcu> {
+        let mut client = Client::new(ecu);
+        client.set_timing(SessionTiming { p2: Duration::from_millis(50), p2_star: Duration::from_millis(300) });
+        client
+    }
+
+    #[test]
+    fn positive_response() {
+        let ecu = ScriptedEcu::new().expect(&[0x22, 0xF1, 0x90], &[&[0x62, 0xF1, 0x90, 0x01, 0x02]]);
+        let mut client = client(ecu);
+
+        assert_eq!(client.read_data_by_identifier(0xF190).unwrap(), vec![0x01, 0x02]);
+        assert!(client.get_ref().is_done());
+    }
+
+    #[test]
+    fn negative_response() {
+        let ecu = ScriptedEcu::new().expect(&[0x2E, 0xF1, 0x99, 0x00], &[&[0x7F, 0x2E, 0x31]]);
+
+        match client(ecu).write_data_by_identifier(0xF199, &[0x00]) {
+            Err(Error::NegativeResponse { service: 0x2E, code: NegativeResponseCode::RequestOutOfRange }) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn unrelated_responses_ignored() {
+        let ecu = ScriptedEcu::new().expect(&[0x11, 0x01], &[
+            &[0x7F, 0x22, 0x31],
+            &[0x62, 0xF1, 0x90],
+            &[0x51, 0x01]
+        ]);
+
+        client(ecu).ecu_reset(0x01).unwrap();
+    }
+
+    #[test]
+    fn response_pending_extends_wait() {
+        let ecu = ScriptedEcu::new().expect_delayed(&[0x31, 0x01, 0xFF, 0x00], &[
+            (IMMEDIATELY, &[0x7F, 0x31, 0x78]),
+            (Duration::from_millis(150), &[0x7F, 0x31, 0x78]),
+            (Duration::from_millis(150), &[0x71, 0x01, 0xFF, 0x00, 0x00])
+        ]);
+        let mut client = client(ecu);
+
+        let started = Instant::now();
+        let status = client.routine_control(RoutineControlType::Start, 0xFF00, &[]).unwrap();
+
+        assert_eq!(status, vec![0x00]);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn response_pending_limited_by_p2_star() {
+        let ecu = ScriptedEcu::new().expect_delayed(&[0x31, 0x01, 0xFF, 0x00], &[
+            (IMMEDIATELY, &[0x7F, 0x31, 0x78]),
+            (Duration::from_millis(500), &[0x71, 0x01, 0xFF, 0x00])
+        ]);
+
+        let result = client(ecu).routine_control(RoutineControlType::Start, 0xFF00, &[]);
+        assert!(matches!(result, Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn timeout_after_p2() {
+        let ecu = ScriptedEcu::new().expect_delayed(&[0x11, 0x01], &[(Duration::from_millis(100), &[0x51, 0x01])]);
+        assert!(matches!(client(ecu).ecu_reset(0x01), Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn tester_present() {
+        let ecu = ScriptedEcu::new()
+            .expect(&[0x3E, 0x00], &[&[0x7E, 0x00]])
+            .expect(&[0x3E, 0x80], &[]);
+        let mut client = client(ecu);
+
+        client.tester_present(false).unwrap();
+        client.tester_present(true).unwrap();
+        assert!(client.get_ref().is_done());
+    }
+
+    #[test]
+    fn tester_present_rejected() {
+        let ecu = ScriptedEcu::new().expect(&[0x3E, 0x00], &[&[0x7F, 0x3E, 0x7F]]);
+
+        match client(ecu).tester_present(false) {
+            Err(Error::NegativeResponse { code: NegativeResponseCode::ServiceNotSupportedInActiveSession, .. }) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn session_timing() {
+        let ecu = ScriptedEcu::new().expect(&[0x10, 0x03], &[&[0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]]);
+
+        let timing = client(ecu).diagnostic_session_control(0x03).unwrap();
+        assert_eq!(timing, SessionTiming { p2: Duration::from_millis(50), p2_star: Duration::from_millis(5000) });
+    }
+
+    #[test]
+    fn security_access() {
+        let ecu = ScriptedEcu::new()
+            .expect(&[0x27, 0x01], &[&[0x67, 0x01, 0x12, 0x34]])
+            .expect(&[0x27, 0x02, 0xED, 0xCB], &[&[0x67, 0x02]])
+            .expect(&[0x27, 0x01], &[&[0x67, 0x01, 0x00, 0x00]]);
+        let mut client = client(ecu);
+
+        client.security_access(0x01, |seed| seed.iter().map(|b| b ^ 0xFF).collect()).unwrap();
+        client.security_access(0x01, |_| panic!("already unlocked")).unwrap();
+        assert!(client.get_ref().is_done());
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let mut client = client(ScriptedEcu::new());
+
+        assert!(matches!(client.request(&[]), Err(Error::InvalidRequest)));
+        for level in [0x00, 0x02, 0x7F, 0x81, 0xFF] {
+            assert!(matches!(client.security_access(level, |seed| seed.to_vec()), Err(Error::InvalidRequest)));
+        }
+        assert!(client.get_ref().requests().is_empty());
+    }
+
+    #[test]
+    fn download() {
+        let ecu = ScriptedEcu::new()
+            .expect(&[0x34, 0x00, 0x44, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x0A], &[&[0x74, 0x20, 0x00, 0x06]])
+            .expect(&[0x36, 0x01, 0, 1, 2, 3], &[&[0x76, 0x01]])
+            .expect(&[0x36, 0x02, 4, 5, 6, 7], &[&[0x76, 0x02]])
+            .expect(&[0x36, 0x03, 8, 9], &[&[0x76, 0x03]])
+            .expect(&[0x37], &[&[0x77]]);
+        let mut client = client(ecu);
+
+        client.download(0x00, 0x1000, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+        assert!(client.get_ref().is_done());
+    }
+}
@@ -0,0 +1,174 @@
+//! UDS (ISO 14229) diagnostic services on top of an ISO-TP [Transport].
+//!
+//! [Client] implements the tester side, sending service requests and handling negative responses.
+//! [Server] implements the ECU side, i.e. for simulating ECUs without hardware, answering requests using
+//! registered service, data identifier and routine handlers while tracking session and security state.
+//! [ScriptedEcu] stands in for an ECU answering with scripted responses, i.e. for testing [Client] code.
+
+use std::fmt;
+use isotp;
+
+mod client;
+mod scripted;
+mod server;
+
+pub use self::client::{Client, KeepAlive, RoutineControlType, SessionTiming};
+pub use self::scripted::ScriptedEcu;
+pub use self::server::{Response, Server, State, DEFAULT_SESSION};
+pub use isotp::Transport;
+
+/// Service identifiers of the services supported by [Client] and [Server].
+pub mod sid {
+    pub const DIAGNOSTIC_SESSION_CONTROL: u8 = 0x10;
+    pub const ECU_RESET: u8 = 0x11;
+    pub const SECURITY_ACCESS: u8 = 0x27;
+    pub const TESTER_PRESENT: u8 = 0x3E;
+    pub const READ_DATA_BY_IDENTIFIER: u8 = 0x22;
+    pub const WRITE_DATA_BY_IDENTIFIER: u8 = 0x2E;
+    pub const ROUTINE_CONTROL: u8 = 0x31;
+    pub const REQUEST_DOWNLOAD: u8 = 0x34;
+    pub const TRANSFER_DATA: u8 = 0x36;
+    pub const REQUEST_TRANSFER_EXIT: u8 = 0x37;
+    /// Service identifier of negative responses
+    pub const NEGATIVE_RESPONSE: u8 = 0x7F;
+}
+
+/// Positive responses use the request service identifier with this bit set.
+pub const POSITIVE_RESPONSE: u8 = 0x40;
+
+/// Sub-function bit requesting the server to not send a positive response.
+pub const SUPPRESS_POSITIVE_RESPONSE: u8 = 0x80;
+
+/// Negative response codes, see ISO 14229-1 for details.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NegativeResponseCode {
+    GeneralReject,
+    ServiceNotSupported,
+    SubFunctionNotSupported,
+    IncorrectMessageLengthOrInvalidFormat,
+    ResponseTooLong,
+    BusyRepeatRequest,
+    ConditionsNotCorrect,
+    RequestSequenceError,
+    RequestOutOfRange,
+    SecurityAccessDenied,
+    InvalidKey,
+    ExceededNumberOfAttempts,
+    RequiredTimeDelayNotExpired,
+    UploadDownloadNotAccepted,
+    TransferDataSuspended,
+    GeneralProgrammingFailure,
+    WrongBlockSequenceCounter,
+    /// The server accepted the request but needs more time, the final response follows within P2*
+    ResponsePending,
+    SubFunctionNotSupportedInActiveSession,
+    ServiceNotSupportedInActiveSession,
+    /// Any other (i.e. vehicle manufacturer specific) code
+    Other(u8)
+}
+
+impl From<u8> for NegativeResponseCode {
+    fn from(value: u8) -> NegativeResponseCode {
+        use self::NegativeResponseCode::*;
+
+        match value {
+            0x10 => GeneralReject,
+            0x11 => ServiceNotSupported,
+            0x12 => SubFunctionNotSupported,
+            0x13 => IncorrectMessageLengthOrInvalidFormat,
+            0x14 => ResponseTooLong,
+            0x21 => BusyRepeatRequest,
+            0x22 => ConditionsNotCorrect,
+            0x24 => RequestSequenceError,
+            0x31 => RequestOutOfRange,
+            0x33 => SecurityAccessDenied,
+            0x35 => InvalidKey,
+            0x36 => ExceededNumberOfAttempts,
+            0x37 => RequiredTimeDelayNotExpired,
+            0x70 => UploadDownloadNotAccepted,
+            0x71 => TransferDataSuspended,
+            0x72 => GeneralProgrammingFailure,
+            0x73 => WrongBlockSequenceCounter,
+            0x78 => ResponsePending,
+            0x7E => SubFunctionNotSupportedInActiveSession,
+            0x7F => ServiceNotSupportedInActiveSession,
+            other => Other(other)
+        }
+    }
+}
+
+impl From<NegativeResponseCode> for u8 {
+    fn from(value: NegativeResponseCode) -> u8 {
+        use self::NegativeResponseCode::*;
+
+        match value {
+            GeneralReject => 0x10,
+            ServiceNotSupported => 0x11,
+            SubFunctionNotSupported => 0x12,
+            IncorrectMessageLengthOrInvalidFormat => 0x13,
+            ResponseTooLong => 0x14,
+            BusyRepeatRequest => 0x21,
+            ConditionsNotCorrect => 0x22,
+            RequestSequenceError => 0x24,
+            RequestOutOfRange => 0x31,
+            SecurityAccessDenied => 0x33,
+            InvalidKey => 0x35,
+            ExceededNumberOfAttempts => 0x36,
+            RequiredTimeDelayNotExpired => 0x37,
+            UploadDownloadNotAccepted => 0x70,
+            TransferDataSuspended => 0x71,
+            GeneralProgrammingFailure => 0x72,
+            WrongBlockSequenceCounter => 0x73,
+            ResponsePending => 0x78,
+            SubFunctionNotSupportedInActiveSession => 0x7E,
+            ServiceNotSupportedInActiveSession => 0x7F,
+            Other(other) => other
+        }
+    }
+}
+
+/// UDS errors.
+#[derive(Debug)]
+pub enum Error {
+    /// The ISO-TP transport returned an error
+    Transport(isotp::Error),
+    /// The server rejected the request of the given service
+    NegativeResponse { service: u8, code: NegativeResponseCode },
+    /// The server response could not be parsed or does not match the request
+    UnexpectedResponse(Vec<u8>),
+    /// The server did not respond within P2 (or P2* after a response pending notification)
+    Timeout,
+    /// The request is invalid and has not been sent, i.e. it is empty or uses an invalid sub-function
+    InvalidRequest
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl From<isotp::Error> for Error {
+    fn from(err: isotp::Error) -> Error {
+        Error::Transport(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            Error::NegativeResponse { service, code } => {
+                write!(f, "negative response to service {:#04x}: {:?}", service, code)
+            }
+            Error::UnexpectedResponse(data) => write!(f, "unexpected response: {:02x?}", data),
+            Error::Timeout => write!(f, "no response from server"),
+            Error::InvalidRequest => write!(f, "invalid request")
+        }
+    }
+}
+
+impl ::std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            _ => None
+        }
+    }
+}
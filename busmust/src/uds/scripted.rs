@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
+use isotp;
+use super::Transport;
+
+/// Request expected by a [ScriptedEcu] and the responses sent after it.
+struct Exchange {
+    request: Vec<u8>,
+    responses: Vec<(Duration, Vec<u8>)>
+}
+
+/// In-process [Transport] standing in for an ECU, answering the expected requests with scripted responses.
+///
+/// Requests must be sent in the scripted order, the responses of a request are received one after another,
+/// each after its delay. Receiving without any pending response times out immediately.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use busmust::uds::{Client, ScriptedEcu};
+///
+/// let ecu = ScriptedEcu::new()
+///     .expect(&[0x10, 0x03], &[&[0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]])
+///     .expect_delayed(&[0x22, 0xF1, 0x90], &[
+///         (Duration::from_millis(0), &[0x7F, 0x22, 0x78]),
+///         (Duration::from_millis(10), &[0x62, 0xF1, 0x90, 0x42])
+///     ]);
+///
+/// let mut client = Client::new(ecu);
+/// client.diagnostic_session_control(0x03).unwrap();
+/// assert_eq!(client.read_data_by_identifier(0xF190).unwrap(), vec![0x42]);
+/// assert!(client.get_ref().is_done());
+/// ```
+#[derive(Default)]
+pub struct ScriptedEcu {
+    script: VecDeque<Exchange>,
+    pending: VecDeque<(Duration, Vec<u8>)>,
+    requests: Vec<Vec<u8>>
+}
+
+impl ScriptedEcu {
+    pub fn new() -> ScriptedEcu {
+        ScriptedEcu::default()
+    }
+
+    /// Expect the given request next and answer it immediately with `responses`, use no response for
+    /// requests with suppressed positive response.
+    pub fn expect(self, request: &[u8], responses: &[&[u8]]) -> ScriptedEcu {
+        let responses: Vec<(Duration, &[u8])> = responses.iter().map(|r| (Duration::from_millis(0), *r)).collect();
+        self.expect_delayed(request, &responses)
+    }
+
+    /// Expect the given request next and answer it with `responses`, each sent the given time after the
+    /// previous one was received.
+    pub fn expect_delayed(mut self, request: &[u8], responses: &[(Duration, &[u8])]) -> ScriptedEcu {
+        self.script.push_back(Exchange {
+            request: request.to_vec(),
+            responses: responses.iter().map(|&(delay, response)| (delay, response.to_vec())).collect()
+        });
+        self
+    }
+
+    /// Get all requests received so far.
+    pub fn requests(&self) -> &[Vec<u8>] {
+        &self.requests
+    }
+
+    /// Check whether all expected requests have been received and all responses have been sent.
+    pub fn is_done(&self) -> bool {
+        self.script.is_empty() && self.pending.is_empty()
+    }
+}
+
+impl Transport for ScriptedEcu {
+    /// Receive a request, queueing its responses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the request is not the next expected one.
+    fn send(&mut self, data: &[u8]) -> isotp::Result<()> {
+        self.requests.push(data.to_vec());
+
+        let exchange = match self.script.pop_front() {
+            Some(exchange) => exchange,
+            None => panic!("unexpected request {:02X?}, no more requests expected", data)
+        };
+        if exchange.request != data {
+            panic!("unexpected request {:02X?}, expected {:02X?}", data, exchange.request);
+        }
+
+        self.pending.extend(exchange.responses);
+        Ok(())
+    }
+
+    fn recv(&mut self, timeout: Duration) -> isotp::Result<Vec<u8>> {
+        let (delay, response) = match self.pending.pop_front() {
+            Some(pending) => pending,
+            None => return Err(isotp::Error::Timeout)
+        };
+
+        // Not sent within the timeout, keep the rest of the delay for the next call.
+        if delay > timeout {
+            thread::sleep(timeout);
+            self.pending.push_front((delay - timeout, response));
+            return Err(isotp::Error::Timeout);
+        }
+
+        thread::sleep(delay);
+        Ok(response)
+    }
+}
@@ -0,0 +1,698 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use isotp;
+use super::{sid, Error, NegativeResponseCode, Result, SessionTiming, RoutineControlType, Transport};
+use super::{POSITIVE_RESPONSE, SUPPRESS_POSITIVE_RESPONSE};
+
+/// Default diagnostic session, entered on start, reset and S3 timeout.
+pub const DEFAULT_SESSION: u8 = 0x01;
+
+/// Positive response parameters (without the service identifier) or negative response code returned by handlers.
+pub type Response = ::std::result::Result<Vec<u8>, NegativeResponseCode>;
+
+type ServiceHandler = Box<dyn FnMut(&mut State, &[u8]) -> Response + Send>;
+type ReadHandler = Box<dyn FnMut(&State) -> Response + Send>;
+type WriteHandler = Box<dyn FnMut(&mut State, &[u8]) -> ::std::result::Result<(), NegativeResponseCode> + Send>;
+type RoutineHandler = Box<dyn FnMut(&mut State, RoutineControlType, &[u8]) -> Response + Send>;
+type ResetHandler = Box<dyn FnMut(u8) -> ::std::result::Result<(), NegativeResponseCode> + Send>;
+type SeedGenerator = Box<dyn FnMut() -> Vec<u8> + Send>;
+type KeyVerifier = Box<dyn FnMut(&[u8], &[u8]) -> bool + Send>;
+
+/// Session and security state of a [Server], passed to all handlers.
+#[derive(Debug, Clone)]
+pub struct State {
+    session: u8,
+    unlocked: Option<u8>,
+    seed: Option<(u8, Vec<u8>)>,
+    failed_attempts: u8
+}
+
+impl State {
+    fn new() -> State {
+        State { session: DEFAULT_SESSION, unlocked: None, seed: None, failed_attempts: 0 }
+    }
+
+    /// Get the active diagnostic session.
+    pub fn session(&self) -> u8 {
+        self.session
+    }
+
+    /// Get the unlocked security level (odd `requestSeed` sub-function), if any.
+    pub fn security_level(&self) -> Option<u8> {
+        self.unlocked
+    }
+
+    /// Lock security access and return to the default session.
+    pub fn reset(&mut self) {
+        *self = State::new();
+    }
+}
+
+struct SecurityLevel {
+    seed: SeedGenerator,
+    verify: KeyVerifier
+}
+
+struct Access {
+    sessions: Vec<u8>,
+    security_level: Option<u8>
+}
+
+/// UDS server (ECU simulator), answering requests received over an ISO-TP [Transport].
+///
+/// DiagnosticSessionControl, ECUReset, SecurityAccess, TesterPresent, ReadDataByIdentifier, WriteDataByIdentifier
+/// and RoutineControl are implemented by the server itself and dispatched to the registered data identifier,
+/// routine and security level handlers. Any other service can be implemented using [Server::service], which
+/// also overrides the built-in implementations.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::sync::atomic::AtomicBool;
+/// use busmust::dmgr::enum_devices;
+/// use busmust::isotp::IsoTpSocket;
+/// use busmust::uds::{sid, NegativeResponseCode, Server};
+///
+/// let channel = enum_devices().unwrap().next().unwrap().open_ex().unwrap();
+/// let stop = AtomicBool::new(false);
+///
+/// let socket = IsoTpSocket::builder().tx_sid(0x7E8).rx_sid(0x7E0).build(&channel);
+/// let mut server = Server::new(socket)
+///     .sessions(&[0x01, 0x03])
+///     .security_level(0x01, || vec![0x12, 0x34], |seed, key| key.iter().zip(seed).all(|(k, s)| k ^ s == 0xFF))
+///     .data_identifier(0xF190, b"WVWZZZ1JZXW000001".to_vec())
+///     .write_data(0xF199, |state, data| if data.len() == 4 { Ok(()) } else {
+///         Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat)
+///     })
+///     .require(sid::WRITE_DATA_BY_IDENTIFIER, &[0x03], Some(0x01));
+///
+/// server.run(&stop).unwrap();
+/// ```
+pub struct Server<T> {
+    transport: T,
+    state: State,
+    sessions: Vec<u8>,
+    timing: SessionTiming,
+    s3: Duration,
+    max_attempts: u8,
+    last_request: Instant,
+    access: HashMap<u8, Access>,
+    services: HashMap<u8, ServiceHandler>,
+    read_handlers: HashMap<u16, ReadHandler>,
+    write_handlers: HashMap<u16, WriteHandler>,
+    routines: HashMap<u16, RoutineHandler>,
+    security_levels: HashMap<u8, SecurityLevel>,
+    reset_handler: Option<ResetHandler>
+}
+
+impl<T: Transport> Server<T> {
+    /// Create a server supporting the default, programming and extended sessions, reporting P2 = 50 ms, P2* = 5 s,
+    /// with S3 = 5 s and locking security access after 3 invalid keys.
+    pub fn new(transport: T) -> Server<T> {
+        Server {
+            transport,
+            state: State::new(),
+            sessions: vec![0x01, 0x02, 0x03],
+            timing: SessionTiming { p2: Duration::from_millis(50), p2_star: Duration::from_millis(5000) },
+            s3: Duration::from_millis(5000),
+            max_attempts: 3,
+            last_request: Instant::now(),
+            access: HashMap::new(),
+            services: HashMap::new(),
+            read_handlers: HashMap::new(),
+            write_handlers: HashMap::new(),
+            routines: HashMap::new(),
+            security_levels: HashMap::new(),
+            reset_handler: None
+        }
+    }
+
+    /// Set the diagnostic sessions accepted by DiagnosticSessionControl.
+    pub fn sessions(mut self, sessions: &[u8]) -> Server<T> {
+        self.sessions = sessions.to_vec();
+        self
+    }
+
+    /// Set the timing reported in DiagnosticSessionControl responses.
+    pub fn timing(mut self, timing: SessionTiming) -> Server<T> {
+        self.timing = timing;
+        self
+    }
+
+    /// Set the time without requests after which the server returns to the default session.
+    pub fn s3(mut self, value: Duration) -> Server<T> {
+        self.s3 = value;
+        self
+    }
+
+    /// Set the number of invalid keys after which seed requests are rejected until the next session change.
+    pub fn max_attempts(mut self, value: u8) -> Server<T> {
+        self.max_attempts = value;
+        self
+    }
+
+    /// Only accept the given service in one of `sessions` and, if set, with `security_level` unlocked.
+    pub fn require(mut self, service: u8, sessions: &[u8], security_level: Option<u8>) -> Server<T> {
+        self.access.insert(service, Access { sessions: sessions.to_vec(), security_level });
+        self
+    }
+
+    /// Handle the given service, receiving the request parameters after the service identifier.
+    pub fn service<F>(mut self, service: u8, handler: F) -> Server<T>
+        where F: FnMut(&mut State, &[u8]) -> Response + Send + 'static
+    {
+        self.services.insert(service, Box::new(handler));
+        self
+    }
+
+    /// Handle ReadDataByIdentifier of the given data identifier.
+    pub fn read_data<F>(mut self, did: u16, handler: F) -> Server<T>
+        where F: FnMut(&State) -> Response + Send + 'static
+    {
+        self.read_handlers.insert(did, Box::new(handler));
+        self
+    }
+
+    /// Handle WriteDataByIdentifier of the given data identifier.
+    pub fn write_data<F>(mut self, did: u16, handler: F) -> Server<T>
+        where F: FnMut(&mut State, &[u8]) -> ::std::result::Result<(), NegativeResponseCode> + Send + 'static
+    {
+        self.write_handlers.insert(did, Box::new(handler));
+        self
+    }
+
+    /// Serve a constant, read only data identifier.
+    pub fn data_identifier(self, did: u16, value: Vec<u8>) -> Server<T> {
+        self.read_data(did, move |_| Ok(value.clone()))
+    }
+
+    /// Handle RoutineControl of the given routine, the handler returns the routine status record.
+    pub fn routine<F>(mut self, routine: u16, handler: F) -> Server<T>
+        where F: FnMut(&mut State, RoutineControlType, &[u8]) -> Response + Send + 'static
+    {
+        self.routines.insert(routine, Box::new(handler));
+        self
+    }
+
+    /// Enable SecurityAccess of the given level.
+    ///
+    /// # Arguments
+    ///
+    /// * `level`: Odd `requestSeed` sub-function.
+    /// * `seed`: Generates a new seed on every request.
+    /// * `verify`: Checks the key sent by the client for the given seed.
+    ///
+    /// returns: [Server<T>]
+    pub fn security_level<S, V>(mut self, level: u8, seed: S, verify: V) -> Server<T>
+        where S: FnMut() -> Vec<u8> + Send + 'static,
+              V: FnMut(&[u8], &[u8]) -> bool + Send + 'static
+    {
+        self.security_levels.insert(level, SecurityLevel { seed: Box::new(seed), verify: Box::new(verify) });
+        self
+    }
+
+    /// Called on ECUReset with the reset type, before the positive response is sent.
+    pub fn on_reset<F>(mut self, handler: F) -> Server<T>
+        where F: FnMut(u8) -> ::std::result::Result<(), NegativeResponseCode> + Send + 'static
+    {
+        self.reset_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Get the current session and security state.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Get a reference to the underlying transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Consume the server, returning the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Process a single request without sending the response, i.e. for use with a custom transport.
+    ///
+    /// returns: The complete response, or `None` if the positive response is suppressed.
+    pub fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        self.check_s3();
+        self.last_request = Instant::now();
+
+        let service = *request.first()?;
+        let mut params = request[1..].to_vec();
+
+        let suppress = has_sub_function(service) && params.first().is_some_and(|b| b & SUPPRESS_POSITIVE_RESPONSE != 0);
+        if suppress {
+            params[0] &= !SUPPRESS_POSITIVE_RESPONSE;
+        }
+
+        match self.dispatch(service, &params) {
+            Ok(_) if suppress => None,
+            Ok(mut response) => {
+                response.insert(0, service | POSITIVE_RESPONSE);
+                Some(response)
+            }
+            Err(code) => Some(vec![sid::NEGATIVE_RESPONSE, service, code.into()])
+        }
+    }
+
+    /// Wait at most `timeout` for a request and answer it.
+    ///
+    /// returns: `false` if no request has been received.
+    pub fn serve_once(&mut self, timeout: Duration) -> Result<bool> {
+        let request = match self.transport.recv(timeout) {
+            Ok(request) => request,
+            Err(isotp::Error::Timeout) => {
+                self.check_s3();
+                return Ok(false);
+            }
+            Err(e) => return Err(e.into())
+        };
+
+        if let Some(response) = self.handle(&request) {
+            self.transport.send(&response)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Answer requests until `stop` is set, ignoring malformed or incomplete ISO-TP transfers.
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<()> {
+        while !stop.load(Ordering::SeqCst) {
+            match self.serve_once(Duration::from_millis(100)) {
+                Ok(_) => {}
+                Err(Error::Transport(isotp::Error::Device(e))) => return Err(Error::Transport(isotp::Error::Device(e))),
+                Err(Error::Transport(_)) => {}
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_s3(&mut self) {
+        if self.state.session != DEFAULT_SESSION && self.last_request.elapsed() > self.s3 {
+            self.state.reset();
+        }
+    }
+
+    fn dispatch(&mut self, service: u8, params: &[u8]) -> Response {
+        if let Some(access) = self.access.get(&service) {
+            if !access.sessions.contains(&self.state.session) {
+                return Err(NegativeResponseCode::ServiceNotSupportedInActiveSession);
+            }
+            if access.security_level.is_some() && access.security_level != self.state.unlocked {
+                return Err(NegativeResponseCode::SecurityAccessDenied);
+            }
+        }
+
+        if let Some(handler) = self.services.get_mut(&service) {
+            return handler(&mut self.state, params);
+        }
+
+        match service {
+            sid::DIAGNOSTIC_SESSION_CONTROL => self.diagnostic_session_control(params),
+            sid::ECU_RESET => self.ecu_reset(params),
+            sid::SECURITY_ACCESS => self.security_access(params),
+            sid::TESTER_PRESENT => match params {
+                [0x00] => Ok(vec![0x00]),
+                [_] => Err(NegativeResponseCode::SubFunctionNotSupported),
+                _ => Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat)
+            },
+            sid::READ_DATA_BY_IDENTIFIER => self.read_data_by_identifier(params),
+            sid::WRITE_DATA_BY_IDENTIFIER => self.write_data_by_identifier(params),
+            sid::ROUTINE_CONTROL => self.routine_control(params),
+            _ => Err(NegativeResponseCode::ServiceNotSupported)
+        }
+    }
+
+    fn diagnostic_session_control(&mut self, params: &[u8]) -> Response {
+        let session = match params {
+            [session] => *session,
+            _ => return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat)
+        };
+
+        if !self.sessions.contains(&session) {
+            return Err(NegativeResponseCode::SubFunctionNotSupported);
+        }
+
+        if session != self.state.session {
+            self.state.reset();
+            self.state.session = session;
+        }
+
+        let p2 = (self.timing.p2.as_millis().min(0xFFFF) as u16).to_be_bytes();
+        let p2_star = ((self.timing.p2_star.as_millis() / 10).min(0xFFFF) as u16).to_be_bytes();
+        Ok(vec![session, p2[0], p2[1], p2_star[0], p2_star[1]])
+    }
+
+    fn ecu_reset(&mut self, params: &[u8]) -> Response {
+        let reset_type = match params {
+            [reset_type] => *reset_type,
+            _ => return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat)
+        };
+
+        if !(0x01..=0x03).contains(&reset_type) {
+            return Err(NegativeResponseCode::SubFunctionNotSupported);
+        }
+
+        if let Some(handler) = self.reset_handler.as_mut() {
+            handler(reset_type)?;
+        }
+
+        self.state.reset();
+        Ok(vec![reset_type])
+    }
+
+    fn security_access(&mut self, params: &[u8]) -> Response {
+        let sub_function = match params.first() {
+            Some(&sub_function) => sub_function,
+            None => return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat)
+        };
+
+        let level = if sub_function & 1 == 1 { sub_function } else { sub_function.wrapping_sub(1) };
+        let security = match self.security_levels.get_mut(&level) {
+            Some(security) => security,
+            None => return Err(NegativeResponseCode::SubFunctionNotSupported)
+        };
+
+        if sub_function == level {
+            if self.state.failed_attempts >= self.max_attempts {
+                return Err(NegativeResponseCode::RequiredTimeDelayNotExpired);
+            }
+
+            let mut seed = (security.seed)();
+            if self.state.unlocked == Some(level) {
+                seed.iter_mut().for_each(|b| *b = 0);
+            } else {
+                self.state.seed = Some((level, seed.clone()));
+            }
+
+            seed.insert(0, sub_function);
+            return Ok(seed);
+        }
+
+        match self.state.seed.take() {
+            Some((seed_level, ref seed)) if seed_level == level => {
+                if (security.verify)(seed, &params[1..]) {
+                    self.state.unlocked = Some(level);
+                    self.state.failed_attempts = 0;
+                    Ok(vec![sub_function])
+                } else {
+                    self.state.failed_attempts += 1;
+                    if self.state.failed_attempts >= self.max_attempts {
+                        Err(NegativeResponseCode::ExceededNumberOfAttempts)
+                    } else {
+                        Err(NegativeResponseCode::InvalidKey)
+                    }
+                }
+            }
+            _ => Err(NegativeResponseCode::RequestSequenceError)
+        }
+    }
+
+    fn read_data_by_identifier(&mut self, params: &[u8]) -> Response {
+        if params.is_empty() || params.len() & 1 != 0 {
+            return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+        }
+
+        let mut response = Vec::new();
+        for did in params.chunks(2) {
+            let handler = match self.read_handlers.get_mut(&u16::from_be_bytes([did[0], did[1]])) {
+                Some(handler) => handler,
+                None => return Err(NegativeResponseCode::RequestOutOfRange)
+            };
+
+            response.extend_from_slice(did);
+            response.extend(handler(&self.state)?);
+        }
+
+        Ok(response)
+    }
+
+    fn write_data_by_identifier(&mut self, params: &[u8]) -> Response {
+        if params.len() < 3 {
+            return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+        }
+
+        match self.write_handlers.get_mut(&u16::from_be_bytes([params[0], params[1]])) {
+            Some(handler) => handler(&mut self.state, &params[2..])?,
+            None => return Err(NegativeResponseCode::RequestOutOfRange)
+        }
+
+        Ok(params[..2].to_vec())
+    }
+
+    fn routine_control(&mut self, params: &[u8]) -> Response {
+        if params.len() < 3 {
+            return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+        }
+
+        let control = match params[0] {
+            0x01 => RoutineControlType::Start,
+            0x02 => RoutineControlType::Stop,
+            0x03 => RoutineControlType::RequestResults,
+            _ => return Err(NegativeResponseCode::SubFunctionNotSupported)
+        };
+
+        let handler = match self.routines.get_mut(&u16::from_be_bytes([params[1], params[2]])) {
+            Some(handler) => handler,
+            None => return Err(NegativeResponseCode::RequestOutOfRange)
+        };
+
+        let mut response = params[..3].to_vec();
+        response.extend(handler(&mut self.state, control, &params[3..])?);
+        Ok(response)
+    }
+}
+
+/// Services whose first parameter is a sub-function supporting [SUPPRESS_POSITIVE_RESPONSE].
+fn has_sub_function(service: u8) -> bool {
+    matches!(service, sid::DIAGNOSTIC_SESSION_CONTROL | sid::ECU_RESET | sid::SECURITY_ACCESS |
+        sid::TESTER_PRESENT | sid::ROUTINE_CONTROL)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use backend::VirtualBus;
+    use dmgr::OpenChannel;
+    use isotp::IsoTpSocket;
+    use uds::Client;
+    use super::*;
+
+    type Socket = IsoTpSocket<OpenChannel>;
+
+    /// Server running in a background thread, stopped when dropped.
+    struct Running {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<Result<()>>>
+    }
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::SeqCst);
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap().unwrap();
+            }
+        }
+    }
+
+    /// Run a server configured by `configure` on a virtual bus and connect a client to it.
+    fn serve<F>(configure: F) -> (Client<Socket>, Running)
+        where F: FnOnce(Server<Socket>) -> Server<Socket> + Send + 'static
+    {
+        let bus = VirtualBus::new(2);
+        let mut devices = bus.enum_devices().unwrap();
+        let tester = devices.next().unwrap().open_ex().unwrap();
+        let ecu = devices.next().unwrap().open_ex().unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            let socket = IsoTpSocket::builder().tx_sid(0x7E8).rx_sid(0x7E0).build(ecu);
+            configure(Server::new(socket)).run(&stopped)
+        });
+
+        let client = Client::new(IsoTpSocket::builder().tx_sid(0x7E0).rx_sid(0x7E8).build(tester));
+        (client, Running { stop, thread: Some(thread) })
+    }
+
+    fn xor_key(seed: &[u8]) -> Vec<u8> {
+        seed.iter().map(|b| b ^ 0xFF).collect()
+    }
+
+    fn secured(server: Server<Socket>) -> Server<Socket> {
+        server
+            .security_level(0x01, || vec![0x12, 0x34], |seed, key| xor_key(seed) == key)
+            .routine(0xFF00, |_, _, _| Ok(vec![0x00]))
+            .require(sid::ROUTINE_CONTROL, &[0x03], Some(0x01))
+    }
+
+    fn nrc<T: ::std::fmt::Debug>(result: Result<T>) -> NegativeResponseCode {
+        match result {
+            Err(Error::NegativeResponse { code, .. }) => code,
+            other => panic!("expected negative response, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn read_and_write_data() {
+        let (mut client, _server) = serve(|server| {
+            let mut value = [0u8; 4];
+            server
+                .data_identifier(0xF190, b"VIN".to_vec())
+                .write_data(0xF199, move |_, data| if data.len() == value.len() {
+                    value.copy_from_slice(data);
+                    Ok(())
+                } else {
+                    Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat)
+                })
+        });
+
+        assert_eq!(client.read_data_by_identifier(0xF190).unwrap(), b"VIN".to_vec());
+        client.write_data_by_identifier(0xF199, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(nrc(client.write_data_by_identifier(0xF199, &[1])),
+                   NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+    }
+
+    #[test]
+    fn negative_responses() {
+        let (mut client, _server) = serve(|server| server.data_identifier(0xF190, vec![0x01]));
+
+        assert_eq!(nrc(client.request(&[0x85, 0x01])), NegativeResponseCode::ServiceNotSupported);
+        assert_eq!(nrc(client.read_data_by_identifier(0xF191)), NegativeResponseCode::RequestOutOfRange);
+        assert_eq!(nrc(client.request(&[sid::READ_DATA_BY_IDENTIFIER, 0xF1])),
+                   NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+        assert_eq!(nrc(client.diagnostic_session_control(0x40)), NegativeResponseCode::SubFunctionNotSupported);
+        assert_eq!(nrc(client.request(&[sid::TESTER_PRESENT, 0x05])), NegativeResponseCode::SubFunctionNotSupported);
+        assert_eq!(nrc(client.ecu_reset(0x05)), NegativeResponseCode::SubFunctionNotSupported);
+        assert_eq!(nrc(client.routine_control(RoutineControlType::Start, 0x0203, &[])),
+                   NegativeResponseCode::RequestOutOfRange);
+        assert_eq!(nrc(client.security_access(0x03, xor_key)), NegativeResponseCode::SubFunctionNotSupported);
+    }
+
+    #[test]
+    fn session_gating() {
+        let (mut client, _server) = serve(|server| {
+            server
+                .write_data(0xF199, |_, _| Ok(()))
+                .require(sid::WRITE_DATA_BY_IDENTIFIER, &[0x03], None)
+        });
+
+        assert_eq!(nrc(client.write_data_by_identifier(0xF199, &[0x01])),
+                   NegativeResponseCode::ServiceNotSupportedInActiveSession);
+
+        let timing = client.diagnostic_session_control(0x03).unwrap();
+        assert_eq!(timing, SessionTiming { p2: Duration::from_millis(50), p2_star: Duration::from_millis(5000) });
+        client.write_data_by_identifier(0xF199, &[0x01]).unwrap();
+
+        client.diagnostic_session_control(DEFAULT_SESSION).unwrap();
+        assert_eq!(nrc(client.write_data_by_identifier(0xF199, &[0x01])),
+                   NegativeResponseCode::ServiceNotSupportedInActiveSession);
+    }
+
+    #[test]
+    fn security_gating() {
+        let (mut client, _server) = serve(secured);
+
+        client.diagnostic_session_control(0x03).unwrap();
+        assert_eq!(nrc(client.routine_control(RoutineControlType::Start, 0xFF00, &[])),
+                   NegativeResponseCode::SecurityAccessDenied);
+
+        client.security_access(0x01, xor_key).unwrap();
+        assert_eq!(client.routine_control(RoutineControlType::Start, 0xFF00, &[]).unwrap(), vec![0x00]);
+
+        // Already unlocked levels report a zero seed.
+        assert_eq!(client.request(&[sid::SECURITY_ACCESS, 0x01]).unwrap(), vec![0x67, 0x01, 0x00, 0x00]);
+
+        // Changing the session locks security access again.
+        client.diagnostic_session_control(0x02).unwrap();
+        client.diagnostic_session_control(0x03).unwrap();
+        assert_eq!(nrc(client.routine_control(RoutineControlType::Start, 0xFF00, &[])),
+                   NegativeResponseCode::SecurityAccessDenied);
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let (mut client, _server) = serve(|server| secured(server).max_attempts(2));
+
+        assert_eq!(nrc(client.request(&[sid::SECURITY_ACCESS, 0x02, 0xED, 0xCB])),
+                   NegativeResponseCode::RequestSequenceError);
+
+        assert_eq!(nrc(client.security_access(0x01, |_| vec![0x00, 0x00])), NegativeResponseCode::InvalidKey);
+        assert_eq!(nrc(client.security_access(0x01, |_| vec![0x00, 0x00])),
+                   NegativeResponseCode::ExceededNumberOfAttempts);
+        assert_eq!(nrc(client.security_access(0x01, xor_key)), NegativeResponseCode::RequiredTimeDelayNotExpired);
+    }
+
+    #[test]
+    fn s3_timeout() {
+        let (mut client, _server) = serve(|server| {
+            server
+                .s3(Duration::from_millis(200))
+                .write_data(0xF199, |_, _| Ok(()))
+                .require(sid::WRITE_DATA_BY_IDENTIFIER, &[0x03], None)
+        });
+
+        client.diagnostic_session_control(0x03).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.tester_present(true).unwrap();
+        thread::sleep(Duration::from_millis(150));
+        client.write_data_by_identifier(0xF199, &[0x01]).unwrap();
+
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(nrc(client.write_data_by_identifier(0xF199, &[0x01])),
+                   NegativeResponseCode::ServiceNotSupportedInActiveSession);
+    }
+
+    #[test]
+    fn suppressed_positive_response() {
+        let (mut client, _server) = serve(|server| server.data_identifier(0xF190, vec![0x01]));
+
+        client.tester_present(true).unwrap();
+        assert_eq!(client.read_data_by_identifier(0xF190).unwrap(), vec![0x01]);
+    }
+
+    #[test]
+    fn ecu_reset() {
+        let (mut client, _server) = serve(|server| {
+            secured(server).on_reset(|reset_type| if reset_type == 0x02 {
+                Err(NegativeResponseCode::ConditionsNotCorrect)
+            } else {
+                Ok(())
+            })
+        });
+
+        client.diagnostic_session_control(0x03).unwrap();
+        client.security_access(0x01, xor_key).unwrap();
+
+        assert_eq!(nrc(client.ecu_reset(0x02)), NegativeResponseCode::ConditionsNotCorrect);
+        client.routine_control(RoutineControlType::Start, 0xFF00, &[]).unwrap();
+
+        client.ecu_reset(0x01).unwrap();
+        assert_eq!(nrc(client.routine_control(RoutineControlType::Start, 0xFF00, &[])),
+                   NegativeResponseCode::ServiceNotSupportedInActiveSession);
+    }
+
+    #[test]
+    fn custom_service() {
+        let (mut client, _server) = serve(|server| {
+            server
+                .service(sid::REQUEST_TRANSFER_EXIT, |state, params| {
+                    if state.session() == 0x02 { Ok(params.to_vec()) } else { Err(NegativeResponseCode::RequestSequenceError) }
+                })
+                .service(sid::TESTER_PRESENT, |_, _| Err(NegativeResponseCode::BusyRepeatRequest))
+        });
+
+        assert_eq!(nrc(client.request_transfer_exit(&[0xAB])), NegativeResponseCode::RequestSequenceError);
+        client.diagnostic_session_control(0x02).unwrap();
+        assert_eq!(client.request_transfer_exit(&[0xAB]).unwrap(), vec![0xAB]);
+        assert_eq!(nrc(client.tester_present(false)), NegativeResponseCode::BusyRepeatRequest);
+    }
+}